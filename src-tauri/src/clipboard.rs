@@ -1,17 +1,164 @@
-use crate::state::AppState;
-use arboard::Clipboard;
+use crate::core::{
+    count_words, prefer_html_capture, prepare_captured_text, sha1_hex, sha1_hex_bytes,
+    truncate_for_log,
+};
+use crate::images::{rgba_to_data_uri, CLIPBOARD_IMAGE_MAX_WIDTH};
+use crate::state::{AppState, Settings};
+use crate::url_processor::html_fragment_to_markdown;
+use arboard::{Clipboard, ImageData};
 use log::{error, info, warn};
-use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use tauri::Emitter;
 
+/// Capturable clipboard content, found by [`read_content`].
+enum ClipboardContent {
+    /// Markdown converted from the HTML variant, preferred over the plain text by
+    /// [`prefer_html_capture`].
+    Html(String),
+    /// Plain text, prepared by [`prepare_captured_text`].
+    Text(String),
+    /// An image, when there is no text.
+    Image(ImageData<'static>),
+}
+
+impl ClipboardContent {
+    /// Identifies the content, so it is captured only once however it was copied:
+    /// the hash of the converted Markdown, or of the raw pixels for images, which
+    /// are only encoded once they are captured.
+    fn hash(&self) -> String {
+        match self {
+            ClipboardContent::Html(markdown) | ClipboardContent::Text(markdown) => {
+                sha1_hex(markdown)
+            }
+            ClipboardContent::Image(image) => sha1_hex_bytes(&image.bytes),
+        }
+    }
+
+    /// Describes the content for the log: its kind and size, and a preview of the
+    /// text only if `log_preview_chars` opts into one.
+    fn describe(&self, log_preview_chars: usize) -> String {
+        let (kind, text) = match self {
+            ClipboardContent::Html(markdown) => ("HTML", markdown),
+            ClipboardContent::Text(text) => ("text", text),
+            ClipboardContent::Image(image) => {
+                return format!("image {}x{}", image.width, image.height)
+            }
+        };
+        if log_preview_chars == 0 {
+            format!("{} ({} chars)", kind, text.chars().count())
+        } else {
+            format!("{} \"{}\"", kind, truncate_for_log(text, log_preview_chars))
+        }
+    }
+
+    /// Converts the content to Markdown; images become an embedded PNG.
+    fn into_markdown(self) -> Result<String, String> {
+        match self {
+            ClipboardContent::Html(markdown) | ClipboardContent::Text(markdown) => Ok(markdown),
+            ClipboardContent::Image(image) => {
+                let uri = rgba_to_data_uri(
+                    image.width as u32,
+                    image.height as u32,
+                    image.bytes.into_owned(),
+                    CLIPBOARD_IMAGE_MAX_WIDTH,
+                )?;
+                Ok(format!("![]({})", uri))
+            }
+        }
+    }
+}
+
+/// Reads the clipboard: its plain text, its HTML variant where the platform offers
+/// one, and an image if there is no text.
+///
+/// # Returns
+/// The content to capture, `None` if there is nothing to capture, or the error of
+/// the text read.
+fn read_content(
+    clipboard: &mut Clipboard,
+    escape_plain_text: bool,
+) -> Result<Option<ClipboardContent>, arboard::Error> {
+    let text = match clipboard.get_text() {
+        Ok(text) => text,
+        Err(arboard::Error::ContentNotAvailable) => String::new(),
+        Err(e) => return Err(e),
+    };
+    let html_markdown = clipboard
+        .get()
+        .html()
+        .map(|html| html_fragment_to_markdown(&html))
+        .unwrap_or_default();
+
+    if prefer_html_capture(&text, &html_markdown) {
+        return Ok(Some(ClipboardContent::Html(html_markdown)));
+    }
+    if !text.trim().is_empty() {
+        return Ok(Some(ClipboardContent::Text(prepare_captured_text(
+            &text,
+            escape_plain_text,
+        ))));
+    }
+    match clipboard.get_image() {
+        Ok(image) => Ok(Some(ClipboardContent::Image(image))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Sends captured Markdown to the e-reader or, if only adding to the editor is
+/// enabled, to the editor.
+///
+/// # Returns
+/// `true` if the content was delivered.
+fn deliver(
+    state: &AppState,
+    handle: &tauri::AppHandle,
+    settings: &Settings,
+    description: &str,
+    markdown: String,
+) -> bool {
+    if settings.send_on_copy {
+        info!("New {} detected. Sending to e-reader.", description);
+        let words = count_words(&markdown);
+        match state.shared_text.write() {
+            Ok(mut shared_text) => {
+                state.replace_shared_text(&mut shared_text, markdown);
+                state.stats.record_document(words);
+                true
+            }
+            Err(e) => {
+                error!("Failed to lock shared_text for sending: {}", e);
+                false
+            }
+        }
+    } else if settings.add_to_editor_on_copy {
+        info!(
+            "New {} detected. Emitting event to add to editor.",
+            description
+        );
+        if let Err(e) = handle.emit("clipboard-add-to-editor", &markdown) {
+            error!("Failed to emit clipboard event: {}", e);
+        }
+        true
+    } else {
+        false
+    }
+}
+
 /// Spawns a dedicated thread to monitor the system clipboard for changes.
 ///
 /// Depending on the `AppState` flags, this function can:
 /// 1. Directly replace the shared text for the e-reader.
 /// 2. Emit an event to the frontend to add the text to the editor.
+///
+/// Plain text is captured by default; rich content copied from a browser is
+/// captured from its HTML variant when that keeps formatting the text loses, and a
+/// copied image is embedded. See [`read_content`].
+///
+/// If `clear_clipboard_after_capture` is set, the clipboard is emptied after each
+/// capture. The hash of the captured content stays in `last_hash`, so it is not
+/// captured again if a clipboard manager restores it.
 pub fn spawn_monitor(state: Arc<AppState>, handle: tauri::AppHandle) {
     thread::spawn(move || {
         info!("Clipboard monitoring thread started.");
@@ -23,47 +170,70 @@ pub fn spawn_monitor(state: Arc<AppState>, handle: tauri::AppHandle) {
             }
         };
 
-        let mut last_text = clipboard.get_text().unwrap_or_default();
+        // Content already on the clipboard at startup is not captured.
+        let mut last_hash =
+            read_content(&mut clipboard, state.settings().escape_plain_text_on_copy)
+                .ok()
+                .flatten()
+                .map(|content| content.hash())
+                .unwrap_or_default();
+        // Set while the clipboard is empty because we cleared it, so that the empty
+        // clipboard does not reset `last_hash`.
+        let mut cleared = false;
 
         loop {
-            let send_enabled = state.send_on_copy.load(Ordering::Relaxed);
-            let add_to_editor_enabled = state.add_to_editor_on_copy.load(Ordering::Relaxed);
-
-            if !send_enabled && !add_to_editor_enabled {
-                thread::sleep(Duration::from_millis(500));
+            // Read on every iteration, so a changed interval applies right away.
+            let settings = state.settings();
+            let interval = Duration::from_millis(settings.clipboard_interval_ms);
+            if !settings.send_on_copy && !settings.add_to_editor_on_copy {
+                thread::sleep(interval);
                 continue;
             }
 
-            match clipboard.get_text() {
-                Ok(current_text) => {
-                    if !current_text.trim().is_empty() && current_text != last_text {
-                        if send_enabled {
-                            info!("New text detected. Sending to e-reader.");
-                            match state.shared_text.write() {
-                                Ok(mut shared_text) => {
-                                    *shared_text = current_text.clone();
-                                    last_text = current_text;
-                                }
-                                Err(e) => {
-                                    error!("Failed to lock shared_text for sending: {}", e);
+            match read_content(&mut clipboard, settings.escape_plain_text_on_copy) {
+                Ok(Some(content)) => {
+                    let hash = content.hash();
+                    if hash != last_hash {
+                        cleared = false;
+                        let description = content.describe(settings.log_preview_chars);
+                        let markdown = content.into_markdown().and_then(|markdown| {
+                            state.ensure_text_fits(&markdown)?;
+                            Ok(markdown)
+                        });
+                        match markdown {
+                            Ok(markdown) => {
+                                if deliver(&state, &handle, &settings, &description, markdown) {
+                                    last_hash = hash;
+                                    if settings.clear_clipboard_after_capture {
+                                        match clipboard.clear() {
+                                            Ok(()) => {
+                                                info!("Cleared the clipboard after capture.");
+                                                cleared = true;
+                                            }
+                                            Err(e) => {
+                                                warn!("Could not clear the clipboard: {}", e)
+                                            }
+                                        }
+                                    }
                                 }
                             }
-                        } else if add_to_editor_enabled {
-                            info!("New text detected. Emitting event to add to editor.");
-                            if let Err(e) = handle.emit("clipboard-add-to-editor", &current_text) {
-                                error!("Failed to emit clipboard event: {}", e);
+                            Err(e) => {
+                                warn!("Could not capture clipboard {}: {}", description, e);
+                                last_hash = hash;
                             }
-                            last_text = current_text;
                         }
                     }
                 }
+                Ok(None) if cleared => {}
+                // Emptied by someone else: the same content copied again is new.
+                Ok(None) => last_hash.clear(),
                 Err(e) => {
                     warn!("Could not read text from clipboard: {}", e);
-                    last_text.clear();
+                    last_hash.clear();
                 }
             }
 
-            thread::sleep(Duration::from_millis(500));
+            thread::sleep(interval);
         }
     });
 }
@@ -1,9 +1,28 @@
-use crate::network::get_local_ip_address;
-use crate::server::SERVER_PORT;
-use crate::state::AppState;
+use crate::core::{
+    self, estimate_pages, markdown_to_plain_text, ContentMode, ContentStats, LineDiff,
+    PageEstimate, Replacement, SelectionFormat,
+};
+use crate::epub;
+use crate::export::{export_file_name, render_standalone_html};
+use crate::images::fetch_image_data_uri;
+use crate::import::{import_text_file, IMPORT_EXTENSIONS};
+use crate::library::DocumentInfo;
+use crate::links::{self, LinkReport, LINK_CHECK_TIMEOUT};
+use crate::network::list_local_ip_addresses;
+use crate::peers::{self, PeerInfo, DISCOVERY_TIMEOUT};
+use crate::server;
+use crate::state::{
+    AppState, ClampedSetting, ReadingProgress, SettingsReport, StatsReport, WatchedPage,
+};
+use crate::url_processor::{
+    self, fetch_cover_image_url, sanitize_markdown, Allowlist, SanitizePreset,
+};
+use crate::watch::{self, MIN_WATCH_INTERVAL};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tauri::State;
+use std::time::Duration;
+use tauri::{AppHandle, State};
+use tauri_plugin_dialog::DialogExt;
 
 /// Retrieves the current shared text from the application state.
 #[tauri::command]
@@ -15,14 +34,39 @@ pub fn get_text(state: State<Arc<AppState>>) -> Result<String, String> {
         .map_err(|e| format!("Failed to acquire read lock: {}", e))
 }
 
+/// Returns the source hash of the shared text (as in `GET /api/source`), for use
+/// as `base_hash` when saving.
+#[tauri::command]
+pub fn get_text_hash(state: State<Arc<AppState>>) -> Result<String, String> {
+    state
+        .shared_text
+        .read()
+        .map(|text| core::sha1_hex(&text))
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))
+}
+
 /// Overwrites the shared text with new content. This is now the primary method for updating
 /// the state from the frontend to ensure consistency.
+///
+/// # Arguments
+/// * `new_text` - The new Markdown text.
+/// * `base_hash` - The source hash of the text the edit was based on. If set and
+///   the shared text has changed since, nothing is written and an error is returned.
 #[tauri::command]
-pub fn set_text(new_text: String, state: State<Arc<AppState>>) -> Result<(), String> {
+pub fn set_text(
+    new_text: String,
+    base_hash: Option<String>,
+    state: State<Arc<AppState>>,
+) -> Result<(), String> {
     log::info!("Setting shared text via command.");
+    state.ensure_text_fits(&new_text)?;
     match state.shared_text.write() {
         Ok(mut text) => {
-            *text = new_text;
+            if base_hash.is_some_and(|base_hash| base_hash != core::sha1_hex(&text)) {
+                log::warn!("Rejected set_text: the shared text changed since it was loaded.");
+                return Err("Текст на читалке изменился после загрузки в редактор.".to_string());
+            }
+            state.replace_shared_text(&mut text, new_text);
             log::info!("Successfully set shared text from command.");
             Ok(())
         }
@@ -34,22 +78,700 @@ pub fn set_text(new_text: String, state: State<Arc<AppState>>) -> Result<(), Str
     }
 }
 
-/// Gets the local network address for the web reader.
+/// Replaces the shared text with `markdown`, making `title` its leading H1 if given
+/// (an existing leading H1 is replaced). For programmatic producers that have the
+/// title separately from the body. The previous text can be brought back with `undo`.
+///
+/// # Returns
+/// The source hash of the new shared text, as in `GET /api/source`, or an error
+/// if the text is longer than the `max_text_chars` setting allows.
+#[tauri::command]
+pub fn set_structured(
+    title: Option<String>,
+    markdown: String,
+    state: State<Arc<AppState>>,
+) -> Result<String, String> {
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for set_structured: {}", e))?;
+    let hash = state.set_structured_text(&mut text, title.as_deref(), markdown)?;
+    log::info!("Successfully set shared text from structured content.");
+    Ok(hash)
+}
+
+/// Strips unsafe or cluttering HTML (scripts, inline styles, event handlers) from the
+/// shared text, using the same sanitizer as fetched articles. The previous text can
+/// be brought back with `undo`.
+///
+/// # Returns
+/// The cleaned Markdown, which also replaces the shared text.
+#[tauri::command]
+pub fn sanitize_current(state: State<Arc<AppState>>) -> Result<String, String> {
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for sanitize_current: {}", e))?;
+    let cleaned = sanitize_markdown(&text);
+    state.ensure_text_fits(&cleaned)?;
+    log::info!(
+        "Sanitized shared text: {} -> {} bytes.",
+        text.len(),
+        cleaned.len()
+    );
+    state.replace_shared_text(&mut text, cleaned.clone());
+    Ok(cleaned)
+}
+
+/// Empties the shared text, clearing both the editor and the reader. The text can
+/// be brought back with `undo`.
+#[tauri::command]
+pub fn clear_text(state: State<Arc<AppState>>) -> Result<(), String> {
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for clear_text: {}", e))?;
+    state.replace_shared_text(&mut text, String::new());
+    log::info!("Cleared shared text.");
+    Ok(())
+}
+
+/// Looks up the Open Graph (or Twitter card) cover image of a page, for use as a
+/// thumbnail.
+///
+/// # Arguments
+/// * `url` - The page to inspect.
+/// * `inline` - When `true`, the image is downloaded and returned as a `data:` URI
+///   (scaled down to `max_width` if set) instead of as a remote URL.
+///
+/// # Returns
+/// `None` if the page declares no cover image.
+#[tauri::command]
+pub async fn fetch_cover_image(
+    url: String,
+    inline: bool,
+    max_width: Option<u32>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Option<String>, String> {
+    state.ensure_network_allowed()?;
+    log::info!("Looking up cover image for: {}", url);
+
+    let cover = fetch_cover_image_url(&url).await.map_err(|e| {
+        log::error!("Failed to load page {} for its cover image: {}", url, e);
+        e.to_string()
+    })?;
+    let Some(cover) = cover else {
+        log::info!("No cover image declared by {}", url);
+        return Ok(None);
+    };
+
+    if !inline {
+        return Ok(Some(cover.to_string()));
+    }
+    fetch_image_data_uri(cover.as_str(), max_width)
+        .await
+        .map(Some)
+        .map_err(|e| {
+            log::error!("Failed to fetch cover image {}: {}", cover, e);
+            e
+        })
+}
+
+/// Sends the current shared text to another running instance, replacing its content.
+///
+/// # Arguments
+/// * `host` - Host name or IP address of the other instance.
+/// * `port` - Port of its web server.
+///
+/// # Returns
+/// The source hash confirmed by the other instance.
+#[tauri::command]
+pub async fn push_to_peer(
+    host: String,
+    port: u16,
+    state: State<'_, Arc<AppState>>,
+) -> Result<String, String> {
+    state.ensure_network_allowed()?;
+    let text = state
+        .shared_text
+        .read()
+        .map(|text| text.clone())
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+
+    log::info!("Pushing shared text to peer {}:{}", host, port);
+    peers::push_to_peer(&host, port, &text).await.map_err(|e| {
+        log::error!("Failed to push to peer {}:{}: {}", host, port, e);
+        e
+    })
+}
+
+/// Looks for other instances of the application on the local network via mDNS.
+/// This instance is not included in the results.
+#[tauri::command]
+pub async fn discover_peers(state: State<'_, Arc<AppState>>) -> Result<Vec<PeerInfo>, String> {
+    state.ensure_network_allowed()?;
+    log::info!("Browsing the local network for peers.");
+    let local_port = state.server_port.load(Ordering::SeqCst);
+    let peers = tauri::async_runtime::spawn_blocking(move || {
+        peers::discover_peers(DISCOVERY_TIMEOUT, local_port)
+    })
+    .await
+    .map_err(|e| format!("Поиск устройств прерван: {}", e))??;
+
+    log::info!("Found {} peer(s).", peers.len());
+    Ok(peers)
+}
+
+/// Checks every web link of the shared text and reports which ones are broken.
+#[tauri::command]
+pub async fn check_links(state: State<'_, Arc<AppState>>) -> Result<LinkReport, String> {
+    state.ensure_network_allowed()?;
+    let (html, _) = state
+        .shared_text
+        .read()
+        .map(|text| state.render(&text))
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+
+    let urls = links::extract_links(&html);
+    log::info!("Checking {} link(s) in the shared text.", urls.len());
+    let report = links::check_links(urls, LINK_CHECK_TIMEOUT).await?;
+    log::info!(
+        "Link check finished: {} of {} broken.",
+        report.broken,
+        report.checked
+    );
+    Ok(report)
+}
+
+/// Compares the text currently shown on the reader with `new_text`, so the editor
+/// can preview what saving would change.
+///
+/// # Returns
+/// A line-based diff from the live text to `new_text`.
+#[tauri::command]
+pub fn diff_against_live(
+    new_text: String,
+    state: State<Arc<AppState>>,
+) -> Result<LineDiff, String> {
+    let live = state
+        .shared_text
+        .read()
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+    Ok(core::diff_lines(&live, &new_text))
+}
+
+/// Fetches a web page, converts it to Markdown and sends it to the reader. Does
+/// the same as `POST /api/url`, without going through the web server, so it also
+/// works when the server could not start.
+///
+/// # Arguments
+/// * `url` - The page to fetch.
+/// * `embed_images` - Whether to download the page's images into the text, so
+///   the reader does not need to reach their servers. Off by default.
+///
+/// # Returns
+/// The Markdown now shown on the reader, or an error message for the user.
+#[tauri::command]
+pub async fn fetch_url(
+    url: String,
+    embed_images: Option<bool>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<String, String> {
+    state.ensure_network_allowed()?;
+    state.ensure_fetch_allowed()?;
+    log::info!("Fetching URL from the desktop app: {}", url);
+    let allowlist = Allowlist::for_preset(state.settings().sanitize_preset);
+    let markdown = url_processor::process_url(&url, embed_images.unwrap_or(false), &allowlist)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to process URL {}: {}", url, e);
+            e.to_string()
+        })?;
+    state.ensure_text_fits(&markdown)?;
+
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+    state.stats.record_fetch();
+    state.stats.record_document(core::count_words(&markdown));
+    state.replace_shared_text(&mut text, markdown.clone());
+    log::info!("Successfully updated shared text from URL.");
+    Ok(markdown)
+}
+
+/// Loads a page into the shared text and keeps re-fetching it in the background,
+/// updating the reader whenever the page changes. Replaces any previous watch.
+///
+/// # Arguments
+/// * `url` - The page to watch.
+/// * `interval_secs` - Seconds between fetches; raised to [`MIN_WATCH_INTERVAL`]
+///   if shorter.
+///
+/// # Returns
+/// The interval actually used, in seconds.
+#[tauri::command]
+pub async fn watch_url(
+    url: String,
+    interval_secs: u64,
+    state: State<'_, Arc<AppState>>,
+) -> Result<u64, String> {
+    state.ensure_network_allowed()?;
+    let interval = Duration::from_secs(interval_secs).max(MIN_WATCH_INTERVAL);
+
+    // Stop the previous watch before the first fetch, so it cannot overwrite the result.
+    let generation = state.watch_generation.fetch_add(1, Ordering::SeqCst) + 1;
+    state
+        .watched_page
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock: {}", e))?
+        .take();
+    let hash = watch::fetch_into_shared_text(&state, &url)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to load watched URL {}: {}", url, e);
+            e
+        })?;
+
+    *state
+        .watched_page
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock: {}", e))? = Some(WatchedPage {
+        url: url.clone(),
+        interval_secs: interval.as_secs(),
+    });
+    state.mark_unsaved();
+    log::info!("Watching {} every {} s.", url, interval.as_secs());
+    tauri::async_runtime::spawn(watch::watch_url(
+        state.inner().clone(),
+        url,
+        interval,
+        generation,
+        hash,
+    ));
+    Ok(interval.as_secs())
+}
+
+/// Stops re-fetching the watched page. The shared text is left as it is.
+#[tauri::command]
+pub fn stop_watching(state: State<Arc<AppState>>) -> Result<(), String> {
+    state.watch_generation.fetch_add(1, Ordering::SeqCst);
+    let previous = state
+        .watched_page
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock: {}", e))?
+        .take();
+    if let Some(page) = previous {
+        log::info!("Stopping watch of {}", page.url);
+        state.mark_unsaved();
+    }
+    Ok(())
+}
+
+/// Returns the page being watched, e.g. one whose watch resumed after a restart.
+#[tauri::command]
+pub fn get_watched_page(state: State<Arc<AppState>>) -> Result<Option<WatchedPage>, String> {
+    state
+        .watched_page
+        .read()
+        .map(|page| page.clone())
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))
+}
+
+/// Replaces every occurrence of `pattern` in the shared text, either literally or
+/// as a regular expression with capture group support (`$1`) in `replacement`.
+/// The previous text can be brought back with `undo`.
+#[tauri::command]
+pub fn find_replace(
+    pattern: String,
+    replacement: String,
+    regex: bool,
+    state: State<Arc<AppState>>,
+) -> Result<Replacement, String> {
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for find_replace: {}", e))?;
+    let replaced = core::find_replace(&text, &pattern, &replacement, regex)?;
+    if replaced.count > 0 {
+        state.ensure_text_fits(&replaced.text)?;
+        state.replace_shared_text(&mut text, replaced.text.clone());
+    }
+    log::info!(
+        "Replaced {} occurrence(s) in shared text (regex: {}).",
+        replaced.count,
+        regex
+    );
+
+    Ok(replaced)
+}
+
+/// Formats a selection of editor text as a quote, heading, code or bulleted list.
+/// The editor owns the selection and replaces it with the result.
+#[tauri::command]
+pub fn format_selection(kind: SelectionFormat, text: String) -> String {
+    core::format_selection(kind, &text)
+}
+
+/// Switches the reader between the article and a separate scratch buffer.
+/// Both texts are kept; the one switched away from is restored on the next toggle.
+///
+/// # Returns
+/// `true` if the scratch buffer is now active.
+#[tauri::command]
+pub fn toggle_scratch(state: State<Arc<AppState>>) -> Result<bool, String> {
+    let scratch_active = state.toggle_scratch()?;
+    log::info!(
+        "Switched to the {} buffer.",
+        if scratch_active { "scratch" } else { "article" }
+    );
+    Ok(scratch_active)
+}
+
+/// Reports whether the scratch buffer is active, e.g. because it was when the app
+/// was last closed.
+#[tauri::command]
+pub fn is_scratch_active(state: State<Arc<AppState>>) -> bool {
+    state.scratch_active.load(Ordering::SeqCst)
+}
+
+/// Restores the shared text as it was before its last change by the editor, the
+/// clipboard or a fetched page, and updates the reader.
+///
+/// # Returns
+/// The restored text for the editor, or an error if there is nothing to undo.
+#[tauri::command]
+pub fn undo(state: State<Arc<AppState>>) -> Result<String, String> {
+    let text = state.undo()?;
+    log::info!("Undid the last change of the shared text.");
+    Ok(text)
+}
+
+/// Takes back the last `undo` and updates the reader.
+///
+/// # Returns
+/// The restored text for the editor, or an error if there is nothing to redo.
+#[tauri::command]
+pub fn redo(state: State<Arc<AppState>>) -> Result<String, String> {
+    let text = state.redo()?;
+    log::info!("Redid the last undone change of the shared text.");
+    Ok(text)
+}
+
+/// Lists the saved documents, marking the one shown on the reader.
+#[tauri::command]
+pub fn list_documents(state: State<Arc<AppState>>) -> Result<Vec<DocumentInfo>, String> {
+    state.list_documents()
+}
+
+/// Adds an empty document and shows it on the reader; the previous one is kept.
+///
+/// # Arguments
+/// * `name` - The document name; a numbered default is used if it is blank.
+///
+/// # Returns
+/// The updated document list.
+#[tauri::command]
+pub fn new_document(
+    state: State<Arc<AppState>>,
+    name: Option<String>,
+) -> Result<Vec<DocumentInfo>, String> {
+    let documents = state.new_document(name.as_deref().unwrap_or_default())?;
+    log::info!(
+        "Created a new document, {} in the library.",
+        documents.len()
+    );
+    Ok(documents)
+}
+
+/// Shows the document `id` on the reader and in the editor.
+///
+/// # Returns
+/// The updated document list.
+#[tauri::command]
+pub fn switch_document(state: State<Arc<AppState>>, id: u64) -> Result<Vec<DocumentInfo>, String> {
+    let documents = state.switch_document(id)?;
+    log::info!("Switched to document {}.", id);
+    Ok(documents)
+}
+
+/// Deletes the document `id` with its text. The last document cannot be deleted.
+///
+/// # Returns
+/// The updated document list.
+#[tauri::command]
+pub fn delete_document(state: State<Arc<AppState>>, id: u64) -> Result<Vec<DocumentInfo>, String> {
+    let documents = state.delete_document(id)?;
+    log::info!("Deleted document {}.", id);
+    Ok(documents)
+}
+
+/// Converts an EPUB book to Markdown and loads it as the shared text. The previous
+/// text can be brought back with `undo`.
+///
+/// # Arguments
+/// * `path` - The path of the `.epub` file.
+///
+/// # Returns
+/// The imported Markdown text.
+#[tauri::command]
+pub async fn import_epub(path: String, state: State<'_, Arc<AppState>>) -> Result<String, String> {
+    log::info!("Importing EPUB: {}", path);
+    let markdown = tauri::async_runtime::spawn_blocking(move || {
+        epub::import_epub(std::path::Path::new(&path))
+    })
+    .await
+    .map_err(|e| format!("Импорт прерван: {}", e))?
+    .map_err(|e| {
+        log::error!("Failed to import EPUB: {}", e);
+        e
+    })?;
+    state.ensure_text_fits(&markdown)?;
+
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for import_epub: {}", e))?;
+    state.stats.record_document(core::count_words(&markdown));
+    state.replace_shared_text(&mut text, markdown.clone());
+    log::info!("Imported EPUB: {} bytes of Markdown.", markdown.len());
+    Ok(markdown)
+}
+
+/// Returns the shared text flattened to plain text, without Markdown or HTML markup.
+#[tauri::command]
+pub fn get_plain_text(state: State<Arc<AppState>>) -> Result<String, String> {
+    state
+        .shared_text
+        .read()
+        .map(|text| markdown_to_plain_text(&text))
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))
+}
+
+/// Returns the length, word count, reading time and source hash of the shared
+/// text, as `GET /api/stats` does.
+#[tauri::command]
+pub fn get_content_stats(state: State<Arc<AppState>>) -> Result<ContentStats, String> {
+    state
+        .shared_text
+        .read()
+        .map(|text| core::content_stats(&text))
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))
+}
+
+/// Returns rough page-count estimates of the shared text at each reader font size.
+#[tauri::command]
+pub fn estimate_page_counts(state: State<Arc<AppState>>) -> Result<Vec<PageEstimate>, String> {
+    state
+        .shared_text
+        .read()
+        .map(|text| estimate_pages(&text))
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))
+}
+
+/// Renders the shared text as a standalone HTML document (styled, without scripts)
+/// that can be saved and opened offline.
+#[tauri::command]
+pub fn export_html(state: State<Arc<AppState>>) -> Result<String, String> {
+    let shared_text = state
+        .shared_text
+        .read()
+        .map(|text| text.clone())
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+
+    let settings = state.settings();
+
+    log::info!("Exporting shared text as standalone HTML.");
+    Ok(render_standalone_html(
+        &shared_text,
+        settings.content_mode,
+        settings.reader.auto_title,
+    ))
+}
+
+/// Saves the shared text as a Markdown file at a location chosen by the user in a
+/// save dialog. The suggested name is derived from the document title.
+///
+/// # Returns
+/// The path the file was saved to, or `None` if the user cancelled the dialog.
+#[tauri::command]
+pub async fn export_markdown(
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Option<String>, String> {
+    let markdown = state
+        .shared_text
+        .read()
+        .map(|text| text.clone())
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+    let settings = state.settings();
+    let title = core::content_title(&markdown, settings.content_mode, true);
+
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    app.dialog()
+        .file()
+        .set_title("Экспорт в Markdown")
+        .add_filter("Markdown", &["md"])
+        .set_file_name(export_file_name(&title, "md"))
+        .save_file(move |path| {
+            let _ = sender.send(path);
+        });
+    let Some(path) = receiver
+        .await
+        .map_err(|e| format!("Диалог сохранения закрылся с ошибкой: {}", e))?
+    else {
+        log::info!("Markdown export cancelled.");
+        return Ok(None);
+    };
+    let path = path
+        .into_path()
+        .map_err(|e| format!("Не удалось сохранить файл: {}", e))?;
+
+    std::fs::write(&path, markdown).map_err(|e| {
+        log::error!("Failed to export Markdown to {}: {}", path.display(), e);
+        format!("Не удалось сохранить {}: {}", path.display(), e)
+    })?;
+    log::info!("Exported shared text as Markdown to {}.", path.display());
+    Ok(Some(path.display().to_string()))
+}
+
+/// Loads a local Markdown, plain text or HTML file, chosen by the user in an open
+/// dialog, as the shared text. HTML is converted to Markdown. The previous text can
+/// be brought back with `undo`.
+///
+/// # Returns
+/// The loaded Markdown, or `None` if the user cancelled the dialog.
+#[tauri::command]
+pub async fn import_file(
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Option<String>, String> {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    app.dialog()
+        .file()
+        .set_title("Открыть файл")
+        .add_filter("Текст, Markdown, HTML", &IMPORT_EXTENSIONS)
+        .pick_file(move |path| {
+            let _ = sender.send(path);
+        });
+    let Some(path) = receiver
+        .await
+        .map_err(|e| format!("Диалог открытия закрылся с ошибкой: {}", e))?
+    else {
+        log::info!("File import cancelled.");
+        return Ok(None);
+    };
+    let path = path
+        .into_path()
+        .map_err(|e| format!("Не удалось открыть файл: {}", e))?;
+
+    log::info!("Importing file: {}", path.display());
+    let markdown = tauri::async_runtime::spawn_blocking(move || import_text_file(&path))
+        .await
+        .map_err(|e| format!("Импорт прерван: {}", e))?
+        .map_err(|e| {
+            log::error!("Failed to import file: {}", e);
+            e
+        })?;
+    state.ensure_text_fits(&markdown)?;
+
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for import_file: {}", e))?;
+    state.stats.record_document(core::count_words(&markdown));
+    state.replace_shared_text(&mut text, markdown.clone());
+    log::info!("Imported file: {} bytes of Markdown.", markdown.len());
+    Ok(Some(markdown))
+}
+
+/// Downloads an image and returns it as a base64 `data:` URI, optionally scaled
+/// down to `max_width` pixels, so it can be embedded into the document.
+#[tauri::command]
+pub async fn fetch_image(
+    url: String,
+    max_width: Option<u32>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<String, String> {
+    state.ensure_network_allowed()?;
+    log::info!("Fetching image for embedding: {}", url);
+    fetch_image_data_uri(&url, max_width).await.map_err(|e| {
+        log::error!("Failed to fetch image {}: {}", url, e);
+        e
+    })
+}
+
+/// Gets the addresses the web reader may be opened at, one per local IPv4
+/// address, including the access token if one is required.
+///
+/// # Returns
+/// The candidate URLs, most likely reachable first; empty if no address was found.
+#[tauri::command]
+pub fn get_server_info(state: State<Arc<AppState>>) -> Vec<String> {
+    let token_query = state
+        .access_token
+        .as_ref()
+        .map(|token| format!("?token={}", token))
+        .unwrap_or_default();
+    let port = state.server_port.load(Ordering::SeqCst);
+    list_local_ip_addresses()
+        .into_iter()
+        .map(|ip| format!("http://{}:{}/get{}", ip, port, token_query))
+        .collect()
+}
+
+/// Returns the port the web server listens on, for the editor's requests to it.
+#[tauri::command]
+pub fn get_server_port(state: State<Arc<AppState>>) -> u16 {
+    state.server_port.load(Ordering::SeqCst)
+}
+
+/// Stops the web server and starts it again, e.g. when it could not bind a port
+/// at launch. The port may change; read the addresses again afterwards.
+///
+/// # Returns
+/// Whether the server is listening again.
 #[tauri::command]
-pub fn get_server_info() -> Result<String, String> {
-    match get_local_ip_address() {
-        Some(ip) => Ok(format!(
-            "Откройте на читалке: http://{}:{}/get",
-            ip, SERVER_PORT
-        )),
-        None => Ok("Не удалось определить IP-адрес. Проверьте подключение к сети.".to_string()),
+pub async fn restart_server(state: State<'_, Arc<AppState>>) -> Result<bool, String> {
+    let restarted = server::restart_server(state.inner().clone()).await;
+    if !restarted {
+        log::warn!("Server restart failed: no free port");
     }
+    Ok(restarted)
+}
+
+/// Reports whether the app was launched in safe mode (`KI_SAFE_MODE`), in which
+/// clipboard monitoring and network fetching are disabled.
+#[tauri::command]
+pub fn get_safe_mode(state: State<Arc<AppState>>) -> bool {
+    state.safe_mode
+}
+
+/// Returns the usage counters of the current session, and how long ago the shared
+/// text changed, for the stats panel.
+#[tauri::command]
+pub fn get_reading_stats(state: State<Arc<AppState>>) -> StatsReport {
+    state.stats.report(state.updated_at.load(Ordering::SeqCst))
+}
+
+/// Returns the page the reader last reported being on, or `None` if it has not
+/// reported one for the current text yet.
+#[tauri::command]
+pub fn get_reading_progress(state: State<Arc<AppState>>) -> Option<ReadingProgress> {
+    state.reading_progress()
+}
+
+/// Returns every setting with its current value and allowed values, the same
+/// report as `GET /api/settings`.
+#[tauri::command]
+pub fn get_all_settings(state: State<Arc<AppState>>) -> SettingsReport {
+    state.settings().report()
 }
 
 /// Enables or disables automatically sending clipboard text to the e-reader.
 #[tauri::command]
 pub fn set_send_on_copy(enabled: bool, state: State<Arc<AppState>>) -> Result<(), String> {
-    state.send_on_copy.store(enabled, Ordering::Relaxed);
+    state.update_settings(|settings| settings.send_on_copy = enabled)?;
     log::info!("Send on copy set to: {}", enabled);
     Ok(())
 }
@@ -57,9 +779,132 @@ pub fn set_send_on_copy(enabled: bool, state: State<Arc<AppState>>) -> Result<()
 /// Enables or disables automatically adding clipboard text to the editor.
 #[tauri::command]
 pub fn set_add_to_editor_on_copy(enabled: bool, state: State<Arc<AppState>>) -> Result<(), String> {
-    state
-        .add_to_editor_on_copy
-        .store(enabled, Ordering::Relaxed);
+    state.update_settings(|settings| settings.add_to_editor_on_copy = enabled)?;
     log::info!("Add to editor on copy set to: {}", enabled);
     Ok(())
 }
+
+/// Enables or disables escaping Markdown syntax in clipboard text detected as plain text.
+#[tauri::command]
+pub fn set_escape_plain_text_on_copy(
+    enabled: bool,
+    state: State<Arc<AppState>>,
+) -> Result<(), String> {
+    state.update_settings(|settings| settings.escape_plain_text_on_copy = enabled)?;
+    log::info!("Escape plain text on copy set to: {}", enabled);
+    Ok(())
+}
+
+/// Enables or disables emptying the system clipboard after its text was captured.
+/// Off by default, since the copied text is lost from the clipboard.
+#[tauri::command]
+pub fn set_clear_clipboard_after_capture(
+    enabled: bool,
+    state: State<Arc<AppState>>,
+) -> Result<(), String> {
+    state.update_settings(|settings| settings.clear_clipboard_after_capture = enabled)?;
+    log::info!("Clear clipboard after capture set to: {}", enabled);
+    Ok(())
+}
+
+/// Sets after how many minutes without web server requests the application exits.
+/// `0` disables idle shutdown. The idle timer restarts when the setting changes.
+///
+/// # Returns
+/// The setting if it was out of range and had to be clamped, for a warning.
+#[tauri::command]
+pub fn set_idle_shutdown_minutes(
+    minutes: u64,
+    state: State<Arc<AppState>>,
+) -> Result<Vec<ClampedSetting>, String> {
+    let clamped = state.update_settings(|settings| settings.idle_shutdown_minutes = minutes)?;
+    state.record_request();
+    log::info!(
+        "Idle shutdown set to: {} minute(s)",
+        state.settings().idle_shutdown_minutes
+    );
+    Ok(clamped)
+}
+
+/// Sets how often the clipboard is checked for new content. Takes effect on the
+/// next check, without a restart.
+///
+/// # Arguments
+/// * `interval_ms` - The time between two checks, in milliseconds; at least 100.
+///
+/// # Returns
+/// The setting if it was out of range and had to be clamped, for a warning.
+#[tauri::command]
+pub fn set_clipboard_interval(
+    interval_ms: u64,
+    state: State<Arc<AppState>>,
+) -> Result<Vec<ClampedSetting>, String> {
+    let clamped = state.update_settings(|settings| settings.clipboard_interval_ms = interval_ms)?;
+    log::info!(
+        "Clipboard check interval set to: {} ms",
+        state.settings().clipboard_interval_ms
+    );
+    Ok(clamped)
+}
+
+/// Sets the maximum length of the shared text, in characters. Longer texts are
+/// rejected when saved or captured from the clipboard.
+///
+/// # Returns
+/// The setting if it was out of range and had to be clamped, for a warning.
+#[tauri::command]
+pub fn set_max_text_chars(
+    max_chars: u64,
+    state: State<Arc<AppState>>,
+) -> Result<Vec<ClampedSetting>, String> {
+    let clamped = state.update_settings(|settings| settings.max_text_chars = max_chars)?;
+    log::info!(
+        "Maximum text length set to: {} characters",
+        state.settings().max_text_chars
+    );
+    Ok(clamped)
+}
+
+/// Sets which tags fetched pages keep when they are sanitized: the standard set,
+/// text only (no images) or the full set with embedded media.
+#[tauri::command]
+pub fn set_sanitize_preset(
+    preset: SanitizePreset,
+    state: State<Arc<AppState>>,
+) -> Result<(), String> {
+    state.update_settings(|settings| settings.sanitize_preset = preset)?;
+    log::info!("Sanitize preset for fetched pages set to: {:?}", preset);
+    Ok(())
+}
+
+/// Switches between rendering the shared text as Markdown and showing it as
+/// sanitized HTML, and repaints the readers with the new rendering.
+#[tauri::command]
+pub fn set_content_mode(mode: ContentMode, state: State<Arc<AppState>>) -> Result<(), String> {
+    state.update_settings(|settings| settings.content_mode = mode)?;
+    log::info!("Content mode set to: {:?}", mode);
+    let text = state
+        .shared_text
+        .read()
+        .map(|text| text.clone())
+        .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+    state.notify_content_changed(&text);
+    Ok(())
+}
+
+/// Sets how many characters of captured content are shown in log messages.
+///
+/// # Returns
+/// The setting if it was out of range and had to be clamped, for a warning.
+#[tauri::command]
+pub fn set_log_preview_chars(
+    max_chars: usize,
+    state: State<Arc<AppState>>,
+) -> Result<Vec<ClampedSetting>, String> {
+    let clamped = state.update_settings(|settings| settings.log_preview_chars = max_chars)?;
+    log::info!(
+        "Log preview length set to: {}",
+        state.settings().log_preview_chars
+    );
+    Ok(clamped)
+}
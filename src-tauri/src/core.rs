@@ -1,4 +1,6 @@
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use unicode_segmentation::UnicodeSegmentation;
 
 /// Processes a Markdown string into HTML and computes its SHA1 hash.
 /// This function is central to determining if the content has changed.
@@ -11,13 +13,1977 @@ use sha1::{Digest, Sha1};
 /// * `String` - The generated HTML.
 /// * `String` - The hex-encoded SHA1 hash of the HTML.
 pub fn process_markdown(markdown_text: &str) -> (String, String) {
-    let html_content = markdown::to_html_with_options(markdown_text, &markdown::Options::gfm())
-        .unwrap_or_else(|e| format!("<p>Markdown processing error: {}</p>", e));
+    let (html_content, _) = process_markdown_with_toc(markdown_text);
+    let current_hash = sha1_hex(&html_content);
 
+    (html_content, current_hash)
+}
+
+/// A heading of a document, as listed in its table of contents.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TocEntry {
+    /// 1 for `<h1>` through 6 for `<h6>`.
+    pub level: u8,
+    /// The heading as plain text.
+    pub text: String,
+    /// The `id` of the heading element, for `#id` links. See [`heading_id`].
+    pub id: String,
+}
+
+/// Renders Markdown to HTML like [`process_markdown`] and lists its headings.
+///
+/// # Returns
+/// A tuple containing:
+/// * `String` - The generated HTML, with an `id` on every heading.
+/// * `Vec<TocEntry>` - The headings in document order.
+pub fn process_markdown_with_toc(markdown_text: &str) -> (String, Vec<TocEntry>) {
+    match markdown::to_html_with_options(markdown_text, &markdown::Options::gfm()) {
+        Ok(html) => add_heading_ids(&render_details(&render_figures(&render_definition_lists(
+            &mark_task_list_items(&html),
+        )))),
+        Err(e) => (
+            format!("<p>Markdown processing error: {}</p>", e),
+            Vec::new(),
+        ),
+    }
+}
+
+/// How the shared text is interpreted when it is rendered for the reader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentMode {
+    /// The text is Markdown; raw HTML in it is escaped.
+    #[default]
+    Markdown,
+    /// The text is an HTML fragment, shown as-is once sanitized.
+    Html,
+}
+
+/// Renders the shared text according to `mode` and lists its headings, like
+/// [`process_markdown_with_toc`]. In HTML mode the text skips Markdown parsing
+/// and is cleaned with [`sanitize_html`], the allowlist used for fetched articles.
+///
+/// [`sanitize_html`]: crate::url_processor::sanitize_html
+pub fn render_content_with_toc(text: &str, mode: ContentMode) -> (String, Vec<TocEntry>) {
+    match mode {
+        ContentMode::Markdown => process_markdown_with_toc(text),
+        ContentMode::Html => add_heading_ids(&crate::url_processor::sanitize_html(text)),
+    }
+}
+
+/// Renders the shared text according to `mode`, like [`process_markdown`].
+///
+/// # Returns
+/// The generated HTML and the hex-encoded SHA1 hash of it.
+pub fn render_content(text: &str, mode: ContentMode) -> (String, String) {
+    let (html, _) = render_content_with_toc(text, mode);
+    let hash = sha1_hex(&html);
+    (html, hash)
+}
+
+/// Returns the title of the shared text like [`document_title`]. In HTML mode it
+/// is the first `<h1>`, else (if `auto_title` is set) the first line of its text.
+pub fn content_title(text: &str, mode: ContentMode, auto_title: bool) -> String {
+    match mode {
+        ContentMode::Markdown => document_title(text, auto_title),
+        ContentMode::Html => {
+            let (html, toc) = render_content_with_toc(text, mode);
+            toc.into_iter()
+                .find(|entry| entry.level == 1)
+                .map(|entry| entry.text)
+                .or_else(|| {
+                    auto_title
+                        .then(|| derive_title(&html_to_plain_text(&html)))
+                        .flatten()
+                })
+                .unwrap_or_else(|| DEFAULT_TITLE.to_string())
+        }
+    }
+}
+
+/// Gives every `<hN>` heading an `id` made from its text with [`heading_id`], and
+/// lists the headings.
+fn add_heading_ids(html: &str) -> (String, Vec<TocEntry>) {
+    let mut rendered = String::with_capacity(html.len());
+    let mut toc: Vec<TocEntry> = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<h") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(heading) = parse_heading(rest) else {
+            rendered.push_str("<h");
+            rest = &rest[2..];
+            continue;
+        };
+
+        let text = html_to_plain_text(heading.inner).trim().to_string();
+        let base = heading_id(&text);
+        let mut id = base.clone();
+        let mut repeat = 0;
+        while toc.iter().any(|entry| entry.id == id) {
+            repeat += 1;
+            id = format!("{}-{}", base, repeat);
+        }
+        rendered.push_str(&format!(
+            "<h{} id=\"{}\"{}>{}",
+            heading.level, id, heading.attributes, heading.inner
+        ));
+        toc.push(TocEntry {
+            level: heading.level,
+            text,
+            id,
+        });
+        rest = &rest[heading.close..];
+    }
+
+    rendered.push_str(rest);
+    (rendered, toc)
+}
+
+/// A heading element found by [`parse_heading`].
+struct HeadingTag<'a> {
+    level: u8,
+    /// Everything between `<hN` and `>`, with its leading space.
+    attributes: &'a str,
+    inner: &'a str,
+    /// The offset of the closing `</hN>`.
+    close: usize,
+}
+
+/// Parses the heading element at the start of `html`, if there is one.
+fn parse_heading(html: &str) -> Option<HeadingTag<'_>> {
+    let bytes = html.as_bytes();
+    let level = bytes.get(2).filter(|b| (b'1'..=b'6').contains(b))? - b'0';
+    if !matches!(bytes.get(3), Some(b'>' | b' ')) {
+        return None;
+    }
+    let open_end = html.find('>')?;
+    let close = html.find(&format!("</h{}>", level))?;
+    (open_end < close).then(|| HeadingTag {
+        level,
+        attributes: &html[3..open_end],
+        inner: &html[open_end + 1..close],
+        close,
+    })
+}
+
+/// Makes the anchor id for a heading: its words in lowercase joined by `-`, with
+/// punctuation dropped (`"Глава 1: Начало"` becomes `"глава-1-начало"`). The same
+/// text always gives the same id, so links stay valid across edits; repeated
+/// headings get `-1`, `-2`, … suffixes in document order.
+pub fn heading_id(text: &str) -> String {
+    let id = text
+        .to_lowercase()
+        .split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if id.is_empty() {
+        "section".to_string()
+    } else {
+        id
+    }
+}
+
+/// Computes the hex-encoded SHA1 hash of a string.
+pub fn sha1_hex(text: &str) -> String {
+    sha1_hex_bytes(text.as_bytes())
+}
+
+/// Returns the SHA-1 hash of `bytes` as lowercase hex, like [`sha1_hex`].
+pub fn sha1_hex_bytes(bytes: &[u8]) -> String {
     let mut hasher = Sha1::new();
-    hasher.update(html_content.as_bytes());
-    let hash_result = hasher.finalize();
-    let current_hash = format!("{:x}", hash_result);
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
 
-    (html_content, current_hash)
+/// Adds a `task-list-item` class to list items that start with a GFM task checkbox
+/// (`- [ ]` / `- [x]`), so the reader can drop their bullets. The checkboxes
+/// themselves are already rendered as disabled inputs by the GFM parser.
+fn mark_task_list_items(html: &str) -> String {
+    const CHECKBOX: &str = "<input type=\"checkbox\"";
+    html.replace(
+        &format!("<li>{}", CHECKBOX),
+        &format!("<li class=\"task-list-item\">{}", CHECKBOX),
+    )
+    .replace(
+        &format!("<li>\n<p>{}", CHECKBOX),
+        &format!("<li class=\"task-list-item\">\n<p>{}", CHECKBOX),
+    )
+}
+
+/// Turns paragraphs written in the PHP Markdown Extra definition list syntax into
+/// `<dl>` elements, since the GFM parser has no support for them:
+///
+/// ```text
+/// Term
+/// : Definition
+/// ```
+///
+/// Lines before the first `: ` line become terms, each `: ` line starts a new
+/// definition and other lines continue the previous definition.
+fn render_definition_lists(html: &str) -> String {
+    let mut rendered = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<p>") {
+        rendered.push_str(&rest[..start]);
+        let after_open = &rest[start + 3..];
+        let Some(end) = after_open.find("</p>") else {
+            rest = &rest[start..];
+            break;
+        };
+        let paragraph = &after_open[..end];
+        match definition_list(paragraph) {
+            Some(list) => rendered.push_str(&list),
+            None => {
+                rendered.push_str("<p>");
+                rendered.push_str(paragraph);
+                rendered.push_str("</p>");
+            }
+        }
+        rest = &after_open[end + 4..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+fn definition_list(paragraph: &str) -> Option<String> {
+    let lines: Vec<&str> = paragraph.lines().collect();
+    let first_definition = lines.iter().position(|line| line.starts_with(": "))?;
+    if first_definition == 0 {
+        return None;
+    }
+
+    let mut list = String::from("<dl>\n");
+    for term in &lines[..first_definition] {
+        list.push_str(&format!("<dt>{}</dt>\n", term.trim()));
+    }
+
+    let mut definitions: Vec<String> = Vec::new();
+    for line in &lines[first_definition..] {
+        match (line.strip_prefix(": "), definitions.last_mut()) {
+            (Some(definition), _) => definitions.push(definition.trim().to_string()),
+            (None, Some(last)) => {
+                last.push('\n');
+                last.push_str(line.trim());
+            }
+            (None, None) => return None,
+        }
+    }
+    for definition in definitions {
+        list.push_str(&format!("<dd>{}</dd>\n", definition));
+    }
+
+    list.push_str("</dl>");
+    Some(list)
+}
+
+/// The `<details>`/`<summary>` tags as escaped by the GFM parser, and the elements
+/// they are turned back into.
+const DETAILS_TAGS: [(&str, &str); 5] = [
+    ("&lt;details&gt;", "<details>"),
+    ("&lt;details open&gt;", "<details open>"),
+    ("&lt;/details&gt;", "</details>"),
+    ("&lt;summary&gt;", "<summary>"),
+    ("&lt;/summary&gt;", "</summary>"),
+];
+
+/// Restores collapsible `<details>` sections. Raw HTML is escaped by the GFM
+/// parser, so an HTML block such as
+///
+/// ```text
+/// <details>
+/// <summary>Title</summary>
+///
+/// Hidden *Markdown*
+///
+/// </details>
+/// ```
+///
+/// arrives as text. On lines that start with one of these tags (outside code
+/// blocks) only the `details`/`summary` tags are turned back into elements;
+/// everything else stays escaped. Unclosed sections are closed at the end of the
+/// document and stray closing tags are kept as text.
+fn render_details(html: &str) -> String {
+    let mut rendered = String::with_capacity(html.len());
+    let mut open_sections = 0usize;
+    let mut in_pre = false;
+
+    for line in html.split_inclusive('\n') {
+        if in_pre || line.starts_with("<pre") {
+            in_pre = !line.contains("</pre>");
+            rendered.push_str(line);
+            continue;
+        }
+        if !DETAILS_TAGS
+            .iter()
+            .any(|(escaped, _)| line.starts_with(escaped))
+        {
+            rendered.push_str(line);
+            continue;
+        }
+
+        let mut rest = line;
+        while !rest.is_empty() {
+            match DETAILS_TAGS
+                .iter()
+                .find(|(escaped, _)| rest.starts_with(escaped))
+            {
+                Some(&(escaped, tag)) => {
+                    match tag {
+                        "<details>" | "<details open>" => open_sections += 1,
+                        "</details>" if open_sections > 0 => open_sections -= 1,
+                        "</details>" => {
+                            // A stray closing tag stays visible as text.
+                            rendered.push_str(escaped);
+                            rest = &rest[escaped.len()..];
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    rendered.push_str(tag);
+                    rest = &rest[escaped.len()..];
+                }
+                None => {
+                    let skip = rest.chars().next().map_or(0, char::len_utf8);
+                    let next = rest[skip..].find("&lt;").map_or(rest.len(), |i| i + skip);
+                    rendered.push_str(&rest[..next]);
+                    rest = &rest[next..];
+                }
+            }
+        }
+    }
+
+    for _ in 0..open_sections {
+        rendered.push_str("</details>");
+    }
+    rendered
+}
+
+/// Wraps paragraphs that hold nothing but an image into `<figure>` elements, so the
+/// reader can center them. An italic-only paragraph right after the image becomes
+/// its `<figcaption>`; this is how captions of fetched articles come out of the
+/// Markdown conversion:
+///
+/// ```text
+/// ![Alt](image.png)
+///
+/// *Caption*
+/// ```
+fn render_figures(html: &str) -> String {
+    let mut rendered = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<p><img ") {
+        rendered.push_str(&rest[..start]);
+        let after_open = &rest[start + 3..];
+        let Some(end) = after_open.find("</p>") else {
+            rest = &rest[start..];
+            break;
+        };
+        let image = &after_open[..end];
+        if !(image.ends_with("/>") && image.matches('<').count() == 1) {
+            rendered.push_str("<p>");
+            rest = after_open;
+            continue;
+        }
+        rest = &after_open[end + 4..];
+
+        rendered.push_str("<figure>");
+        rendered.push_str(image);
+        let caption = rest
+            .strip_prefix('\n')
+            .and_then(|next| next.strip_prefix("<p><em>"))
+            .and_then(|next| {
+                let end = next.find("</em></p>")?;
+                let caption = &next[..end];
+                (!caption.contains("</p>")).then(|| (caption, &next[end + 9..]))
+            });
+        if let Some((caption, after_caption)) = caption {
+            rendered.push_str("<figcaption>");
+            rendered.push_str(caption);
+            rendered.push_str("</figcaption>");
+            rest = after_caption;
+        }
+        rendered.push_str("</figure>");
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Replaces every image of rendered HTML with its alt text, for readers that hide
+/// images. Images without alt text are dropped.
+pub fn replace_images_with_alt(html: &str) -> String {
+    map_images(html, |tag| match image_alt(tag) {
+        "" => String::new(),
+        alt => format!("<span class=\"image-alt\">{}</span>", alt),
+    })
+}
+
+/// Moves every image of rendered HTML into an "Иллюстрации" section at the end.
+/// Each image leaves a numbered link in the text, and its gallery entry links back.
+pub fn move_images_to_gallery(html: &str) -> String {
+    let mut gallery = String::new();
+    let mut count = 0;
+
+    let mut rendered = map_images(html, |tag| {
+        count += 1;
+        let alt = image_alt(tag);
+        gallery.push_str(&format!(
+            "<figure id=\"image-{n}\">{tag}<figcaption><a href=\"#image-ref-{n}\">{n}.</a> {alt}</figcaption></figure>\n",
+            n = count,
+        ));
+        format!(
+            "<a class=\"image-ref\" id=\"image-ref-{n}\" href=\"#image-{n}\">[илл. {n}]</a>",
+            n = count
+        )
+    });
+
+    if count > 0 {
+        rendered.push_str("\n<section class=\"image-gallery\">\n<h2>Иллюстрации</h2>\n");
+        rendered.push_str(&gallery);
+        rendered.push_str("</section>");
+    }
+    rendered
+}
+
+/// Calls `replace` for every `<img>` tag of rendered HTML and substitutes the result.
+fn map_images(html: &str, mut replace: impl FnMut(&str) -> String) -> String {
+    let mut rendered = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<img ") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        rendered.push_str(&replace(&rest[..=end]));
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// The (still HTML-escaped) `alt` attribute of a rendered `<img>` tag.
+fn image_alt(tag: &str) -> &str {
+    tag.split_once(" alt=\"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map_or("", |(alt, _)| alt)
+}
+
+/// Flattens Markdown into clean plain text, e.g. for text-to-speech or word counts.
+///
+/// The text is rendered to HTML first so that all Markdown syntax is resolved,
+/// then flattened with [`html_to_plain_text`].
+pub fn markdown_to_plain_text(markdown_text: &str) -> String {
+    let (html_content, _) = process_markdown(markdown_text);
+    html_to_plain_text(&html_content)
+}
+
+/// Counts the words of a Markdown document, ignoring markup.
+pub fn count_words(markdown_text: &str) -> usize {
+    markdown_to_plain_text(markdown_text)
+        .split_whitespace()
+        .count()
+}
+
+/// Counts the characters of `markdown`, leaving out the `data:` URIs of embedded
+/// images (`![..](data:image/..)` or `src="data:image/.."`): an embedded image is
+/// long, but cheap to render compared to the same length of text. Any other
+/// `data:` text counts in full.
+pub fn count_chars_without_data_uris(markdown: &str) -> usize {
+    const IMAGE_URI: &str = "data:image/";
+    let mut count = 0;
+    let mut rest = markdown;
+    while let Some(start) = rest.find(IMAGE_URI) {
+        let before = &rest[..start];
+        count += before.chars().count();
+        let uri = &rest[start..];
+        let embedded = ["](", "src=\"", "src='"]
+            .iter()
+            .any(|opening| before.ends_with(opening));
+        let end = if embedded {
+            uri.find(|c: char| matches!(c, ')' | '"' | '\'') || c.is_whitespace())
+                .unwrap_or(uri.len())
+        } else {
+            count += IMAGE_URI.len();
+            IMAGE_URI.len()
+        };
+        rest = &uri[end..];
+    }
+    count + rest.chars().count()
+}
+
+/// Returns whether `new` only adds text to the end of `old`, as when captured
+/// text is appended in the editor. Trailing whitespace of `old` is ignored, so
+/// appending after a blank line still counts. Nothing counts as appended to an
+/// empty text.
+pub fn is_append(old: &str, new: &str) -> bool {
+    let old = old.trim_end();
+    !old.is_empty() && new.len() > old.len() && new.starts_with(old)
+}
+
+/// Strips tags from rendered HTML, keeping the structure readable as text.
+///
+/// Whitespace is collapsed outside of `<pre>` blocks, block elements are separated
+/// by blank lines, list items get `•` or `N.` markers (indented when nested),
+/// table cells are separated by tabs and HTML entities are decoded.
+pub fn html_to_plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    // One entry per open list: `None` for bullets, `Some(n)` for the next number.
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    let mut pre_depth = 0usize;
+    let mut at_item_start = false;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let tag_start = rest.find('<').unwrap_or(rest.len());
+        let raw_text = decode_html_entities(&rest[..tag_start]);
+        if pre_depth > 0 {
+            text.push_str(&raw_text);
+        } else {
+            push_collapsed(&mut text, &raw_text);
+        }
+        if !raw_text.trim().is_empty() {
+            at_item_start = false;
+        }
+        rest = &rest[tag_start..];
+        if rest.is_empty() {
+            break;
+        }
+
+        let tag_end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let tag = &rest[1..tag_end.saturating_sub(1).max(1)];
+        rest = &rest[tag_end..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        match (name.as_str(), closing) {
+            ("ul", false) => {
+                lists.push(None);
+                push_line_break(&mut text);
+            }
+            ("ol", false) => {
+                lists.push(Some(ordered_list_start(tag)));
+                push_line_break(&mut text);
+            }
+            ("ul" | "ol", true) => {
+                lists.pop();
+                if lists.is_empty() {
+                    push_block_break(&mut text);
+                } else {
+                    push_line_break(&mut text);
+                }
+            }
+            ("li", false) => {
+                push_line_break(&mut text);
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("• "),
+                }
+                at_item_start = true;
+            }
+            ("input", false) if tag.contains("checkbox") => {
+                text.push_str(if tag.contains("checked") {
+                    "[x] "
+                } else {
+                    "[ ] "
+                });
+            }
+            ("br", _) => text.push('\n'),
+            ("td" | "th", true) => text.push('\t'),
+            ("tr", true) => {
+                while text.ends_with('\t') {
+                    text.pop();
+                }
+                text.push('\n');
+            }
+            ("pre", _) => {
+                if closing {
+                    pre_depth = pre_depth.saturating_sub(1);
+                } else {
+                    pre_depth += 1;
+                }
+                push_block_break(&mut text);
+            }
+            (
+                "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" | "div" | "table"
+                | "hr" | "figure" | "figcaption" | "dl" | "dt" | "dd" | "details" | "summary",
+                _,
+            ) if !at_item_start => {
+                if lists.is_empty() {
+                    push_block_break(&mut text);
+                } else {
+                    push_line_break(&mut text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    normalize_blank_lines(&text)
+}
+
+/// Appends text with runs of whitespace collapsed into single spaces.
+fn push_collapsed(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !out.is_empty() && !out.ends_with([' ', '\n', '\t']) {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+fn push_line_break(out: &mut String) {
+    let trimmed_len = out.trim_end_matches(' ').len();
+    out.truncate(trimmed_len);
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn push_block_break(out: &mut String) {
+    push_line_break(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+/// Reads the `start` attribute of an `<ol>` tag, defaulting to 1.
+fn ordered_list_start(tag: &str) -> usize {
+    tag.split_once("start=\"")
+        .and_then(|(_, value)| value.split('"').next())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Trims trailing spaces on every line and collapses runs of blank lines into one.
+fn normalize_blank_lines(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut blank_run = 0;
+
+    for line in text.trim().lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        normalized.push_str(line);
+        normalized.push('\n');
+    }
+
+    normalized.trim_end().to_string()
+}
+
+/// Decodes named (`&amp;`, `&lt;`, `&gt;`, `&quot;`, `&apos;`, `&nbsp;`) and
+/// numeric (`&#39;`, `&#x27;`) character references. Unknown ones are kept as is.
+pub fn decode_html_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let entity_end = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '#')
+            .map(|end| end + 1)
+            .filter(|&end| rest[end..].starts_with(';'));
+        let replacement = entity_end.and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse::<u32>))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end + 1))
+        });
+
+        match replacement {
+            Some((c, consumed)) => {
+                decoded.push(c);
+                rest = &rest[consumed..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Average silent reading speed used for reading time estimates.
+pub const READING_WORDS_PER_MINUTE: usize = 200;
+
+/// Size metrics of a document, as reported by `GET /api/stats`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContentStats {
+    /// Characters of the Markdown source, markup included.
+    pub chars: usize,
+    /// Words, not counting Markdown or HTML markup; see [`count_words`].
+    pub words: usize,
+    /// Estimated reading time in whole minutes, at [`READING_WORDS_PER_MINUTE`].
+    pub reading_minutes: usize,
+    /// The source hash, as in `GET /api/source`.
+    pub hash: String,
+}
+
+/// Measures a Markdown document: its length, word count and reading time.
+pub fn content_stats(markdown_text: &str) -> ContentStats {
+    let words = count_words(markdown_text);
+    ContentStats {
+        chars: markdown_text.chars().count(),
+        words,
+        reading_minutes: words.div_ceil(READING_WORDS_PER_MINUTE),
+        hash: sha1_hex(markdown_text),
+    }
+}
+
+/// Font scales (in `em`) offered by the reader; `1.3` is the default size.
+pub const FONT_SCALES: [(&str, f32); 4] = [
+    ("Мелкий", 1.0),
+    ("Средний", 1.3),
+    ("Крупный", 1.6),
+    ("Очень крупный", 2.0),
+];
+
+/// CSS viewport of a typical 6" E-Ink reader, in pixels.
+const ESTIMATE_VIEWPORT_WIDTH: f32 = 600.0;
+const ESTIMATE_VIEWPORT_HEIGHT: f32 = 800.0;
+
+/// A rough page count for the document at one font scale.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PageEstimate {
+    pub label: String,
+    pub font_scale: f32,
+    /// An approximation from average glyph metrics, not a real layout.
+    pub estimated_pages: usize,
+}
+
+/// Estimates how many reader pages the document takes at each of [`FONT_SCALES`].
+///
+/// The estimate uses the flattened plain text and the reader's layout constants
+/// (16px base font, 1.6 line height, 25px side margins, 40px UI bar) on a standard
+/// 600×800 viewport, assuming an average glyph is half an `em` wide. Each paragraph
+/// break costs one extra line.
+pub fn estimate_pages(markdown_text: &str) -> Vec<PageEstimate> {
+    let plain_text = markdown_to_plain_text(markdown_text);
+    let char_count = plain_text.chars().filter(|c| *c != '\n').count() as f32;
+    let line_breaks = plain_text.matches('\n').count() as f32;
+
+    FONT_SCALES
+        .iter()
+        .map(|&(label, font_scale)| {
+            let font_px = 16.0 * font_scale;
+            let chars_per_line = ((ESTIMATE_VIEWPORT_WIDTH - 50.0) / (font_px * 0.5)).floor();
+            let lines_per_page = ((ESTIMATE_VIEWPORT_HEIGHT - 40.0) / (font_px * 1.6)).floor();
+            let lines = (char_count / chars_per_line).ceil() + line_breaks;
+
+            PageEstimate {
+                label: label.to_string(),
+                font_scale,
+                estimated_pages: ((lines / lines_per_page).ceil() as usize).max(1),
+            }
+        })
+        .collect()
+}
+
+/// Extracts the document title from the first level-one ATX heading (`# Title`).
+///
+/// # Returns
+/// The trimmed heading text, or `None` if the document has no H1.
+pub fn extract_title(markdown_text: &str) -> Option<String> {
+    markdown_text
+        .lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().trim_end_matches('#').trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// The title of documents that have none, e.g. in the reader tab and exports.
+pub const DEFAULT_TITLE: &str = "Текст для чтения";
+
+/// The longest title, in characters, derived by [`derive_title`].
+pub const AUTO_TITLE_MAX_CHARS: usize = 60;
+
+/// Returns the title to show for a document in page and export metadata: its first
+/// H1, else (if `auto_title` is set) a title derived from its first line of text,
+/// else [`DEFAULT_TITLE`]. The document itself is never changed.
+pub fn document_title(markdown_text: &str, auto_title: bool) -> String {
+    extract_title(markdown_text)
+        .or_else(|| auto_title.then(|| derive_title(markdown_text)).flatten())
+        .unwrap_or_else(|| DEFAULT_TITLE.to_string())
+}
+
+/// Derives a title from the first line that has any text once Markdown syntax is
+/// removed. Lines with only an image, a rule or a code fence are skipped. Titles
+/// longer than [`AUTO_TITLE_MAX_CHARS`] are cut at a word boundary and end with `…`.
+///
+/// # Returns
+/// The derived title, or `None` if the document has no text.
+pub fn derive_title(markdown_text: &str) -> Option<String> {
+    let line = markdown_text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| markdown_to_plain_text(line.trim()))
+        .map(|text| {
+            let text = text.trim_start();
+            text.strip_prefix("• ").unwrap_or(text).trim().to_string()
+        })
+        .find(|text| !text.is_empty())?;
+    let title = line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if title.chars().count() <= AUTO_TITLE_MAX_CHARS {
+        return Some(title);
+    }
+    let cut = title
+        .char_indices()
+        .nth(AUTO_TITLE_MAX_CHARS)
+        .map_or(title.len(), |(index, _)| index);
+    let truncated = match title[..cut].rfind(' ') {
+        Some(space) if space > 0 => &title[..space],
+        _ => &title[..cut],
+    };
+    Some(format!(
+        "{}…",
+        truncated.trim_end_matches([',', ';', ':', '-', ' '])
+    ))
+}
+
+/// Makes `title` the leading H1 of the document, replacing an existing leading H1.
+/// A blank title leaves the document unchanged.
+///
+/// # Arguments
+/// * `markdown_text` - The document body.
+/// * `title` - The title; line breaks in it are collapsed to spaces.
+///
+/// # Returns
+/// The document starting with `# {title}`.
+pub fn set_leading_title(markdown_text: &str, title: &str) -> String {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        return markdown_text.to_string();
+    }
+
+    let is_h1 = |line: &str| {
+        let line = line.trim();
+        line == "#" || line.starts_with("# ")
+    };
+    let body = markdown_text.trim_start_matches(['\n', '\r']);
+    let (first_line, rest) = body.split_once('\n').unwrap_or((body, ""));
+    let body = if is_h1(first_line) { rest } else { body };
+    let body = body.trim_start_matches(['\n', '\r']);
+
+    if body.is_empty() {
+        format!("# {}\n", title)
+    } else {
+        format!("# {}\n\n{}", title, body)
+    }
+}
+
+/// The longest search pattern accepted by [`find_replace`].
+pub const MAX_FIND_PATTERN_CHARS: usize = 1_000;
+
+/// The memory limit, in bytes, for a compiled [`find_replace`] regex.
+const FIND_REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// The outcome of [`find_replace`].
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Replacement {
+    /// The text after all replacements.
+    pub text: String,
+    /// How many occurrences were replaced.
+    pub count: usize,
+}
+
+/// Replaces every occurrence of `pattern` in `text`.
+///
+/// In regex mode the pattern uses the `regex` crate syntax and the replacement
+/// may refer to capture groups (`$1`, `${name}`). The `regex` crate matches in
+/// linear time, so pathological patterns cannot hang; overly large patterns are
+/// rejected by the length and compiled-size limits instead.
+///
+/// # Arguments
+/// * `text` - The text to search.
+/// * `pattern` - A literal string, or a regular expression if `regex` is set.
+/// * `replacement` - The replacement text.
+/// * `regex` - Whether `pattern` is a regular expression.
+///
+/// # Returns
+/// The new text and the number of replacements, or an error for an empty,
+/// oversized or invalid pattern.
+pub fn find_replace(
+    text: &str,
+    pattern: &str,
+    replacement: &str,
+    regex: bool,
+) -> Result<Replacement, String> {
+    if pattern.is_empty() {
+        return Err("Пустой шаблон поиска.".to_string());
+    }
+    if pattern.chars().count() > MAX_FIND_PATTERN_CHARS {
+        return Err(format!(
+            "Шаблон поиска длиннее {} символов.",
+            MAX_FIND_PATTERN_CHARS
+        ));
+    }
+
+    if !regex {
+        return Ok(Replacement {
+            text: text.replace(pattern, replacement),
+            count: text.matches(pattern).count(),
+        });
+    }
+
+    let re = regex::RegexBuilder::new(pattern)
+        .size_limit(FIND_REGEX_SIZE_LIMIT)
+        .dfa_size_limit(FIND_REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("Неверное регулярное выражение: {}", e))?;
+    Ok(Replacement {
+        text: re.replace_all(text, replacement).into_owned(),
+        count: re.find_iter(text).count(),
+    })
+}
+
+/// How long [`diff_lines`] may search for a minimal diff before settling for a
+/// coarser one.
+const DIFF_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Whether a [`DiffSegment`] is unchanged, added or removed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    Equal,
+    Added,
+    Removed,
+}
+
+/// A run of consecutive lines with the same [`DiffKind`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DiffSegment {
+    pub kind: DiffKind,
+    /// The lines of the segment, each with its line ending.
+    pub text: String,
+    pub lines: usize,
+}
+
+/// A line-based diff between two texts.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LineDiff {
+    /// The number of added lines.
+    pub added: usize,
+    /// The number of removed lines.
+    pub removed: usize,
+    /// All lines of both texts in order, grouped into segments.
+    pub segments: Vec<DiffSegment>,
+}
+
+/// Computes a line-based diff from `old_text` to `new_text`.
+///
+/// # Arguments
+/// * `old_text` - The current text.
+/// * `new_text` - The proposed text.
+///
+/// # Returns
+/// The diff, with consecutive lines of the same kind merged into one segment.
+pub fn diff_lines(old_text: &str, new_text: &str) -> LineDiff {
+    let diff = similar::TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(old_text, new_text);
+
+    let mut result = LineDiff {
+        added: 0,
+        removed: 0,
+        segments: Vec::new(),
+    };
+    for change in diff.iter_all_changes() {
+        let kind = match change.tag() {
+            similar::ChangeTag::Equal => DiffKind::Equal,
+            similar::ChangeTag::Insert => {
+                result.added += 1;
+                DiffKind::Added
+            }
+            similar::ChangeTag::Delete => {
+                result.removed += 1;
+                DiffKind::Removed
+            }
+        };
+
+        match result.segments.last_mut() {
+            Some(segment) if segment.kind == kind => {
+                segment.text.push_str(change.value());
+                segment.lines += 1;
+            }
+            _ => result.segments.push(DiffSegment {
+                kind,
+                text: change.value().to_string(),
+                lines: 1,
+            }),
+        }
+    }
+
+    result
+}
+
+/// Elements whose whitespace is significant; [`minify_html`] leaves their content
+/// untouched. Inline `code` is included since the reader shows it with `pre-wrap`.
+const WHITESPACE_SENSITIVE_TAGS: [&str; 5] = ["pre", "code", "textarea", "script", "style"];
+
+/// Block-level elements, around which whitespace has no effect on rendering.
+const BLOCK_TAGS: [&str; 33] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "details",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "ol",
+    "p",
+    "section",
+    "summary",
+    "table",
+    "tr",
+    "ul",
+    "main",
+];
+
+/// Removes insignificant whitespace from HTML to shrink it for slow connections.
+///
+/// Whitespace runs in text are collapsed to a single space and dropped entirely
+/// next to block-level tags. The content of `<pre>`, `<code>` and the other
+/// [`WHITESPACE_SENSITIVE_TAGS`] is kept byte for byte, as are the tags themselves.
+pub fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut sensitive_depth = 0usize;
+    let mut after_block = true;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            let (name, closing) = tag_name(tag);
+            if WHITESPACE_SENSITIVE_TAGS.contains(&name.as_str()) && !tag.ends_with("/>") {
+                if closing {
+                    sensitive_depth = sensitive_depth.saturating_sub(1);
+                } else {
+                    sensitive_depth += 1;
+                }
+            }
+            after_block = BLOCK_TAGS.contains(&name.as_str());
+            minified.push_str(tag);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..end];
+        rest = &rest[end..];
+        if sensitive_depth > 0 {
+            minified.push_str(text);
+            continue;
+        }
+
+        let before_block = rest.is_empty() || BLOCK_TAGS.contains(&tag_name(rest).0.as_str());
+        let leading_space = text.starts_with(char::is_whitespace) && !after_block;
+        let trailing_space = text.ends_with(char::is_whitespace) && !before_block;
+        let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if words.is_empty() {
+            // Whitespace between two inline elements still separates them.
+            if leading_space && trailing_space {
+                minified.push(' ');
+            }
+            continue;
+        }
+        if leading_space {
+            minified.push(' ');
+        }
+        minified.push_str(&words);
+        if trailing_space {
+            minified.push(' ');
+        }
+    }
+
+    minified
+}
+
+/// Returns the lowercase element name of a tag such as `<P class="x">` or `</p>`,
+/// and whether it is a closing tag.
+fn tag_name(tag: &str) -> (String, bool) {
+    let inner = tag.trim_start_matches('<');
+    let closing = inner.starts_with('/');
+    let name = inner
+        .trim_start_matches('/')
+        .chars()
+        .take_while(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    (name, closing)
+}
+
+/// Escapes the characters that are significant in HTML text and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Shortens text for log output so large pastes don't flood the log.
+///
+/// The text is cut after `max_chars` grapheme clusters, so multi-byte characters
+/// and combined sequences (emoji, accents) are never split. Truncated output ends
+/// with an ellipsis and the total character count.
+///
+/// # Arguments
+/// * `text` - The text to be logged.
+/// * `max_chars` - The maximum number of graphemes to keep.
+///
+/// # Returns
+/// A single-line preview of the text, safe to pass to the logger.
+pub fn truncate_for_log(text: &str, max_chars: usize) -> String {
+    let single_line = text.replace(['\r', '\n'], " ");
+    let mut graphemes = single_line.graphemes(true);
+    let preview: String = graphemes.by_ref().take(max_chars).collect();
+
+    if graphemes.next().is_none() {
+        preview
+    } else {
+        format!("{}… ({} chars)", preview, text.chars().count())
+    }
+}
+
+/// Guesses whether captured text was written as Markdown or is plain text.
+///
+/// Block-level markers (headings, fences, quotes, tables, lists of two or more
+/// items) and well-formed inline syntax (links, paired emphasis, inline code)
+/// count as Markdown. Stray `*`, `_` or `#` inside ordinary prose or a shell
+/// command do not.
+pub fn looks_like_markdown(text: &str) -> bool {
+    let mut list_items = 0;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if is_atx_heading(trimmed)
+            || trimmed.starts_with("```")
+            || trimmed.starts_with("~~~")
+            || trimmed.starts_with("> ")
+            || is_table_delimiter_row(trimmed)
+        {
+            return true;
+        }
+        if list_marker_len(trimmed).is_some() {
+            list_items += 1;
+        }
+        if has_inline_markdown(trimmed) {
+            return true;
+        }
+    }
+
+    list_items >= 2
+}
+
+/// Escapes Markdown-significant characters so plain text renders literally.
+///
+/// Inline markers are escaped everywhere, block markers only at the start of a
+/// line, which keeps the stored text readable in the editor.
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + text.len() / 8);
+
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            escaped.push('\n');
+        }
+
+        let content = line.trim_start();
+        escaped.push_str(&line[..line.len() - content.len()]);
+
+        let mut rest = content;
+        if let Some(digits) = ordered_list_digits(content) {
+            escaped.push_str(&content[..digits]);
+            escaped.push('\\');
+            rest = &content[digits..];
+        } else if content.starts_with(['#', '>', '-', '+', '=']) {
+            escaped.push('\\');
+        }
+
+        for c in rest.chars() {
+            if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '~') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+    }
+
+    escaped
+}
+
+/// Prepares text captured from the clipboard for storage as Markdown.
+///
+/// When `escape_plain_text` is enabled and the text doesn't look like Markdown,
+/// its Markdown-significant characters are escaped. Otherwise it is kept as is.
+pub fn prepare_captured_text(text: &str, escape_plain_text: bool) -> String {
+    if escape_plain_text && !looks_like_markdown(text) {
+        escape_markdown(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Decides whether Markdown converted from the HTML variant of clipboard content
+/// should be captured instead of its plain text variant.
+///
+/// Plain text stays the default. HTML wins only when the text is empty or garbled
+/// (contains U+FFFD), or when both hold the same words and only the HTML keeps
+/// formatting such as links, headings, lists or emphasis.
+pub fn prefer_html_capture(text: &str, html_markdown: &str) -> bool {
+    if html_markdown.trim().is_empty() {
+        return false;
+    }
+    if text.trim().is_empty() || text.contains(char::REPLACEMENT_CHARACTER) {
+        return true;
+    }
+
+    // List markers and other symbols differ between the variants; only words count.
+    let words = |text: &str| {
+        text.split_whitespace()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .count()
+    };
+    let text_words = words(text);
+    let html_words = words(&markdown_to_plain_text(html_markdown));
+    // Within 10%: the HTML is the same selection, not a fragment or extra page chrome.
+    let same_text = html_words.abs_diff(text_words) * 10 <= text_words;
+    same_text && looks_like_markdown(html_markdown) && !looks_like_markdown(text)
+}
+
+/// The Markdown block a selection of editor text can be turned into.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionFormat {
+    Quote,
+    H1,
+    H2,
+    Code,
+    Bullet,
+}
+
+/// Formats a selection of editor text as the given Markdown block.
+///
+/// Quotes and bullets prefix every non-blank line; existing list markers are
+/// replaced. Headings join the lines into one and replace an existing heading
+/// marker. Code becomes an inline span, or a fenced block if it spans several
+/// lines. A trailing line break of the selection is kept.
+///
+/// # Arguments
+/// * `kind` - The block to produce.
+/// * `text` - The selected text.
+///
+/// # Returns
+/// The formatted Markdown, to replace the selection with.
+pub fn format_selection(kind: SelectionFormat, text: &str) -> String {
+    let (body, line_break) = match text.strip_suffix('\n') {
+        Some(body) => (body.strip_suffix('\r').unwrap_or(body), "\n"),
+        None => (text, ""),
+    };
+
+    let formatted = match kind {
+        SelectionFormat::Quote => prefix_lines(body, |line| format!("> {}", line)),
+        SelectionFormat::Bullet => prefix_lines(body, |line| {
+            let content = line.trim_start();
+            let marker = list_marker_len(content).unwrap_or(0);
+            format!("- {}", &content[marker..])
+        }),
+        SelectionFormat::H1 => heading(body, "#"),
+        SelectionFormat::H2 => heading(body, "##"),
+        SelectionFormat::Code if body.contains('\n') => {
+            let fence = "`".repeat(longest_run(body, '`').max(2) + 1);
+            format!("{}\n{}\n{}", fence, body, fence)
+        }
+        SelectionFormat::Code => {
+            let ticks = "`".repeat(longest_run(body, '`') + 1);
+            // A space keeps backticks at the edges from merging with the delimiters.
+            let pad = if body.starts_with('`') || body.ends_with('`') {
+                " "
+            } else {
+                ""
+            };
+            format!("{}{}{}{}{}", ticks, pad, body, pad, ticks)
+        }
+    };
+
+    formatted + line_break
+}
+
+fn prefix_lines(text: &str, prefix: impl Fn(&str) -> String) -> String {
+    text.lines()
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else {
+                prefix(line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn heading(text: &str, marker: &str) -> String {
+    let title = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title = if is_atx_heading(&title) {
+        title.trim_start_matches('#').trim_start()
+    } else {
+        &title
+    };
+    format!("{} {}", marker, title)
+}
+
+/// Returns the length of the longest run of `c` in `text`.
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c)
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0)
+}
+
+fn is_atx_heading(line: &str) -> bool {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    (1..=6).contains(&level) && line[level..].starts_with(' ')
+}
+
+fn is_table_delimiter_row(line: &str) -> bool {
+    line.contains('|')
+        && line.contains("---")
+        && line
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+}
+
+/// Returns the length of a leading `- `, `* `, `+ ` or `1. ` list marker.
+fn list_marker_len(line: &str) -> Option<usize> {
+    if line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ") {
+        return Some(2);
+    }
+    ordered_list_digits(line)
+        .filter(|&digits| line[digits + 1..].starts_with(' '))
+        .map(|digits| digits + 2)
+}
+
+/// Returns the number of leading digits when the line starts like `12.` or `12)`.
+fn ordered_list_digits(line: &str) -> Option<usize> {
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    (digits > 0 && line[digits..].starts_with(['.', ')'])).then_some(digits)
+}
+
+fn has_inline_markdown(line: &str) -> bool {
+    let has_link = line
+        .find("](")
+        .is_some_and(|pos| line[..pos].contains('[') && line[pos..].contains(')'));
+
+    has_link
+        || has_delimited_span(line, "**")
+        || has_delimited_span(line, "__")
+        || has_delimited_span(line, "*")
+        || has_delimited_span(line, "`")
+}
+
+/// Checks for `<delim>text<delim>` where the opening delimiter follows whitespace
+/// (or the line start), the text doesn't start or end with a space, and the
+/// closing delimiter isn't glued to a following word.
+fn has_delimited_span(line: &str, delim: &str) -> bool {
+    let mut search_from = 0;
+
+    while let Some(offset) = line[search_from..].find(delim) {
+        let open = search_from + offset;
+        let inner_start = open + delim.len();
+        search_from = inner_start;
+
+        let opens_word = line[..open]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+        let inner = &line[inner_start..];
+        if !opens_word || inner.starts_with(char::is_whitespace) || inner.starts_with(delim) {
+            continue;
+        }
+
+        if let Some(close) = inner.find(delim) {
+            let span = &inner[..close];
+            let after = &inner[close + delim.len()..];
+            let closes_word = after
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_whitespace() || c.is_ascii_punctuation());
+            if !span.is_empty() && !span.ends_with(char::is_whitespace) && closes_word {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_markdown_renders_task_lists_as_disabled_checkboxes() {
+        let (html, _) = process_markdown("- [ ] todo\n- [x] done\n- plain");
+
+        assert!(html.contains(
+            "<li class=\"task-list-item\"><input type=\"checkbox\" disabled=\"\" /> todo</li>"
+        ));
+        assert!(html.contains(
+            "<li class=\"task-list-item\"><input type=\"checkbox\" disabled=\"\" checked=\"\" /> done</li>"
+        ));
+        assert!(html.contains("<li>plain</li>"));
+    }
+
+    #[test]
+    fn process_markdown_renders_definition_lists() {
+        let (html, _) =
+            process_markdown("Apple\n: A *fruit*\n: A company\n\nJust text\n:not a definition");
+
+        assert!(html.contains(
+            "<dl>\n<dt>Apple</dt>\n<dd>A <em>fruit</em></dd>\n<dd>A company</dd>\n</dl>"
+        ));
+        assert!(html.contains("<p>Just text\n:not a definition</p>"));
+    }
+
+    #[test]
+    fn process_markdown_wraps_lone_images_in_figures() {
+        let (html, _) = process_markdown(
+            "![A](a.png)\n\n*Caption*\n\n![B](b.png)\n\nText\n\nInline ![C](c.png) image",
+        );
+
+        assert!(html.contains(
+            "<figure><img src=\"a.png\" alt=\"A\" /><figcaption>Caption</figcaption></figure>"
+        ));
+        assert!(html.contains("<figure><img src=\"b.png\" alt=\"B\" /></figure>\n<p>Text</p>"));
+        assert!(html.contains("<p>Inline <img src=\"c.png\" alt=\"C\" /> image</p>"));
+    }
+
+    #[test]
+    fn process_markdown_renders_details_sections() {
+        let (html, _) = process_markdown(
+            "<details>\n<summary>Ссылки <b>x</b></summary>\n\nBody *text*\n\n</details>\n\n```\n<details>\n```\n\n</details>\n\n<details open><summary>Open</summary>Tail",
+        );
+
+        assert!(html.starts_with(
+            "<details>\n<summary>Ссылки &lt;b&gt;x&lt;/b&gt;</summary>\n<p>Body <em>text</em></p>\n</details>"
+        ));
+        assert!(html.contains("<pre><code>&lt;details&gt;\n</code></pre>"));
+        assert!(html.contains("\n&lt;/details&gt;\n"));
+        assert!(html.ends_with("<details open><summary>Open</summary>Tail</details>"));
+    }
+
+    #[test]
+    fn diff_lines_groups_added_and_removed_lines() {
+        let diff = diff_lines(
+            "# Title\nkept\nold line\nend\n",
+            "# Title\nkept\nnew line\nextra\nend\n",
+        );
+
+        assert_eq!((diff.added, diff.removed), (2, 1));
+        let segments: Vec<(DiffKind, &str)> = diff
+            .segments
+            .iter()
+            .map(|segment| (segment.kind, segment.text.as_str()))
+            .collect();
+        assert_eq!(
+            segments,
+            [
+                (DiffKind::Equal, "# Title\nkept\n"),
+                (DiffKind::Removed, "old line\n"),
+                (DiffKind::Added, "new line\nextra\n"),
+                (DiffKind::Equal, "end\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn image_modes_hide_or_collect_images() {
+        let (html, _) = process_markdown(
+            "Text ![Chart &](chart.png) more.\n\n![](deco.png)\n\n![Photo](photo.jpg)\n\n*Caption*",
+        );
+
+        let hidden = replace_images_with_alt(&html);
+        assert!(!hidden.contains("<img"));
+        assert!(hidden.contains("Text <span class=\"image-alt\">Chart &amp;</span> more."));
+        assert!(hidden
+            .contains("<span class=\"image-alt\">Photo</span><figcaption>Caption</figcaption>"));
+
+        let gallery = move_images_to_gallery(&html);
+        assert!(gallery.contains(
+            "Text <a class=\"image-ref\" id=\"image-ref-1\" href=\"#image-1\">[илл. 1]</a> more."
+        ));
+        let (text, appendix) = gallery
+            .split_once("<section class=\"image-gallery\">")
+            .unwrap();
+        assert!(!text.contains("<img"));
+        assert!(appendix.contains(
+            "<figure id=\"image-3\"><img src=\"photo.jpg\" alt=\"Photo\" /><figcaption><a href=\"#image-ref-3\">3.</a> Photo</figcaption></figure>"
+        ));
+
+        assert_eq!(
+            move_images_to_gallery("<p>No images</p>"),
+            "<p>No images</p>"
+        );
+    }
+
+    #[test]
+    fn find_replace_replaces_literal_text() {
+        assert_eq!(
+            find_replace("a.b a.b ab", "a.b", "x", false).unwrap(),
+            Replacement {
+                text: "x x ab".to_string(),
+                count: 2
+            }
+        );
+    }
+
+    #[test]
+    fn find_replace_supports_regex_capture_groups() {
+        let replaced = find_replace(
+            "2024-01-31 and 1999-12-01",
+            r"(\d{4})-(\d{2})-(\d{2})",
+            "$3.$2.$1",
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(replaced.text, "31.01.2024 and 01.12.1999");
+        assert_eq!(replaced.count, 2);
+    }
+
+    #[test]
+    fn find_replace_rejects_bad_patterns() {
+        assert!(find_replace("text", "", "x", false).is_err());
+        assert!(find_replace("text", "(unclosed", "x", true).is_err());
+        assert!(find_replace("text", &"a".repeat(MAX_FIND_PATTERN_CHARS + 1), "x", false).is_err());
+        assert!(find_replace("text", r"\w{1000}{1000}", "x", true).is_err());
+    }
+
+    #[test]
+    fn markdown_to_plain_text_keeps_structure() {
+        let markdown = "# Title\n\nFirst   *para*\ngraph.\n\n- one\n- two\n  1. nested\n\n3. three\n4. four\n\nTom &amp; \"Jerry\" <3";
+
+        assert_eq!(
+            markdown_to_plain_text(markdown),
+            "Title\n\nFirst para graph.\n\n• one\n• two\n  1. nested\n\n3. three\n4. four\n\nTom & \"Jerry\" <3"
+        );
+    }
+
+    #[test]
+    fn markdown_to_plain_text_preserves_code_whitespace() {
+        let markdown = "```\nfn main() {\n    x  =  1;\n}\n```\n\n- [x] done\n- [ ] todo";
+
+        assert_eq!(
+            markdown_to_plain_text(markdown),
+            "fn main() {\n    x  =  1;\n}\n\n• [x] done\n• [ ] todo"
+        );
+    }
+
+    #[test]
+    fn html_mode_sanitizes_instead_of_parsing_markdown() {
+        let text = "<h1>Заголовок</h1>\n<p>Текст с *звездочками*<script>alert(1)</script></p>";
+
+        let (html, toc) = render_content_with_toc(text, ContentMode::Html);
+        assert_eq!(
+            html,
+            "<h1 id=\"заголовок\">Заголовок</h1>\n<p>Текст с *звездочками*</p>"
+        );
+        assert_eq!(toc[0].id, "заголовок");
+        assert_eq!(render_content(text, ContentMode::Html).1, sha1_hex(&html));
+        assert_eq!(content_title(text, ContentMode::Html, false), "Заголовок");
+        assert_eq!(
+            content_title("<p>Первая строка</p><p>Вторая</p>", ContentMode::Html, true),
+            "Первая строка"
+        );
+
+        let (markdown_html, _) = render_content(text, ContentMode::Markdown);
+        assert!(markdown_html.contains("&lt;h1&gt;"));
+    }
+
+    #[test]
+    fn headings_get_stable_unique_ids() {
+        let markdown = "# Глава 1: Начало\n\nТекст.\n\n## *Итоги*\n\n## Итоги";
+        let (html, toc) = process_markdown_with_toc(markdown);
+
+        assert!(html.contains("<h1 id=\"глава-1-начало\">Глава 1: Начало</h1>"));
+        assert!(html.contains("<h2 id=\"итоги\"><em>Итоги</em></h2>"));
+        assert!(html.contains("<h2 id=\"итоги-1\">Итоги</h2>"));
+        assert_eq!(
+            toc.iter()
+                .map(|entry| (entry.level, entry.text.as_str(), entry.id.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "Глава 1: Начало", "глава-1-начало"),
+                (2, "Итоги", "итоги"),
+                (2, "Итоги", "итоги-1"),
+            ]
+        );
+        // The same heading gets the same id after unrelated edits.
+        assert_eq!(
+            process_markdown_with_toc("Вступление.\n\n# Глава 1: Начало").1[0].id,
+            "глава-1-начало"
+        );
+        assert_eq!(heading_id("?!"), "section");
+    }
+
+    #[test]
+    fn data_uris_are_not_counted_as_text() {
+        let image = format!("![Схема](data:image/png;base64,{})", "A".repeat(10_000));
+        assert_eq!(
+            count_chars_without_data_uris(&image),
+            "![Схема]()".chars().count()
+        );
+        let tag = format!(r#"<img src="data:image/gif;base64,{}">"#, "A".repeat(1_000));
+        assert_eq!(count_chars_without_data_uris(&tag), r#"<img src="">"#.len());
+        assert_eq!(count_chars_without_data_uris("Текст"), 5);
+
+        // Outside an image, `data:` is ordinary text.
+        for text in ["data:text/plain,abc", "see data:image/png,abc"] {
+            assert_eq!(count_chars_without_data_uris(text), text.len());
+        }
+    }
+
+    #[test]
+    fn is_append_detects_text_added_at_the_end() {
+        assert!(is_append("Первый абзац.", "Первый абзац.\n\nВторой абзац."));
+        assert!(is_append("Первый абзац.\n", "Первый абзац.\n\nВторой."));
+        assert!(!is_append("Первый абзац.", "Первый абзац."));
+        assert!(!is_append("Первый абзац.", "Новый текст."));
+        assert!(!is_append("Первый абзац.", "Перв"));
+        assert!(!is_append("", "Новый текст."));
+    }
+
+    #[test]
+    fn content_stats_counts_words_without_markup() {
+        let stats = content_stats("# Заголовок\n\nРаз **два** [три](https://example.com).");
+
+        assert_eq!(stats.words, 4);
+        assert_eq!(stats.chars, 52);
+        assert_eq!(stats.reading_minutes, 1);
+        assert_eq!(
+            stats.hash,
+            sha1_hex("# Заголовок\n\nРаз **два** [три](https://example.com).")
+        );
+        assert_eq!(content_stats("").reading_minutes, 0);
+        assert_eq!(content_stats(&"слово ".repeat(401)).reading_minutes, 3);
+    }
+
+    #[test]
+    fn estimate_pages_grows_with_font_scale() {
+        let long_text = "Lorem ipsum dolor sit amet. ".repeat(2000);
+        let estimates = estimate_pages(&long_text);
+
+        assert_eq!(estimates.len(), FONT_SCALES.len());
+        assert!(estimates
+            .windows(2)
+            .all(|pair| pair[0].estimated_pages < pair[1].estimated_pages));
+        assert!(estimate_pages("").iter().all(|e| e.estimated_pages == 1));
+    }
+
+    #[test]
+    fn extract_title_uses_first_h1() {
+        assert_eq!(
+            extract_title("intro\n## Sub\n# Main Title #\n# Second"),
+            Some("Main Title".to_string())
+        );
+        assert_eq!(extract_title("## Only a subheading"), None);
+        assert_eq!(extract_title("#hashtag"), None);
+    }
+
+    #[test]
+    fn derive_title_uses_first_line_of_text() {
+        assert_eq!(
+            derive_title("\n\n![cover](c.png)\n\n---\n**Глава** [первая](x)\nтекст"),
+            Some("Глава первая".to_string())
+        );
+        assert_eq!(
+            derive_title("> - Цитата из списка"),
+            Some("Цитата из списка".to_string())
+        );
+        assert_eq!(derive_title("  \n![](only.png)"), None);
+
+        let long = "Очень длинная первая строка, в которой слов гораздо больше, чем помещается в заголовок";
+        let title = derive_title(long).unwrap();
+        assert_eq!(
+            title,
+            "Очень длинная первая строка, в которой слов гораздо больше…"
+        );
+        assert!(title.chars().count() <= AUTO_TITLE_MAX_CHARS + 1);
+    }
+
+    #[test]
+    fn document_title_prefers_h1_over_derived_title() {
+        assert_eq!(document_title("intro\n# Main", true), "Main");
+        assert_eq!(document_title("intro\n## Sub", true), "intro");
+        assert_eq!(document_title("intro\n## Sub", false), DEFAULT_TITLE);
+        assert_eq!(document_title("", true), DEFAULT_TITLE);
+    }
+
+    #[test]
+    fn set_leading_title_becomes_the_h1() {
+        assert_eq!(
+            set_leading_title("Body text.", "New Title"),
+            "# New Title\n\nBody text."
+        );
+        assert_eq!(
+            set_leading_title("\n# Old Title\n\n## Part\ntext", " New\nTitle "),
+            "# New Title\n\n## Part\ntext"
+        );
+        assert_eq!(set_leading_title("# Old", "New"), "# New\n");
+        assert_eq!(set_leading_title("#tag\ntext", "T"), "# T\n\n#tag\ntext");
+        assert_eq!(set_leading_title("Body", "  "), "Body");
+        assert_eq!(
+            extract_title(&set_leading_title("## Sub", "Main")),
+            Some("Main".to_string())
+        );
+    }
+
+    #[test]
+    fn format_selection_quotes_every_line() {
+        assert_eq!(
+            format_selection(SelectionFormat::Quote, "first\n\nsecond\n"),
+            "> first\n\n> second\n"
+        );
+        assert_eq!(format_selection(SelectionFormat::Quote, "one"), "> one");
+    }
+
+    #[test]
+    fn format_selection_makes_one_heading() {
+        assert_eq!(
+            format_selection(SelectionFormat::H1, "Long\ntitle "),
+            "# Long title"
+        );
+        assert_eq!(format_selection(SelectionFormat::H1, "## Part"), "# Part");
+        assert_eq!(
+            format_selection(SelectionFormat::H2, "# Part\n"),
+            "## Part\n"
+        );
+        assert_eq!(
+            format_selection(SelectionFormat::H2, "#hashtag"),
+            "## #hashtag"
+        );
+    }
+
+    #[test]
+    fn format_selection_wraps_code() {
+        assert_eq!(
+            format_selection(SelectionFormat::Code, "ls -la"),
+            "`ls -la`"
+        );
+        assert_eq!(
+            format_selection(SelectionFormat::Code, "a `b` c"),
+            "``a `b` c``"
+        );
+        assert_eq!(format_selection(SelectionFormat::Code, "`x`"), "`` `x` ``");
+        assert_eq!(
+            format_selection(SelectionFormat::Code, "fn main() {}\n    run();\n"),
+            "```\nfn main() {}\n    run();\n```\n"
+        );
+        assert_eq!(
+            format_selection(SelectionFormat::Code, "```\ncode\n```"),
+            "````\n```\ncode\n```\n````"
+        );
+    }
+
+    #[test]
+    fn format_selection_bullets_every_line() {
+        assert_eq!(
+            format_selection(SelectionFormat::Bullet, "milk\n* eggs\n\n2. bread"),
+            "- milk\n- eggs\n\n- bread"
+        );
+    }
+
+    #[test]
+    fn minify_html_keeps_pre_and_code_byte_for_byte() {
+        let markdown = "# Title\n\nSome   *spaced*\ntext and `a  b`.\n\n```\nfn main() {\n    let  x = 1;\n\n}\n```\n\n- one\n- two\n";
+        let (html, _) = process_markdown(markdown);
+        let minified = minify_html(&html);
+
+        let block = "<pre><code>fn main() {\n    let  x = 1;\n\n}\n</code></pre>";
+        assert!(html.contains(block));
+        assert!(minified.contains(block));
+        assert!(minified.contains("<code>a  b</code>"));
+        assert!(minified.contains("<p>Some <em>spaced</em> text and <code>a  b</code>.</p>"));
+        assert!(minified.contains("</h1><p>"));
+        assert!(minified.contains("<ul><li>one</li><li>two</li></ul>"));
+        assert!(minified.len() < html.len());
+    }
+
+    #[test]
+    fn minify_html_keeps_spaces_between_inline_elements() {
+        assert_eq!(
+            minify_html("<p><em>a</em>\n<strong>b</strong> </p>\n"),
+            "<p><em>a</em> <strong>b</strong></p>"
+        );
+    }
+
+    #[test]
+    fn truncate_for_log_keeps_short_text_intact() {
+        assert_eq!(truncate_for_log("short", 10), "short");
+        assert_eq!(truncate_for_log("", 10), "");
+    }
+
+    #[test]
+    fn truncate_for_log_cuts_on_grapheme_boundary() {
+        assert_eq!(truncate_for_log("Привет, мир", 6), "Привет… (11 chars)");
+        // "e" + combining acute accent is a single grapheme and must stay whole.
+        assert_eq!(
+            truncate_for_log("e\u{301}e\u{301}e\u{301}", 2),
+            "e\u{301}e\u{301}… (6 chars)"
+        );
+        assert_eq!(truncate_for_log("👍🏽👍🏽", 1), "👍🏽… (4 chars)");
+    }
+
+    #[test]
+    fn truncate_for_log_flattens_newlines() {
+        assert_eq!(truncate_for_log("a\nb", 10), "a b");
+    }
+
+    #[test]
+    fn looks_like_markdown_detects_intended_markdown() {
+        assert!(looks_like_markdown("# Title\n\nSome text."));
+        assert!(looks_like_markdown("- first\n- second"));
+        assert!(looks_like_markdown("See [the docs](https://example.com)."));
+        assert!(looks_like_markdown("This is **important** to know."));
+        assert!(looks_like_markdown("Run `cargo build` first."));
+        assert!(looks_like_markdown("```\ncode\n```"));
+    }
+
+    #[test]
+    fn looks_like_markdown_ignores_stray_symbols() {
+        assert!(!looks_like_markdown("ls *.txt *.md | wc -l"));
+        assert!(!looks_like_markdown("rename my_file_name to other_name"));
+        assert!(!looks_like_markdown("2*3*4 = 24"));
+        assert!(!looks_like_markdown("Issue #42 was fixed."));
+        assert!(!looks_like_markdown("- just one dash line"));
+    }
+
+    #[test]
+    fn prefer_html_capture_keeps_formatting_of_the_same_text() {
+        assert!(prefer_html_capture(
+            "See the docs for details.",
+            "See [the docs](https://example.com) for details."
+        ));
+        assert!(prefer_html_capture(
+            "Title\nFirst point\nSecond point",
+            "# Title\n\n- First point\n- Second point"
+        ));
+    }
+
+    #[test]
+    fn prefer_html_capture_falls_back_on_empty_or_garbled_text() {
+        assert!(prefer_html_capture("", "Text from **HTML**"));
+        assert!(prefer_html_capture("Te\u{FFFD}t", "Text"));
+        assert!(!prefer_html_capture("Text", "  "));
+    }
+
+    #[test]
+    fn prefer_html_capture_keeps_plain_text_by_default() {
+        // Nothing gained from the HTML.
+        assert!(!prefer_html_capture(
+            "See the docs for details.",
+            "See the docs for details."
+        ));
+        // Different content, e.g. page chrome around the selection.
+        assert!(!prefer_html_capture(
+            "Just this sentence.",
+            "# Site\n\n- [Home](/)\n- [About](/about)\n\nJust this sentence. Share on social media."
+        ));
+        // The text already is Markdown, e.g. copied from a code editor.
+        assert!(!prefer_html_capture(
+            "# Title\n\nSome **bold** text.",
+            "# Title\n\nSome **bold** text."
+        ));
+    }
+
+    #[test]
+    fn escape_markdown_renders_plain_text_literally() {
+        let plain = "ls *.txt *.md\n# not a heading\n1. not a list\nsnake_case_name";
+        let (html, _) = process_markdown(&escape_markdown(plain));
+
+        assert!(!html.contains("<em>"));
+        assert!(!html.contains("<h1"));
+        assert!(!html.contains("<ol>"));
+        assert!(html.contains("ls *.txt *.md"));
+        assert!(html.contains("# not a heading"));
+        assert!(html.contains("1. not a list"));
+        assert!(html.contains("snake_case_name"));
+    }
+
+    #[test]
+    fn prepare_captured_text_respects_setting() {
+        assert_eq!(prepare_captured_text("a *b* c", false), "a *b* c");
+        assert_eq!(prepare_captured_text("# Title", true), "# Title");
+        assert_eq!(prepare_captured_text("ls *.txt", true), "ls \\*.txt");
+    }
 }
@@ -0,0 +1,330 @@
+use crate::core::decode_html_entities;
+use crate::url_processor::{parse_tag_attributes, sanitize_html};
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+use url::Url;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// The largest EPUB file, in bytes, that will be opened.
+pub const MAX_EPUB_BYTES: u64 = 50 * 1024 * 1024;
+
+/// The largest uncompressed size, in bytes, of a single file inside the archive.
+/// Guards against archives that expand to huge sizes.
+const MAX_ENTRY_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Encryption algorithms used only to obfuscate embedded fonts. Files that use
+/// nothing else are readable; any other algorithm means DRM.
+const FONT_OBFUSCATION_ALGORITHMS: [&str; 2] = [
+    "http://www.idpf.org/2008/embedding",
+    "http://ns.adobe.com/pdf/enc#RC",
+];
+
+/// Reads an EPUB file and converts its chapters, in reading order, into a single
+/// Markdown document. The book title becomes a level-one heading and chapters
+/// that do not start with a heading get one from their `<title>`.
+///
+/// # Arguments
+/// * `path` - The path of the `.epub` file.
+///
+/// # Returns
+/// A `Result` containing the Markdown text, or an error string if the file is too
+/// large, is not a valid EPUB, or is protected by DRM.
+pub fn import_epub(path: &Path) -> Result<String, String> {
+    let file = File::open(path).map_err(|e| format!("Не удалось открыть файл: {}", e))?;
+    let size = file
+        .metadata()
+        .map_err(|e| format!("Не удалось открыть файл: {}", e))?
+        .len();
+    if size > MAX_EPUB_BYTES {
+        return Err(format!(
+            "Файл слишком большой (максимум {} МБ).",
+            MAX_EPUB_BYTES / 1024 / 1024
+        ));
+    }
+
+    let mut archive = ZipArchive::new(file).map_err(malformed)?;
+
+    if archive.by_name("META-INF/rights.xml").is_ok() {
+        return Err(drm_error());
+    }
+    match read_entry(&mut archive, "META-INF/encryption.xml") {
+        Ok(encryption) if is_drm_protected(&encryption) => return Err(drm_error()),
+        Ok(_) | Err(ZipError::FileNotFound) => {}
+        Err(e) => return Err(malformed(e)),
+    }
+
+    let container = read_entry(&mut archive, "META-INF/container.xml").map_err(malformed)?;
+    let opf_path = rootfile_path(&container)
+        .ok_or_else(|| "Неверный EPUB: не найден файл описания книги.".to_string())?;
+    let opf = read_entry(&mut archive, &opf_path).map_err(malformed)?;
+    let package = parse_package(&opf, &opf_path);
+
+    let mut markdown = String::new();
+    if let Some(title) = &package.title {
+        markdown.push_str(&format!("# {}\n\n", title));
+    }
+    for chapter_path in &package.chapters {
+        let xhtml = read_entry(&mut archive, chapter_path).map_err(malformed)?;
+        let chapter = chapter_to_markdown(&xhtml);
+        if !chapter.is_empty() {
+            markdown.push_str(&chapter);
+            markdown.push_str("\n\n");
+        }
+    }
+
+    let markdown = markdown.trim().to_string();
+    if markdown.is_empty() {
+        return Err("В книге не найден текст.".to_string());
+    }
+    Ok(markdown)
+}
+
+fn malformed(e: ZipError) -> String {
+    format!("Файл поврежден или не является EPUB: {}", e)
+}
+
+fn drm_error() -> String {
+    "Книга защищена DRM и не может быть импортирована.".to_string()
+}
+
+/// Reads a text file from the archive, refusing entries larger than [`MAX_ENTRY_BYTES`].
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<String, ZipError> {
+    let entry = archive.by_name(name)?;
+    if entry.size() > MAX_ENTRY_BYTES {
+        return Err(ZipError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is too large", name),
+        )));
+    }
+
+    let mut bytes = Vec::new();
+    entry.take(MAX_ENTRY_BYTES).read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The parts of the OPF package document needed for the import.
+#[derive(Debug, PartialEq)]
+struct Package {
+    title: Option<String>,
+    /// Archive paths of the (X)HTML documents in reading order.
+    chapters: Vec<String>,
+}
+
+/// Finds the path of the OPF package document in `META-INF/container.xml`.
+fn rootfile_path(container: &str) -> Option<String> {
+    xml_tags(container, "rootfile")
+        .into_iter()
+        .find_map(|attributes| attribute(&attributes, "full-path").map(str::to_string))
+}
+
+/// Reads the title and the spine of an OPF package document. Chapter paths are
+/// resolved relative to `opf_path`; spine items that are not (X)HTML are skipped.
+fn parse_package(opf: &str, opf_path: &str) -> Package {
+    let title = xml_text(opf, "title")
+        .map(|title| decode_html_entities(title.trim()))
+        .filter(|title| !title.is_empty());
+
+    let manifest = xml_tags(opf, "item");
+    let chapters = xml_tags(opf, "itemref")
+        .iter()
+        .filter_map(|itemref| attribute(itemref, "idref"))
+        .filter_map(|idref| {
+            manifest
+                .iter()
+                .find(|item| attribute(item, "id") == Some(idref))
+        })
+        .filter(|item| {
+            matches!(
+                attribute(item, "media-type"),
+                Some("application/xhtml+xml" | "text/html")
+            )
+        })
+        .filter_map(|item| attribute(item, "href"))
+        .filter_map(|href| resolve_path(opf_path, href))
+        .collect();
+
+    Package { title, chapters }
+}
+
+/// Whether `META-INF/encryption.xml` encrypts anything beyond embedded fonts.
+fn is_drm_protected(encryption: &str) -> bool {
+    xml_tags(encryption, "EncryptionMethod")
+        .iter()
+        .filter_map(|method| attribute(method, "algorithm"))
+        .any(|algorithm| !FONT_OBFUSCATION_ALGORITHMS.contains(&algorithm))
+}
+
+/// Converts one chapter document to Markdown, adding a `##` heading from its
+/// `<title>` if the chapter does not start with a heading of its own.
+fn chapter_to_markdown(xhtml: &str) -> String {
+    let body = xml_element(xhtml, "body").unwrap_or(xhtml);
+    let markdown = html2md::parse_html(&sanitize_html(body)).trim().to_string();
+    if markdown.is_empty() || markdown.starts_with('#') || starts_with_setext_heading(&markdown) {
+        return markdown;
+    }
+
+    match xml_text(xhtml, "title").map(|title| decode_html_entities(title.trim())) {
+        Some(title) if !title.is_empty() => format!("## {}\n\n{}", title, markdown),
+        _ => markdown,
+    }
+}
+
+/// html2md writes `<h1>`/`<h2>` as underlined (setext) headings.
+fn starts_with_setext_heading(markdown: &str) -> bool {
+    markdown.lines().nth(1).is_some_and(|underline| {
+        !underline.is_empty() && underline.chars().all(|c| c == '=' || c == '-')
+    })
+}
+
+/// Resolves an `href` from the package document into an archive path.
+fn resolve_path(opf_path: &str, href: &str) -> Option<String> {
+    let base = Url::parse("epub:///").ok()?.join(opf_path).ok()?;
+    let resolved = base.join(href).ok()?;
+    Some(percent_decode(resolved.path().trim_start_matches('/')))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the attributes of every start tag with the given local name, ignoring
+/// namespace prefixes (`<opf:item>` matches `item`).
+fn xml_tags(xml: &str, name: &str) -> Vec<Vec<(String, String)>> {
+    let mut tags = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        let name_end = tag
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or(tag.len());
+        let local_name = tag[..name_end].rsplit(':').next().unwrap_or_default();
+        if local_name.eq_ignore_ascii_case(name) {
+            tags.push(parse_tag_attributes(tag[name_end..].trim_end_matches('/')));
+        }
+        rest = &rest[end..];
+    }
+
+    tags
+}
+
+/// Returns the raw content between the first `<name ...>` and its `</name>`.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        if rest[..name_end].rsplit(':').next() == Some(name) {
+            let content_start = rest.find('>')? + 1;
+            let content = &rest[content_start..];
+            let prefix = &rest[..name_end];
+            let end = content.find(&format!("</{}>", prefix))?;
+            return Some(&content[..end]);
+        }
+    }
+}
+
+/// The text content of the first element with the given local name.
+fn xml_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    xml_element(xml, name).filter(|text| !text.contains('<'))
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rootfile_path_reads_container() {
+        let container = r#"<?xml version="1.0"?>
+            <container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+              <rootfiles>
+                <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+              </rootfiles>
+            </container>"#;
+
+        assert_eq!(
+            rootfile_path(container).as_deref(),
+            Some("OEBPS/content.opf")
+        );
+    }
+
+    #[test]
+    fn parse_package_follows_spine_order() {
+        let opf = r#"<?xml version="1.0"?>
+            <opf:package xmlns:opf="http://www.idpf.org/2007/opf">
+              <opf:metadata><dc:title>Война &amp; мир</dc:title></opf:metadata>
+              <opf:manifest>
+                <opf:item id="c2" href="Text/part%202.xhtml" media-type="application/xhtml+xml"/>
+                <opf:item id="css" href="style.css" media-type="text/css"/>
+                <opf:item id="c1" href="../Text/one.xhtml" media-type="application/xhtml+xml"/>
+              </opf:manifest>
+              <opf:spine><opf:itemref idref="c1"/><opf:itemref idref="css"/><opf:itemref idref="c2"/></opf:spine>
+            </opf:package>"#;
+
+        assert_eq!(
+            parse_package(opf, "OEBPS/content.opf"),
+            Package {
+                title: Some("Война & мир".to_string()),
+                chapters: vec![
+                    "Text/one.xhtml".to_string(),
+                    "OEBPS/Text/part 2.xhtml".to_string()
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn is_drm_protected_allows_font_obfuscation_only() {
+        let fonts = r#"<encryption><EncryptedData>
+            <EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+            </EncryptedData></encryption>"#;
+        let drm = r#"<encryption><enc:EncryptedData>
+            <enc:EncryptionMethod Algorithm="http://www.w3.org/2001/04/xmlenc#aes128-cbc"/>
+            </enc:EncryptedData></encryption>"#;
+
+        assert!(!is_drm_protected(fonts));
+        assert!(is_drm_protected(drm));
+    }
+
+    #[test]
+    fn chapter_to_markdown_adds_heading_from_title() {
+        let untitled = "<html><head><title>Глава 1</title></head><body><p>Текст <script>x()</script>главы.</p></body></html>";
+        let titled = "<html><head><title>Глава 2</title></head><body><h2>Своя глава</h2><p>Текст.</p></body></html>";
+
+        assert_eq!(chapter_to_markdown(untitled), "## Глава 1\n\nТекст главы.");
+        assert!(chapter_to_markdown(titled).starts_with("Своя глава\n"));
+    }
+}
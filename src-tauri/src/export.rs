@@ -0,0 +1,304 @@
+use crate::core::{
+    content_title, escape_html, render_content, render_content_with_toc, sha1_hex, ContentMode,
+};
+use crate::server::CONTENT_CSS;
+use std::io::{Cursor, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Renders Markdown into a self-contained HTML document for offline reading.
+///
+/// Unlike the live `/get` page, the result has no script and no pagination: the
+/// content is laid out as a single scrollable column styled with the reader's
+/// typography rules. Images are kept as they appear in the content, so any that
+/// are already inlined as data URIs stay embedded in the file.
+///
+/// # Arguments
+/// * `markdown_text` - The Markdown source of the document.
+/// * `mode` - Whether the source is Markdown or HTML.
+/// * `auto_title` - Whether to derive the `<title>` from the first line of text
+///   when the document has no H1.
+///
+/// # Returns
+/// The complete HTML document as a string.
+pub fn render_standalone_html(markdown_text: &str, mode: ContentMode, auto_title: bool) -> String {
+    let (html_content, _) = render_content(markdown_text, mode);
+    let title = content_title(markdown_text, mode, auto_title);
+
+    // The title goes in last and only into the first placeholder (in `<head>`), so
+    // placeholder-like text in the title or the content stays untouched.
+    STANDALONE_TEMPLATE
+        .replace("{{ content_css }}", CONTENT_CSS)
+        .replace("{{ content }}", &html_content)
+        .replacen("{{ title }}", &escape_html(&title), 1)
+}
+
+const STANDALONE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="ru">
+<head>
+    <meta charset="UTF-8">
+    <title>{{ title }}</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <style>
+        body {
+            margin: 0;
+            padding: 0;
+            font-family: 'Georgia', serif;
+            color: #111;
+            background-color: #fdfdfd;
+        }
+
+        #content-container {
+            max-width: 40em;
+            margin: 0 auto;
+            padding: 25px;
+            font-size: 1.3em;
+            line-height: 1.6;
+            text-align: justify;
+        }
+        {{ content_css }}
+    </style>
+</head>
+<body>
+    <main id="content-container">
+{{ content }}
+    </main>
+</body>
+</html>
+"#;
+
+/// Packs Markdown into a single-chapter EPUB 3 book for reading offline.
+///
+/// The chapter is the rendered document styled with the reader's typography
+/// rules; the navigation lists its headings, or just the chapter if there are
+/// none. Pagination is left to the reading device.
+///
+/// # Arguments
+/// * `markdown_text` - The Markdown source of the document.
+/// * `mode` - Whether the source is Markdown or HTML.
+/// * `auto_title` - Whether to derive the book title from the first line of text
+///   when the document has no H1.
+///
+/// # Returns
+/// The bytes of the `.epub` file, or an error string if it could not be written.
+pub fn render_epub(
+    markdown_text: &str,
+    mode: ContentMode,
+    auto_title: bool,
+) -> Result<Vec<u8>, String> {
+    let (html_content, toc) = render_content_with_toc(markdown_text, mode);
+    let title = escape_html(&content_title(markdown_text, mode, auto_title));
+
+    let nav_items = if toc.is_empty() {
+        format!("<li><a href=\"chapter.xhtml\">{}</a></li>", title)
+    } else {
+        toc.iter()
+            .map(|entry| {
+                format!(
+                    "<li><a href=\"chapter.xhtml#{}\">{}</a></li>",
+                    entry.id,
+                    escape_html(&entry.text)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let package = EPUB_PACKAGE_TEMPLATE
+        .replace("{{ identifier }}", &sha1_hex(markdown_text))
+        .replace("{{ modified }}", &utc_timestamp(SystemTime::now()))
+        .replacen("{{ title }}", &title, 1);
+    let chapter = EPUB_CHAPTER_TEMPLATE
+        .replace("{{ content }}", &html_content)
+        .replacen("{{ title }}", &title, 1);
+    let nav = EPUB_NAV_TEMPLATE
+        .replace("{{ items }}", &nav_items)
+        .replacen("{{ title }}", &title, 1);
+
+    let write_error = |e: &dyn std::fmt::Display| format!("Не удалось создать EPUB: {}", e);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The mimetype must come first and uncompressed, so readers can identify the file.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let entries = [
+        ("mimetype", "application/epub+zip", stored),
+        ("META-INF/container.xml", EPUB_CONTAINER, deflated),
+        ("OEBPS/content.opf", package.as_str(), deflated),
+        ("OEBPS/nav.xhtml", nav.as_str(), deflated),
+        ("OEBPS/chapter.xhtml", chapter.as_str(), deflated),
+        ("OEBPS/style.css", CONTENT_CSS, deflated),
+    ];
+    for (name, contents, options) in entries {
+        zip.start_file(name, options).map_err(|e| write_error(&e))?;
+        zip.write_all(contents.as_bytes())
+            .map_err(|e| write_error(&e))?;
+    }
+    zip.finish()
+        .map(Cursor::into_inner)
+        .map_err(|e| write_error(&e))
+}
+
+/// Suggests a file name for saving the document: its title with the characters
+/// that file systems reject replaced, plus `extension`.
+///
+/// # Arguments
+/// * `title` - The document title, e.g. from [`content_title`].
+/// * `extension` - The extension without the dot, e.g. `md`.
+pub fn export_file_name(title: &str, extension: &str) -> String {
+    let stem: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows also rejects names ending in a dot or space.
+    let stem = stem.trim().trim_end_matches('.');
+    let stem = if stem.is_empty() { "document" } else { stem };
+    format!("{}.{}", stem, extension)
+}
+
+/// Formats `time` as an ISO 8601 UTC timestamp with second precision, as EPUB
+/// requires for the modification date.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Converts days since 1970-01-01 to a civil date (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3_600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+const EPUB_CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+    <rootfiles>
+        <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+    </rootfiles>
+</container>
+"#;
+
+const EPUB_PACKAGE_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="ru">
+    <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+        <dc:identifier id="book-id">urn:sha1:{{ identifier }}</dc:identifier>
+        <dc:title>{{ title }}</dc:title>
+        <dc:language>ru</dc:language>
+        <meta property="dcterms:modified">{{ modified }}</meta>
+    </metadata>
+    <manifest>
+        <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+        <item id="chapter" href="chapter.xhtml" media-type="application/xhtml+xml"/>
+        <item id="style" href="style.css" media-type="text/css"/>
+    </manifest>
+    <spine>
+        <itemref idref="chapter"/>
+    </spine>
+</package>
+"#;
+
+const EPUB_NAV_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="ru" lang="ru">
+<head>
+    <title>{{ title }}</title>
+</head>
+<body>
+    <nav epub:type="toc">
+        <h1>Оглавление</h1>
+        <ol>
+{{ items }}
+        </ol>
+    </nav>
+</body>
+</html>
+"#;
+
+const EPUB_CHAPTER_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="ru" lang="ru">
+<head>
+    <title>{{ title }}</title>
+    <link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+    <div id="content-container">
+{{ content }}
+    </div>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standalone_html_has_title_and_no_script() {
+        let html = render_standalone_html(
+            "# Tom & Jerry\n\nHello, *world*.",
+            ContentMode::Markdown,
+            true,
+        );
+
+        assert!(html.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(html.contains("<em>world</em>"));
+        assert!(html.contains("#content-container blockquote"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn standalone_html_derives_title_only_when_enabled() {
+        let markdown = "Заметки о {{ content }}\n\nТекст.";
+
+        let html = render_standalone_html(markdown, ContentMode::Markdown, true);
+        assert!(html.contains("<title>Заметки о {{ content }}</title>"));
+        assert_eq!(html.matches("<h1>").count(), 0);
+
+        let html = render_standalone_html(markdown, ContentMode::Markdown, false);
+        assert!(html.contains("<title>Текст для чтения</title>"));
+    }
+
+    #[test]
+    fn export_file_name_replaces_reserved_characters() {
+        assert_eq!(
+            export_file_name("Итоги: 2024/25?", "md"),
+            "Итоги_ 2024_25_.md"
+        );
+        assert_eq!(export_file_name(" Конец... ", "md"), "Конец.md");
+        assert_eq!(export_file_name("", "md"), "document.md");
+    }
+
+    #[test]
+    fn utc_timestamp_formats_civil_dates() {
+        let at = |seconds| UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        assert_eq!(utc_timestamp(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(at(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(at(1_790_000_000)), "2026-09-21T14:13:20Z");
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+
+/// How many earlier versions of the shared text are kept for undo.
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+/// Earlier and undone versions of the shared text, so a replacement (e.g. by a
+/// clipboard capture with `send_on_copy`) can be taken back.
+///
+/// Like the library, the history does not hold the current text: undo and redo
+/// swap it with the shared text passed in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    /// Earlier versions, oldest first; at most `limit` of them.
+    undo: VecDeque<String>,
+    /// Undone versions, the most recently undone last.
+    redo: Vec<String>,
+    limit: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LIMIT)
+    }
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: VecDeque::with_capacity(limit),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Records the text that was just replaced. The oldest version is dropped once
+    /// the limit is reached, and the redo versions are forgotten, as they no longer
+    /// follow from the current text.
+    pub fn record(&mut self, previous: String) {
+        if self.undo.len() == self.limit {
+            self.undo.pop_front();
+        }
+        if self.limit > 0 {
+            self.undo.push_back(previous);
+        }
+        self.redo.clear();
+    }
+
+    /// Replaces `current` with the previous version, keeping it for redo.
+    ///
+    /// # Returns
+    /// `false` if there is nothing to undo.
+    pub fn undo(&mut self, current: &mut String) -> bool {
+        let Some(previous) = self.undo.pop_back() else {
+            return false;
+        };
+        self.redo.push(std::mem::replace(current, previous));
+        true
+    }
+
+    /// Replaces `current` with the version it replaced by the last undo.
+    ///
+    /// # Returns
+    /// `false` if there is nothing to redo.
+    pub fn redo(&mut self, current: &mut String) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push_back(std::mem::replace(current, next));
+        true
+    }
+
+    /// Forgets all versions, e.g. when the shared text switches to another document.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_and_redo_move_through_recorded_versions() {
+        let mut history = History::default();
+        let mut text = "первый".to_string();
+        for next in ["второй", "третий"] {
+            history.record(std::mem::replace(&mut text, next.to_string()));
+        }
+
+        assert!(history.undo(&mut text));
+        assert_eq!(text, "второй");
+        assert!(history.undo(&mut text));
+        assert_eq!(text, "первый");
+        assert!(!history.undo(&mut text));
+
+        assert!(history.redo(&mut text));
+        assert_eq!(text, "второй");
+
+        // A new change drops the versions that could still be redone.
+        history.record(std::mem::replace(&mut text, "четвертый".to_string()));
+        assert!(!history.redo(&mut text));
+        assert!(history.undo(&mut text));
+        assert_eq!(text, "второй");
+    }
+
+    #[test]
+    fn history_keeps_only_the_latest_versions() {
+        let mut history = History::new(2);
+        let mut text = "0".to_string();
+        for next in 1..=4 {
+            history.record(std::mem::replace(&mut text, next.to_string()));
+        }
+
+        assert!(history.undo(&mut text));
+        assert!(history.undo(&mut text));
+        assert_eq!(text, "2");
+        assert!(!history.undo(&mut text));
+    }
+}
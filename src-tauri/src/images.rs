@@ -0,0 +1,520 @@
+use crate::core::escape_html;
+use crate::url_processor::{http_client, parse_tag_attributes};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, RgbaImage};
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use url::Url;
+
+/// The largest image, in bytes, that will be downloaded.
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Images copied to the clipboard are scaled down to this width, since screenshots
+/// are much wider than e-reader screens.
+pub const CLIPBOARD_IMAGE_MAX_WIDTH: u32 = 1200;
+
+/// Images of fetched articles are scaled down to this width when embedded.
+pub const ARTICLE_IMAGE_MAX_WIDTH: u32 = 1200;
+
+/// The maximum number of article images downloaded at the same time.
+pub const MAX_CONCURRENT_IMAGE_DOWNLOADS: usize = 4;
+
+/// The time limit for downloading all images of an article.
+pub const IMAGE_EMBED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The total size, in bytes, of the data URIs kept by [`fetch_image_data_uri`] so
+/// that sending the same article again does not download its images again.
+pub const IMAGE_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Downloads an image and returns it as a `data:` URI for embedding in Markdown.
+///
+/// Only PNG, JPEG, GIF and WebP images are accepted; the format is checked from
+/// the file contents, not just the `Content-Type` header. SVG is rejected because
+/// it can carry scripts. Recently embedded images are served from a cache of up
+/// to [`IMAGE_CACHE_BYTES`].
+///
+/// # Arguments
+/// * `url_str` - The address of the image.
+/// * `max_width` - If set, images wider than this are scaled down to fit, keeping
+///   the aspect ratio, and re-encoded.
+///
+/// # Returns
+/// A `Result` containing the data URI on success, or an error string on failure.
+pub async fn fetch_image_data_uri(url_str: &str, max_width: Option<u32>) -> Result<String, String> {
+    let url = Url::parse(url_str.trim()).map_err(|e| format!("Неверный URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Неподдерживаемая схема URL: {}", url.scheme()));
+    }
+
+    let key = (url.to_string(), max_width);
+    if let Some(data_uri) = image_cache().lock().ok().and_then(|cache| cache.get(&key)) {
+        return Ok(data_uri);
+    }
+
+    let mut response = http_client()?
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("Ошибка загрузки изображения: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Ошибка загрузки изображения: сервер ответил со статусом {}",
+            response.status()
+        ));
+    }
+
+    if response
+        .content_length()
+        .is_some_and(|length| length as usize > MAX_IMAGE_BYTES)
+    {
+        return Err(too_large_error());
+    }
+
+    // Content-Length may be missing or wrong, so the limit is also enforced while reading.
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Ошибка чтения изображения: {}", e))?
+    {
+        if bytes.len() + chunk.len() > MAX_IMAGE_BYTES {
+            return Err(too_large_error());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let data_uri = encode_data_uri(&bytes, max_width)?;
+    if let Ok(mut cache) = image_cache().lock() {
+        cache.insert(key, data_uri.clone());
+    }
+    Ok(data_uri)
+}
+
+/// A downloaded image is identified by its address and the width it was scaled to.
+type ImageKey = (String, Option<u32>);
+
+/// The data URIs of recently downloaded images, oldest first, limited to `capacity`
+/// bytes in total.
+struct ImageCache {
+    entries: VecDeque<(ImageKey, String)>,
+    size: usize,
+    capacity: usize,
+}
+
+impl ImageCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    fn get(&self, key: &ImageKey) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|(cached, _)| cached == key)
+            .map(|(_, data_uri)| data_uri.clone())
+    }
+
+    /// Adds an image, evicting the oldest ones to stay within the capacity. An
+    /// image larger than the whole cache is not kept.
+    fn insert(&mut self, key: ImageKey, data_uri: String) {
+        if data_uri.len() > self.capacity || self.get(&key).is_some() {
+            return;
+        }
+        self.size += data_uri.len();
+        self.entries.push_back((key, data_uri));
+        while self.size > self.capacity {
+            if let Some((_, evicted)) = self.entries.pop_front() {
+                self.size -= evicted.len();
+            }
+        }
+    }
+}
+
+fn image_cache() -> &'static Mutex<ImageCache> {
+    static CACHE: OnceLock<Mutex<ImageCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(ImageCache::new(IMAGE_CACHE_BYTES)))
+}
+
+/// Downloads the images of an article and embeds them into its HTML as `data:`
+/// URIs, so the reader shows them without reaching the original servers.
+///
+/// Images are fetched with [`fetch_image_data_uri`], at most
+/// [`MAX_CONCURRENT_IMAGE_DOWNLOADS`] at a time, and scaled down to
+/// [`ARTICLE_IMAGE_MAX_WIDTH`]. An image that fails to download, is larger than
+/// [`MAX_IMAGE_BYTES`] or is still pending after `timeout` keeps its original `src`.
+///
+/// # Arguments
+/// * `html` - The sanitized article HTML.
+/// * `page_url` - The address of the article, for resolving relative sources.
+/// * `timeout` - The time limit for all downloads.
+///
+/// # Returns
+/// The HTML with every downloaded image embedded.
+pub async fn embed_article_images(html: &str, page_url: &Url, timeout: Duration) -> String {
+    let sources = image_sources(html);
+    if sources.is_empty() {
+        return html.to_string();
+    }
+
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_IMAGE_DOWNLOADS));
+    let mut downloads = JoinSet::new();
+    for source in sources {
+        let Ok(url) = page_url.join(&source) else {
+            continue;
+        };
+        let permits = permits.clone();
+        downloads.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let result = fetch_image_data_uri(url.as_str(), Some(ARTICLE_IMAGE_MAX_WIDTH)).await;
+            (source, url, result)
+        });
+    }
+
+    let mut embedded: HashMap<String, String> = HashMap::new();
+    let _ = tokio::time::timeout(timeout, async {
+        while let Some(Ok((source, url, result))) = downloads.join_next().await {
+            match result {
+                Ok(data_uri) => {
+                    embedded.insert(source, data_uri);
+                }
+                Err(e) => log::warn!("Keeping remote image {}: {}", url, e),
+            }
+        }
+    })
+    .await;
+    if !downloads.is_empty() {
+        log::warn!(
+            "{} article image(s) did not download in time and stay remote.",
+            downloads.len()
+        );
+    }
+    downloads.abort_all();
+
+    replace_image_sources(html, &embedded)
+}
+
+/// Collects the unique `src` values of the `<img>` tags of HTML, entity-decoded and
+/// in document order. Images that are already `data:` URIs are skipped.
+fn image_sources(html: &str) -> Vec<String> {
+    let mut sources: Vec<String> = Vec::new();
+    for tag in image_tags(html) {
+        let source = tag
+            .attributes
+            .into_iter()
+            .find(|(name, _)| name == "src")
+            .map(|(_, value)| value);
+        if let Some(source) = source.filter(|source| !source.is_empty()) {
+            if !source.starts_with("data:") && !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+    }
+    sources
+}
+
+/// Rewrites the `<img>` tags whose decoded `src` is a key of `replacements` to
+/// point at its value instead. Other attributes are kept.
+fn replace_image_sources(html: &str, replacements: &HashMap<String, String>) -> String {
+    let mut rewritten = String::with_capacity(html.len());
+    let mut copied = 0;
+    for tag in image_tags(html) {
+        let Some(new_source) = tag
+            .attributes
+            .iter()
+            .find(|(name, _)| name == "src")
+            .and_then(|(_, value)| replacements.get(value))
+        else {
+            continue;
+        };
+
+        rewritten.push_str(&html[copied..tag.start]);
+        rewritten.push_str("<img");
+        for (name, value) in &tag.attributes {
+            let value = if name == "src" { new_source } else { value };
+            rewritten.push_str(&format!(" {}=\"{}\"", name, escape_html(value)));
+        }
+        rewritten.push('>');
+        copied = tag.end;
+    }
+    rewritten.push_str(&html[copied..]);
+    rewritten
+}
+
+/// An `<img>` tag found by [`image_tags`].
+struct ImageTag {
+    /// The byte offset of the `<`.
+    start: usize,
+    /// The attributes, as parsed by [`parse_tag_attributes`].
+    attributes: Vec<(String, String)>,
+    /// The byte offset just past the closing `>`.
+    end: usize,
+}
+
+/// Finds the `<img>` tags of HTML, in document order.
+fn image_tags(html: &str) -> Vec<ImageTag> {
+    let mut tags = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = html[offset..].find("<img") {
+        let start = offset + found;
+        let after_name = start + "<img".len();
+        let Some(close) = html[after_name..].find('>') else {
+            break;
+        };
+        let end = after_name + close + 1;
+        if html[after_name..].starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/') {
+            let inside = html[after_name..end - 1].trim_end_matches('/');
+            tags.push(ImageTag {
+                start,
+                attributes: parse_tag_attributes(inside),
+                end,
+            });
+        }
+        offset = end;
+    }
+    tags
+}
+
+/// Validates image bytes, optionally downsizes them, and encodes them as a data URI.
+fn encode_data_uri(bytes: &[u8], max_width: Option<u32>) -> Result<String, String> {
+    let format = image::guess_format(bytes)
+        .ok()
+        .filter(|format| {
+            matches!(
+                format,
+                ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+            )
+        })
+        .ok_or_else(|| "Неподдерживаемый формат изображения.".to_string())?;
+
+    let Some(max_width) = max_width else {
+        return Ok(format_data_uri(format, bytes));
+    };
+
+    let decoded = image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| format!("Ошибка декодирования изображения: {}", e))?;
+    if decoded.width() <= max_width {
+        return Ok(format_data_uri(format, bytes));
+    }
+
+    let height = (u64::from(decoded.height()) * u64::from(max_width) / u64::from(decoded.width()))
+        .max(1) as u32;
+    let resized = decoded.resize(max_width, height, FilterType::Triangle);
+
+    // Photos stay JPEG; everything else becomes PNG, which every reader supports.
+    let output_format = if format == ImageFormat::Jpeg {
+        ImageFormat::Jpeg
+    } else {
+        ImageFormat::Png
+    };
+    let mut encoded = Cursor::new(Vec::new());
+    resized
+        .write_to(&mut encoded, output_format)
+        .map_err(|e| format!("Ошибка сжатия изображения: {}", e))?;
+
+    Ok(format_data_uri(output_format, encoded.get_ref()))
+}
+
+/// Encodes raw RGBA pixels, as read from the clipboard, as a PNG `data:` URI.
+///
+/// # Arguments
+/// * `width`, `height` - The image size in pixels.
+/// * `rgba` - Four bytes per pixel, row by row.
+/// * `max_width` - Wider images are scaled down to this width, keeping the aspect ratio.
+pub fn rgba_to_data_uri(
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    max_width: u32,
+) -> Result<String, String> {
+    let image = RgbaImage::from_raw(width, height, rgba)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "Некорректное изображение в буфере обмена.".to_string())?;
+    let image = if image.width() > max_width {
+        let height = (u64::from(image.height()) * u64::from(max_width) / u64::from(image.width()))
+            .max(1) as u32;
+        image.resize(max_width, height, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    let mut encoded = Cursor::new(Vec::new());
+    image
+        .write_to(&mut encoded, ImageFormat::Png)
+        .map_err(|e| format!("Ошибка сжатия изображения: {}", e))?;
+    Ok(format_data_uri(ImageFormat::Png, encoded.get_ref()))
+}
+
+fn format_data_uri(format: ImageFormat, bytes: &[u8]) -> String {
+    format!(
+        "data:{};base64,{}",
+        format.to_mime_type(),
+        STANDARD.encode(bytes)
+    )
+}
+
+fn too_large_error() -> String {
+    format!(
+        "Изображение слишком большое (максимум {} МБ).",
+        MAX_IMAGE_BYTES / (1024 * 1024)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut bytes, ImageFormat::Png)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    fn decode_data_uri(uri: &str) -> DynamicImage {
+        let encoded = uri.strip_prefix("data:image/png;base64,").unwrap();
+        image::load_from_memory(&STANDARD.decode(encoded).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn encode_data_uri_keeps_small_images_unchanged() {
+        let bytes = png_bytes(10, 5);
+        let uri = encode_data_uri(&bytes, Some(100)).unwrap();
+
+        assert_eq!(
+            uri,
+            format!("data:image/png;base64,{}", STANDARD.encode(&bytes))
+        );
+    }
+
+    #[test]
+    fn encode_data_uri_downsizes_wide_images() {
+        let uri = encode_data_uri(&png_bytes(400, 200), Some(100)).unwrap();
+        let resized = decode_data_uri(&uri);
+
+        assert_eq!((resized.width(), resized.height()), (100, 50));
+    }
+
+    #[test]
+    fn encode_data_uri_rejects_non_images() {
+        assert!(encode_data_uri(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>", None).is_err());
+        assert!(encode_data_uri(b"plain text", None).is_err());
+    }
+
+    #[test]
+    fn replace_image_sources_rewrites_only_known_sources() {
+        let html = concat!(
+            "<p><img src=\"/a.png?x=1&amp;y=2\" alt=\"Схема\"></p>",
+            "<img alt=\"b\" src=\"https://example.com/b.png\"/>",
+            "<img src=\"data:image/png;base64,AAAA\"><img src=\"/a.png?x=1&amp;y=2\">"
+        );
+        assert_eq!(
+            image_sources(html),
+            ["/a.png?x=1&y=2", "https://example.com/b.png"]
+        );
+
+        let replacements = HashMap::from([(
+            "/a.png?x=1&y=2".to_string(),
+            "data:image/png;base64,QQ==".to_string(),
+        )]);
+        assert_eq!(
+            replace_image_sources(html, &replacements),
+            concat!(
+                "<p><img src=\"data:image/png;base64,QQ==\" alt=\"Схема\"></p>",
+                "<img alt=\"b\" src=\"https://example.com/b.png\"/>",
+                "<img src=\"data:image/png;base64,AAAA\"><img src=\"data:image/png;base64,QQ==\">"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn embed_article_images_keeps_images_that_fail_to_download() {
+        use axum::{routing::get, Router};
+        use tokio::net::TcpListener;
+
+        let png = png_bytes(4, 2);
+        let app = Router::new().route("/images/ok.png", get(move || async move { png }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = Url::parse(&format!(
+            "http://{}/articles/",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let html = "<img src=\"../images/ok.png\"><img src=\"../images/missing.png\">";
+        let embedded = embed_article_images(html, &base, IMAGE_EMBED_TIMEOUT).await;
+
+        let expected = format!(
+            "<img src=\"{}\"><img src=\"../images/missing.png\">",
+            encode_data_uri(&png_bytes(4, 2), None).unwrap()
+        );
+        assert_eq!(embedded, expected);
+    }
+
+    #[tokio::test]
+    async fn fetch_image_data_uri_stops_reading_oversized_images_without_a_length() {
+        use axum::{
+            body::{Body, Bytes},
+            routing::get,
+            Router,
+        };
+        use tokio::net::TcpListener;
+
+        // Streamed in chunks, so the response has no Content-Length.
+        let app = Router::new().route(
+            "/huge.png",
+            get(|| async {
+                let chunk = Bytes::from(vec![0u8; 1024 * 1024]);
+                let chunks = std::iter::repeat_n(chunk, MAX_IMAGE_BYTES / (1024 * 1024) + 2)
+                    .map(Ok::<_, std::io::Error>);
+                Body::from_stream(futures_util::stream::iter(chunks))
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let result = fetch_image_data_uri(&format!("http://{}/huge.png", addr), None).await;
+
+        assert_eq!(result, Err(too_large_error()));
+    }
+
+    #[test]
+    fn image_cache_evicts_the_oldest_images_beyond_its_capacity() {
+        let key = |name: &str| (format!("https://example.com/{}", name), None);
+        let mut cache = ImageCache::new(10);
+        cache.insert(key("a"), "aaaa".to_string());
+        cache.insert(key("b"), "bbbb".to_string());
+        cache.insert(key("c"), "cccc".to_string());
+        cache.insert(key("huge"), "x".repeat(11));
+
+        assert_eq!(cache.get(&key("a")), None);
+        assert_eq!(cache.get(&key("b")).as_deref(), Some("bbbb"));
+        assert_eq!(cache.get(&key("c")).as_deref(), Some("cccc"));
+        assert_eq!(cache.get(&key("huge")), None);
+    }
+
+    #[test]
+    fn rgba_to_data_uri_encodes_and_downsizes_clipboard_pixels() {
+        let uri = rgba_to_data_uri(2, 1, vec![255, 0, 0, 255, 0, 0, 255, 255], 100).unwrap();
+        let image = decode_data_uri(&uri).to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 255, 255]);
+
+        let uri = rgba_to_data_uri(400, 200, vec![0; 400 * 200 * 4], 100).unwrap();
+        let resized = decode_data_uri(&uri);
+        assert_eq!((resized.width(), resized.height()), (100, 50));
+
+        assert!(rgba_to_data_uri(2, 2, vec![0; 3], 100).is_err());
+    }
+}
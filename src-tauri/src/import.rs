@@ -0,0 +1,108 @@
+use crate::url_processor::html_fragment_to_markdown;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The largest text file, in bytes, that will be opened.
+pub const MAX_IMPORT_BYTES: u64 = 10 * 1024 * 1024;
+
+/// The extensions offered in the open dialog, without the dot.
+pub const IMPORT_EXTENSIONS: [&str; 5] = ["md", "markdown", "txt", "html", "htm"];
+
+/// How many leading bytes are checked for NUL bytes, which text files never contain.
+const BINARY_SNIFF_BYTES: usize = 8192;
+
+/// Reads a local Markdown, plain text or HTML file as Markdown. HTML is sanitized
+/// and converted; Markdown and plain text are taken as they are.
+///
+/// # Arguments
+/// * `path` - The file to read; its extension decides how it is converted.
+///
+/// # Returns
+/// The Markdown text, or an error string if the file is too large, has an
+/// unsupported extension, or is not UTF-8 text.
+pub fn import_text_file(path: &Path) -> Result<String, String> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    if !IMPORT_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(unsupported_error());
+    }
+
+    let file = File::open(path).map_err(|e| format!("Не удалось открыть файл: {}", e))?;
+    let mut bytes = Vec::new();
+    // One byte past the limit is enough to tell that the file is too large.
+    file.take(MAX_IMPORT_BYTES + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Не удалось прочитать файл: {}", e))?;
+    if bytes.len() as u64 > MAX_IMPORT_BYTES {
+        return Err(format!(
+            "Файл слишком большой (максимум {} МБ).",
+            MAX_IMPORT_BYTES / 1024 / 1024
+        ));
+    }
+
+    let text = decode_text(&bytes)?;
+    Ok(match extension.as_str() {
+        "html" | "htm" => html_fragment_to_markdown(text),
+        _ => text.to_string(),
+    })
+}
+
+/// Interprets `bytes` as UTF-8 text, without a byte order mark.
+fn decode_text(bytes: &[u8]) -> Result<&str, String> {
+    let sniffed = &bytes[..bytes.len().min(BINARY_SNIFF_BYTES)];
+    if sniffed.contains(&0) {
+        return Err(unsupported_error());
+    }
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    std::str::from_utf8(bytes)
+        .map_err(|_| "Файл не в кодировке UTF-8. Пересохраните его в UTF-8.".to_string())
+}
+
+fn unsupported_error() -> String {
+    "Файл не похож на текст. Поддерживаются файлы .md, .txt и .html.".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ki-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn imports_text_as_is_and_converts_html() {
+        let markdown = temp_file("note.md", "\u{feff}# Заметка\n\n*Текст*".as_bytes());
+        assert_eq!(import_text_file(&markdown).unwrap(), "# Заметка\n\n*Текст*");
+
+        let html = temp_file(
+            "page.HTML",
+            b"<h2>Title</h2><p>Some <b>bold</b> text.</p><script>alert(1)</script>",
+        );
+        let converted = import_text_file(&html).unwrap();
+        assert!(converted.contains("**bold**"));
+        assert!(!converted.contains("alert"));
+    }
+
+    #[test]
+    fn rejects_binary_and_unsupported_files() {
+        let binary = temp_file("image.txt", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+        assert!(import_text_file(&binary)
+            .unwrap_err()
+            .contains("не похож на текст"));
+
+        let pdf = temp_file("book.pdf", b"%PDF-1.7");
+        assert!(import_text_file(&pdf).is_err());
+
+        let latin1 = temp_file("old.txt", b"caf\xe9");
+        assert!(import_text_file(&latin1).unwrap_err().contains("UTF-8"));
+    }
+}
@@ -1,22 +1,128 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use crate::state::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tauri::Manager;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, RunEvent};
 use tauri_plugin_log::{Target, TargetKind, TimezoneStrategy};
+use tokio::sync::broadcast::error::RecvError;
 
 mod clipboard;
 mod commands;
 mod core;
+mod epub;
+mod export;
+mod history;
+mod images;
+mod import;
+mod library;
+mod links;
 mod network;
+mod peers;
+mod rate_limit;
 mod server;
 mod state;
 mod url_processor;
+mod watch;
+
+/// How often the idle shutdown timer is checked.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Exits the application once the web server has been idle for longer than the
+/// configured `idle_shutdown_minutes`. Does nothing while the setting is 0.
+async fn watch_idle_shutdown(state: Arc<AppState>, app_handle: AppHandle) {
+    loop {
+        tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+
+        let minutes = state.settings().idle_shutdown_minutes;
+        if minutes == 0 {
+            continue;
+        }
+        let idle = state.idle_duration();
+        if idle >= Duration::from_secs(minutes.saturating_mul(60)) {
+            log::info!(
+                "Shutting down: no web server requests for {} minute(s), idle shutdown is set to {} minute(s).",
+                idle.as_secs() / 60,
+                minutes
+            );
+            app_handle.exit(0);
+            return;
+        }
+    }
+}
+
+/// Forwards every change of the shared text to the desktop UI as a
+/// `content-changed` event carrying the new source hash.
+async fn forward_content_changes(state: Arc<AppState>, app_handle: AppHandle) {
+    let mut updates = state.content_updates.subscribe();
+    loop {
+        match updates.recv().await {
+            Ok(hash) => {
+                if let Err(e) = app_handle.emit("content-changed", hash) {
+                    log::error!("Failed to emit content-changed event: {}", e);
+                }
+            }
+            // Missed changes are followed by newer ones; only the latest matters.
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Forwards every page the reader reports to the desktop UI as a
+/// `reading-progress` event carrying the progress.
+async fn forward_reading_progress(state: Arc<AppState>, app_handle: AppHandle) {
+    let mut updates = state.progress_updates.subscribe();
+    loop {
+        match updates.recv().await {
+            Ok(progress) => {
+                if let Err(e) = app_handle.emit("reading-progress", progress) {
+                    log::error!("Failed to emit reading-progress event: {}", e);
+                }
+            }
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// The file in the app data directory holding the shared text and settings.
+const STATE_FILE_NAME: &str = "state.json";
+
+/// How often changed state is saved. Changes in between are written together, so
+/// bursts of clipboard captures do not rewrite the file each time.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(2);
+
+fn state_file_path(app_handle: &AppHandle) -> Option<PathBuf> {
+    match app_handle.path().app_data_dir() {
+        Ok(dir) => Some(dir.join(STATE_FILE_NAME)),
+        Err(e) => {
+            log::warn!("No app data directory, state will not be saved: {}", e);
+            None
+        }
+    }
+}
+
+fn persist_state(state: &AppState, path: &Path) {
+    if let Err(e) = state.persist(path) {
+        log::error!("{}", e);
+    }
+}
+
+/// Saves the state to `path` whenever it has changed, at most every [`PERSIST_INTERVAL`].
+async fn persist_changes(state: Arc<AppState>, path: PathBuf) {
+    loop {
+        tokio::time::sleep(PERSIST_INTERVAL).await;
+        if state.unsaved_changes.load(Ordering::SeqCst) {
+            persist_state(&state, &path);
+        }
+    }
+}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    let app_state = Arc::new(AppState::default());
-
     let log_plugin = tauri_plugin_log::Builder::new()
         .targets([
             Target::new(TargetKind::Stdout),
@@ -29,32 +135,134 @@ pub fn run() {
         .build();
 
     tauri::Builder::default()
-        .manage(app_state)
         .plugin(log_plugin)
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
         .setup(|app| {
-            let managed_state = app.state::<Arc<AppState>>().inner().clone();
             let app_handle = app.handle().clone();
 
+            // Restore the previous session; created here because the app data
+            // directory is only known once the app is running.
+            let state_file = state_file_path(&app_handle);
+            let managed_state = Arc::new(match &state_file {
+                Some(path) => AppState::load_or_default(path),
+                None => AppState::default(),
+            });
+            app.manage(managed_state.clone());
+            if let Some(path) = state_file {
+                tauri::async_runtime::spawn(persist_changes(managed_state.clone(), path));
+            }
+
             // Spawn the web server in a background async task.
             let server_state = managed_state.clone();
             tauri::async_runtime::spawn(async move {
-                server::run_server(server_state).await;
+                let handle = server::run_server(server_state.clone()).await;
+                *server_state.server.lock().await = handle;
             });
 
+            // Keep watching the page that was watched when the app was closed.
+            if !managed_state.safe_mode {
+                watch::resume(&managed_state);
+            }
+
+            tauri::async_runtime::spawn(forward_content_changes(
+                managed_state.clone(),
+                app_handle.clone(),
+            ));
+            tauri::async_runtime::spawn(forward_reading_progress(
+                managed_state.clone(),
+                app_handle.clone(),
+            ));
+
+            // Exit automatically once no readers have used the server for a while, if enabled.
+            let idle_state = managed_state.clone();
+            let idle_handle = app_handle.clone();
+            tauri::async_runtime::spawn(watch_idle_shutdown(idle_state, idle_handle));
+
             // Spawn the clipboard monitor in a dedicated background thread.
-            let clipboard_state = managed_state;
-            clipboard::spawn_monitor(clipboard_state, app_handle);
+            if managed_state.safe_mode {
+                log::warn!(
+                    "Safe mode is active: clipboard monitoring and network fetching are disabled."
+                );
+            } else {
+                let clipboard_state = managed_state;
+                clipboard::spawn_monitor(clipboard_state, app_handle);
+            }
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             commands::get_text,
+            commands::get_plain_text,
+            commands::sanitize_current,
+            commands::clear_text,
+            commands::find_replace,
+            commands::diff_against_live,
+            commands::format_selection,
+            commands::estimate_page_counts,
+            commands::get_content_stats,
             commands::set_text,
+            commands::set_structured,
+            commands::get_text_hash,
+            commands::toggle_scratch,
+            commands::is_scratch_active,
+            commands::undo,
+            commands::redo,
+            commands::list_documents,
+            commands::new_document,
+            commands::switch_document,
+            commands::delete_document,
             commands::get_server_info,
+            commands::get_server_port,
+            commands::restart_server,
+            commands::get_safe_mode,
+            commands::get_reading_stats,
+            commands::get_reading_progress,
+            commands::get_all_settings,
+            commands::export_html,
+            commands::export_markdown,
+            commands::import_file,
+            commands::import_epub,
+            commands::fetch_url,
+            commands::fetch_image,
+            commands::fetch_cover_image,
+            commands::push_to_peer,
+            commands::discover_peers,
+            commands::check_links,
+            commands::watch_url,
+            commands::stop_watching,
+            commands::get_watched_page,
             commands::set_send_on_copy,
-            commands::set_add_to_editor_on_copy
+            commands::set_add_to_editor_on_copy,
+            commands::set_escape_plain_text_on_copy,
+            commands::set_clear_clipboard_after_capture,
+            commands::set_log_preview_chars,
+            commands::set_clipboard_interval,
+            commands::set_max_text_chars,
+            commands::set_content_mode,
+            commands::set_sanitize_preset,
+            commands::set_idle_shutdown_minutes
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let RunEvent::Exit = event {
+                // Withdraw the mDNS announcement, so other instances stop listing this one.
+                if let Some(state) = app_handle.try_state::<Arc<AppState>>() {
+                    if let Ok(mut server) = state.server.try_lock() {
+                        if let Some(handle) = server.as_mut() {
+                            handle.stop_advertising();
+                        }
+                    }
+                }
+                // Save changes made since the last periodic save, including on idle
+                // shutdown. Saved even without changes, for the total time in the app.
+                if let (Some(state), Some(path)) = (
+                    app_handle.try_state::<Arc<AppState>>(),
+                    state_file_path(app_handle),
+                ) {
+                    persist_state(&state, &path);
+                }
+            }
+        });
 }
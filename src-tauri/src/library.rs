@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+
+/// One saved document of the library.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    /// Identifier, unique within the library and kept across launches.
+    pub id: u64,
+    pub name: String,
+    /// The Markdown text. Empty for the active document, whose text lives in
+    /// `AppState::shared_text` while it is active.
+    #[serde(default)]
+    pub text: String,
+}
+
+/// A document as listed to the UI, without its text.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DocumentInfo {
+    pub id: u64,
+    pub name: String,
+    /// Whether this is the document shown on the reader.
+    pub active: bool,
+}
+
+/// The saved documents and which one is active. Always holds at least one document.
+///
+/// Like the scratch buffer, the active document is not stored here but in the
+/// shared text, so every writer of the shared text edits the active document
+/// without knowing about the library. Switching swaps the texts.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Library {
+    documents: Vec<Document>,
+    active: usize,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self {
+            documents: vec![Document {
+                id: 1,
+                name: default_name(1),
+                text: String::new(),
+            }],
+            active: 0,
+        }
+    }
+}
+
+impl Library {
+    /// Builds a library from saved documents, or the default one if they are
+    /// empty or `active` is out of range.
+    pub fn from_saved(documents: Vec<Document>, active: usize) -> Self {
+        if active < documents.len() {
+            Self { documents, active }
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+
+    /// The index of the active document in [`Library::documents`].
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// Lists the documents in order, marking the active one.
+    pub fn list(&self) -> Vec<DocumentInfo> {
+        self.documents
+            .iter()
+            .enumerate()
+            .map(|(index, document)| DocumentInfo {
+                id: document.id,
+                name: document.name.clone(),
+                active: index == self.active,
+            })
+            .collect()
+    }
+
+    /// Adds an empty document at the end and makes it active, storing the text
+    /// of the previously active document from `shared_text`.
+    ///
+    /// # Arguments
+    /// * `name` - The document name; blank names get a numbered default.
+    /// * `shared_text` - The shared text, which is left empty for the new document.
+    ///
+    /// # Returns
+    /// The id of the new document.
+    pub fn create(&mut self, name: &str, shared_text: &mut String) -> u64 {
+        let id = self.documents.iter().map(|d| d.id).max().unwrap_or(0) + 1;
+        let name = match name.trim() {
+            "" => default_name(self.documents.len() + 1),
+            name => name.to_string(),
+        };
+        self.documents.push(Document {
+            id,
+            name,
+            text: String::new(),
+        });
+        self.activate(self.documents.len() - 1, shared_text);
+        id
+    }
+
+    /// Makes the document `id` active, swapping its text into `shared_text`.
+    ///
+    /// # Returns
+    /// `true` if the active document changed, or an error if there is no such document.
+    pub fn switch(&mut self, id: u64, shared_text: &mut String) -> Result<bool, String> {
+        let index = self.index_of(id)?;
+        if index == self.active {
+            return Ok(false);
+        }
+        self.activate(index, shared_text);
+        Ok(true)
+    }
+
+    /// Deletes the document `id`. Deleting the active document activates its
+    /// neighbour and moves that one's text into `shared_text`.
+    ///
+    /// # Returns
+    /// `true` if the active document changed, or an error if there is no such
+    /// document or it is the last one.
+    pub fn delete(&mut self, id: u64, shared_text: &mut String) -> Result<bool, String> {
+        let index = self.index_of(id)?;
+        if self.documents.len() == 1 {
+            return Err("Нельзя удалить единственный документ.".to_string());
+        }
+        self.documents.remove(index);
+        if index < self.active {
+            self.active -= 1;
+            Ok(false)
+        } else if index == self.active {
+            self.active = index.min(self.documents.len() - 1);
+            *shared_text = std::mem::take(&mut self.documents[self.active].text);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn index_of(&self, id: u64) -> Result<usize, String> {
+        self.documents
+            .iter()
+            .position(|document| document.id == id)
+            .ok_or_else(|| format!("Документ {} не найден.", id))
+    }
+
+    fn activate(&mut self, index: usize, shared_text: &mut String) {
+        self.documents[self.active].text = std::mem::take(shared_text);
+        *shared_text = std::mem::take(&mut self.documents[index].text);
+        self.active = index;
+    }
+}
+
+fn default_name(number: usize) -> String {
+    format!("Документ {}", number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(library: &Library) -> Vec<(String, bool)> {
+        library
+            .list()
+            .into_iter()
+            .map(|info| (info.name, info.active))
+            .collect()
+    }
+
+    fn active_id(library: &Library) -> u64 {
+        library.documents()[library.active_index()].id
+    }
+
+    #[test]
+    fn switching_swaps_the_active_text_with_the_shared_text() {
+        let mut library = Library::default();
+        let mut shared = "Первая статья".to_string();
+
+        let second = library.create("Вторая", &mut shared);
+        assert_eq!(shared, "");
+        shared.push_str("Вторая статья");
+
+        assert!(library.switch(1, &mut shared).unwrap());
+        assert_eq!(shared, "Первая статья");
+        assert_eq!(
+            names(&library),
+            vec![
+                ("Документ 1".to_string(), true),
+                ("Вторая".to_string(), false)
+            ]
+        );
+
+        assert!(!library.switch(1, &mut shared).unwrap());
+        assert!(library.switch(second, &mut shared).unwrap());
+        assert_eq!(shared, "Вторая статья");
+        assert!(library.switch(42, &mut shared).is_err());
+    }
+
+    #[test]
+    fn deleting_the_active_document_activates_its_neighbour() {
+        let mut library = Library::default();
+        let mut shared = "один".to_string();
+        let second = library.create("", &mut shared);
+        shared = "два".to_string();
+        let third = library.create("", &mut shared);
+        shared = "три".to_string();
+        assert_eq!(library.list()[2].name, "Документ 3");
+
+        // The last document is active: its predecessor takes over.
+        assert!(library.delete(third, &mut shared).unwrap());
+        assert_eq!(shared, "два");
+        assert_eq!(active_id(&library), second);
+
+        // Deleting an earlier document keeps the active one.
+        assert!(!library.delete(1, &mut shared).unwrap());
+        assert_eq!(active_id(&library), second);
+        assert_eq!(shared, "два");
+
+        assert!(library.delete(second, &mut shared).is_err());
+    }
+
+    #[test]
+    fn saved_library_with_invalid_active_index_falls_back_to_default() {
+        let documents = vec![Document {
+            id: 7,
+            name: "Сохраненный".to_string(),
+            text: String::new(),
+        }];
+        assert_eq!(active_id(&Library::from_saved(documents.clone(), 0)), 7);
+        assert_eq!(Library::from_saved(documents, 3), Library::default());
+        assert_eq!(Library::from_saved(Vec::new(), 0), Library::default());
+    }
+}
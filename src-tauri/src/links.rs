@@ -0,0 +1,191 @@
+use crate::core::decode_html_entities;
+use crate::url_processor::http_client;
+use reqwest::header::RANGE;
+use reqwest::{Client, Method, StatusCode};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use url::Url;
+
+/// The maximum number of links checked at the same time.
+pub const MAX_CONCURRENT_LINK_CHECKS: usize = 8;
+
+/// The time limit for checking all links of a document.
+pub const LINK_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The result of checking a single link.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LinkStatus {
+    pub url: String,
+    /// The final HTTP status, if the server answered.
+    pub status: Option<u16>,
+    pub ok: bool,
+    /// Why the link could not be checked, e.g. a connection error or timeout.
+    pub error: Option<String>,
+}
+
+/// The outcome of checking all links of a document.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LinkReport {
+    pub checked: usize,
+    pub broken: usize,
+    pub links: Vec<LinkStatus>,
+}
+
+/// Collects the unique `http`/`https` link targets of rendered HTML, in document order.
+/// Relative links and other schemes (`mailto:`, anchors) are skipped.
+pub fn extract_links(html: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+
+    for (index, matched) in html.match_indices("<a href=\"") {
+        let rest = &html[index + matched.len()..];
+        let Some(end) = rest.find('"') else {
+            continue;
+        };
+        let href = decode_html_entities(&rest[..end]);
+        let Ok(url) = Url::parse(&href) else {
+            continue;
+        };
+        if matches!(url.scheme(), "http" | "https") && !links.contains(&href) {
+            links.push(href);
+        }
+    }
+
+    links
+}
+
+/// Checks every link with a `HEAD` request, at most [`MAX_CONCURRENT_LINK_CHECKS`]
+/// at a time. Servers that do not support `HEAD` are retried with a `GET` for the
+/// first byte. Links still pending after `timeout` are reported as not checked.
+///
+/// # Arguments
+/// * `urls` - The links to check.
+/// * `timeout` - The time limit for the whole check.
+///
+/// # Returns
+/// A report with one entry per link, in the order of `urls`.
+pub async fn check_links(urls: Vec<String>, timeout: Duration) -> Result<LinkReport, String> {
+    let client = http_client()?;
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_LINK_CHECKS));
+    let mut checks = JoinSet::new();
+
+    for (index, url) in urls.iter().cloned().enumerate() {
+        let client = client.clone();
+        let permits = permits.clone();
+        checks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            (index, check_link(&client, url).await)
+        });
+    }
+
+    let mut results: Vec<Option<LinkStatus>> = vec![None; urls.len()];
+    let _ = tokio::time::timeout(timeout, async {
+        while let Some(Ok((index, status))) = checks.join_next().await {
+            results[index] = Some(status);
+        }
+    })
+    .await;
+    checks.abort_all();
+
+    let links: Vec<LinkStatus> = results
+        .into_iter()
+        .zip(urls)
+        .map(|(status, url)| {
+            status.unwrap_or(LinkStatus {
+                url,
+                status: None,
+                ok: false,
+                error: Some("Проверка не завершилась вовремя.".to_string()),
+            })
+        })
+        .collect();
+
+    Ok(LinkReport {
+        checked: links.len(),
+        broken: links.iter().filter(|link| !link.ok).count(),
+        links,
+    })
+}
+
+async fn check_link(client: &Client, url: String) -> LinkStatus {
+    let mut response = client.request(Method::HEAD, &url).send().await;
+
+    if let Ok(head) = &response {
+        if matches!(
+            head.status(),
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        ) {
+            response = client.get(&url).header(RANGE, "bytes=0-0").send().await;
+        }
+    }
+
+    match response {
+        Ok(response) => LinkStatus {
+            status: Some(response.status().as_u16()),
+            ok: response.status().is_success(),
+            error: None,
+            url,
+        },
+        Err(e) => LinkStatus {
+            url,
+            status: None,
+            ok: false,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::Method as AxumMethod, routing::any, Router};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn extract_links_keeps_unique_web_links_in_order() {
+        let html = concat!(
+            "<p><a href=\"https://example.com/a?x=1&amp;y=2\">A</a> ",
+            "<a href=\"mailto:me@example.com\">mail</a> <a href=\"#top\">top</a> ",
+            "<a href=\"http://example.org/\">B</a> ",
+            "<a href=\"https://example.com/a?x=1&amp;y=2\">again</a></p>"
+        );
+
+        assert_eq!(
+            extract_links(html),
+            ["https://example.com/a?x=1&y=2", "http://example.org/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn check_links_retries_with_get_when_head_is_not_allowed() {
+        let app = Router::new()
+            .route(
+                "/no-head",
+                any(|method: AxumMethod| async move {
+                    if method == AxumMethod::HEAD {
+                        StatusCode::METHOD_NOT_ALLOWED
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .route("/ok", any(|| async { StatusCode::OK }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let urls = vec![
+            format!("{}/ok", base),
+            format!("{}/no-head", base),
+            format!("{}/missing", base),
+        ];
+        let report = check_links(urls, LINK_CHECK_TIMEOUT).await.unwrap();
+
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.broken, 1);
+        let statuses: Vec<Option<u16>> = report.links.iter().map(|link| link.status).collect();
+        assert_eq!(statuses, [Some(200), Some(200), Some(404)]);
+    }
+}
@@ -1,9 +1,82 @@
-use local_ip_address::local_ip;
+use local_ip_address::{list_afinet_netifas, local_ip};
+use std::net::{IpAddr, Ipv4Addr};
 
-/// Attempts to discover the local, non-loopback IP address of the machine.
+/// Lists every IPv4 address the reader might reach this machine on, for
+/// machines with several interfaces (VPN, Docker, Wi-Fi and Ethernet).
 ///
 /// # Returns
-/// An `Option<String>` containing the IP address if found, otherwise `None`.
-pub fn get_local_ip_address() -> Option<String> {
-    local_ip().ok().map(|ip| ip.to_string())
+/// The non-loopback, non-link-local addresses without duplicates. Private-range
+/// addresses come first, led by the one the system routes outgoing traffic through.
+pub fn list_local_ip_addresses() -> Vec<String> {
+    let addresses = list_afinet_netifas()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter_map(|(_, ip)| match ip {
+                    IpAddr::V4(ip) => Some(ip),
+                    IpAddr::V6(_) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let primary = match local_ip() {
+        Ok(IpAddr::V4(ip)) => Some(ip),
+        _ => None,
+    };
+    order_candidates(addresses, primary)
+        .into_iter()
+        .map(|ip| ip.to_string())
+        .collect()
+}
+
+/// Drops unusable addresses and duplicates, then orders private ones first. The
+/// order is otherwise stable, except that `primary` leads its group.
+fn order_candidates(addresses: Vec<Ipv4Addr>, primary: Option<Ipv4Addr>) -> Vec<Ipv4Addr> {
+    let mut candidates: Vec<Ipv4Addr> = Vec::new();
+    for ip in addresses {
+        if !ip.is_loopback()
+            && !ip.is_link_local()
+            && !ip.is_unspecified()
+            && !candidates.contains(&ip)
+        {
+            candidates.push(ip);
+        }
+    }
+    candidates.sort_by_key(|ip| (!ip.is_private(), Some(*ip) != primary));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidates_put_private_addresses_first() {
+        let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+        let addresses = vec![
+            ip("127.0.0.1"),
+            ip("100.64.3.7"),
+            ip("172.17.0.1"),
+            ip("169.254.10.2"),
+            ip("192.168.1.5"),
+            ip("10.8.0.2"),
+            ip("172.32.0.1"),
+            ip("192.168.1.5"),
+        ];
+
+        assert_eq!(
+            order_candidates(addresses.clone(), None),
+            vec![
+                ip("172.17.0.1"),
+                ip("192.168.1.5"),
+                ip("10.8.0.2"),
+                ip("100.64.3.7"),
+                ip("172.32.0.1"),
+            ]
+        );
+        assert_eq!(
+            order_candidates(addresses, Some(ip("192.168.1.5")))[..2],
+            [ip("192.168.1.5"), ip("172.17.0.1")]
+        );
+    }
 }
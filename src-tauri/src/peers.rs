@@ -0,0 +1,284 @@
+use crate::core::sha1_hex;
+use crate::url_processor::http_client;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The DNS-SD service type browsed for other instances.
+pub const MDNS_SERVICE_TYPE: &str = "_http._tcp.local.";
+
+/// Instance names of this application start with this prefix, e.g.
+/// `kindle-reader-laptop._http._tcp.local.`.
+pub const MDNS_INSTANCE_PREFIX: &str = "kindle-reader";
+
+/// How long [`discover_peers`] listens for answers.
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Another instance found on the local network.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerInfo {
+    /// The mDNS instance name, e.g. `kindle-reader-laptop`.
+    pub name: String,
+    pub hostname: String,
+    pub addresses: Vec<String>,
+    pub port: u16,
+}
+
+// Body of the peer's POST /api/content endpoint.
+#[derive(Serialize)]
+struct SetTextPayload<'a> {
+    new_text: &'a str,
+}
+
+/// The header in which the peer's `POST /api/content` reports the hash of its new text.
+const CONTENT_HASH_HEADER: &str = "x-content-hash";
+
+/// Replaces the content of another running instance with `text`.
+///
+/// The push only counts as successful if the peer reports, in the response to the
+/// upload, the same source hash as this instance computes for `text`. Nothing is
+/// read back, so this also works with a peer that requires an access token for
+/// reading.
+///
+/// # Arguments
+/// * `host` - Host name or IP address of the peer.
+/// * `port` - Port of the peer's web server.
+/// * `text` - The Markdown text to send.
+///
+/// # Returns
+/// A `Result` containing the source hash confirmed by the peer, or an error string.
+pub async fn push_to_peer(host: &str, port: u16, text: &str) -> Result<String, String> {
+    let url = peer_url(host, port, "/api/content")?;
+    let client = http_client()?;
+    let unreachable = |e: reqwest::Error| format!("Не удалось связаться с {}: {}", url, e);
+
+    let response = client
+        .post(url.clone())
+        .json(&SetTextPayload { new_text: text })
+        .send()
+        .await
+        .map_err(unreachable)?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Другой экземпляр отклонил текст: статус {}",
+            response.status()
+        ));
+    }
+
+    let remote_hash = response
+        .headers()
+        .get(CONTENT_HASH_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| format!("Неожиданный ответ от {}: нет хэша текста", url))?
+        .to_string();
+    if remote_hash != sha1_hex(text) {
+        return Err(
+            "Текст отправлен, но содержимое на другом экземпляре не совпадает.".to_string(),
+        );
+    }
+
+    Ok(remote_hash)
+}
+
+/// This instance's announcement on the local network, which [`discover_peers`] on
+/// other machines finds. Dropping it leaves the announcement up until the process
+/// exits; use [`Advertisement::stop`] to withdraw it.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl std::fmt::Debug for Advertisement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Advertisement")
+            .field("fullname", &self.fullname)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Advertisement {
+    /// Withdraws the announcement, so other instances stop listing this one.
+    pub fn stop(self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            log::warn!("Failed to withdraw mDNS announcement: {}", e);
+        }
+        if let Err(e) = self.daemon.shutdown() {
+            log::warn!("Failed to shut down the mDNS daemon: {}", e);
+        }
+        log::info!("Withdrew mDNS announcement {}", self.fullname);
+    }
+}
+
+/// Announces this instance's web server on `port` to the local network, under an
+/// instance name made of [`MDNS_INSTANCE_PREFIX`], this machine's address and the
+/// port, so that several instances never share a name.
+///
+/// # Returns
+/// The announcement, to withdraw when the server stops, or an error string if
+/// mDNS is unavailable.
+pub fn advertise(port: u16) -> Result<Advertisement, String> {
+    let unavailable = |e: mdns_sd::Error| format!("Не удалось объявить сервер по mDNS: {}", e);
+    let address = local_ip_address::local_ip()
+        .map(|ip| ip.to_string().replace(['.', ':'], "-"))
+        .unwrap_or_else(|_| "localhost".to_string());
+    let instance = format!("{}-{}-{}", MDNS_INSTANCE_PREFIX, address, port);
+    let host_name = format!("{}.local.", instance);
+
+    let daemon = ServiceDaemon::new().map_err(unavailable)?;
+    let info = ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        &instance,
+        &host_name,
+        "",
+        port,
+        &[("path", "/get")][..],
+    )
+    .map_err(unavailable)?
+    .enable_addr_auto();
+    let fullname = info.get_fullname().to_string();
+    if let Err(e) = daemon.register(info) {
+        let _ = daemon.shutdown();
+        return Err(unavailable(e));
+    }
+    log::info!("Announced the server on the local network as {}", fullname);
+    Ok(Advertisement { daemon, fullname })
+}
+
+/// Browses the local network for other instances of the application.
+///
+/// This blocks for `timeout`, so async callers should run it on a blocking thread.
+///
+/// # Arguments
+/// * `timeout` - How long to collect answers.
+/// * `local_port` - The port of this instance's server; services on one of this
+///   machine's addresses with this port are this instance and are left out.
+///
+/// # Returns
+/// The peers found, sorted by name, or an error string if mDNS is unavailable.
+pub fn discover_peers(timeout: Duration, local_port: u16) -> Result<Vec<PeerInfo>, String> {
+    let daemon =
+        ServiceDaemon::new().map_err(|e| format!("Не удалось запустить поиск mDNS: {}", e))?;
+    let receiver = daemon
+        .browse(MDNS_SERVICE_TYPE)
+        .map_err(|e| format!("Не удалось запустить поиск mDNS: {}", e))?;
+
+    let local_addresses = local_addresses();
+    let deadline = Instant::now() + timeout;
+    let mut peers: Vec<PeerInfo> = Vec::new();
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = receiver.recv_timeout(remaining) else {
+            break;
+        };
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        let Some(name) = instance_name(info.get_fullname()) else {
+            continue;
+        };
+
+        let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+        addresses.sort();
+        if info.get_port() == local_port && addresses.iter().any(|a| local_addresses.contains(a)) {
+            continue;
+        }
+
+        let peer = PeerInfo {
+            name: name.to_string(),
+            hostname: info.get_hostname().trim_end_matches('.').to_string(),
+            addresses: addresses.iter().map(IpAddr::to_string).collect(),
+            port: info.get_port(),
+        };
+        if !peers.contains(&peer) {
+            peers.push(peer);
+        }
+    }
+
+    if let Err(e) = daemon.shutdown() {
+        log::warn!("Failed to shut down the mDNS daemon: {}", e);
+    }
+
+    peers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(peers)
+}
+
+/// Returns the instance part of a full service name if it belongs to this
+/// application, e.g. `kindle-reader-laptop` for `kindle-reader-laptop._http._tcp.local.`.
+fn instance_name(fullname: &str) -> Option<&str> {
+    fullname
+        .strip_suffix(MDNS_SERVICE_TYPE)
+        .map(|name| name.trim_end_matches('.'))
+        .filter(|name| name.starts_with(MDNS_INSTANCE_PREFIX))
+}
+
+/// All addresses of this machine's network interfaces.
+fn local_addresses() -> Vec<IpAddr> {
+    local_ip_address::list_afinet_netifas()
+        .map(|interfaces| interfaces.into_iter().map(|(_, ip)| ip).collect())
+        .unwrap_or_default()
+}
+
+/// Builds an `http://` URL on a peer, bracketing bare IPv6 addresses.
+fn peer_url(host: &str, port: u16, path: &str) -> Result<Url, String> {
+    let host = host.trim();
+    let host = if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    };
+
+    let url = Url::parse(&format!("http://{}:{}{}", host, port, path))
+        .map_err(|e| format!("Неверный адрес: {}", e))?;
+    if url.host_str().is_none_or(str::is_empty) || url.path() != path {
+        return Err(format!("Неверный адрес: {}", host));
+    }
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_name_keeps_only_this_applications_services() {
+        assert_eq!(
+            instance_name("kindle-reader-laptop._http._tcp.local."),
+            Some("kindle-reader-laptop")
+        );
+        assert_eq!(
+            instance_name("kindle-reader._http._tcp.local."),
+            Some("kindle-reader")
+        );
+        assert_eq!(instance_name("printer._http._tcp.local."), None);
+        assert_eq!(instance_name("kindle-reader._ipp._tcp.local."), None);
+    }
+
+    #[test]
+    fn peer_url_accepts_host_names_and_ip_addresses() {
+        assert_eq!(
+            peer_url("laptop.local", 5001, "/api/content")
+                .unwrap()
+                .as_str(),
+            "http://laptop.local:5001/api/content"
+        );
+        assert_eq!(
+            peer_url(" 192.168.1.5 ", 5001, "/api/content")
+                .unwrap()
+                .as_str(),
+            "http://192.168.1.5:5001/api/content"
+        );
+        assert_eq!(
+            peer_url("fe80::1", 8080, "/api/content").unwrap().as_str(),
+            "http://[fe80::1]:8080/api/content"
+        );
+    }
+
+    #[test]
+    fn peer_url_rejects_invalid_hosts() {
+        assert!(peer_url("", 5001, "/api/content").is_err());
+        assert!(peer_url("bad host", 5001, "/api/content").is_err());
+        assert!(peer_url("evil.com/x?", 5001, "/api/content").is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many pages may be fetched from URLs per [`FETCH_RATE_WINDOW`] by default.
+pub const DEFAULT_FETCHES_PER_MINUTE: u32 = 10;
+
+/// The window the fetch limit refers to.
+pub const FETCH_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// A token bucket: up to `capacity` calls at once, refilled evenly so that at most
+/// `capacity` calls are allowed per `window` in the long run.
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: u32,
+    window: Duration,
+    /// The tokens left and when they were last counted.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// A limiter that starts full. A `capacity` of 0 allows nothing.
+    pub fn new(capacity: u32, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            bucket: Mutex::new((f64::from(capacity), Instant::now())),
+        }
+    }
+
+    /// The number of calls allowed per window.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Takes a token if one is left.
+    ///
+    /// # Returns
+    /// `false` if the limit is exhausted; the call should be rejected.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        // A poisoned lock only means another caller panicked; the count is still usable.
+        let mut bucket = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (tokens, counted_at) = &mut *bucket;
+        let refill = now.saturating_duration_since(*counted_at).as_secs_f64()
+            / self.window.as_secs_f64()
+            * f64::from(self.capacity);
+        *tokens = (*tokens + refill).min(f64::from(self.capacity));
+        *counted_at = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_over_the_limit_is_rejected_until_tokens_refill() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at(start));
+        }
+        assert!(!limiter.try_acquire_at(start + Duration::from_secs(10)));
+
+        // One token comes back every 20 seconds.
+        assert!(limiter.try_acquire_at(start + Duration::from_secs(21)));
+        assert!(!limiter.try_acquire_at(start + Duration::from_secs(22)));
+    }
+
+    #[test]
+    fn zero_capacity_allows_nothing() {
+        assert!(!RateLimiter::new(0, FETCH_RATE_WINDOW).try_acquire());
+    }
+}
@@ -1,79 +1,447 @@
-use crate::{core::process_markdown, state::AppState, url_processor};
+use crate::{
+    core::{
+        content_stats, content_title, count_words, escape_html, minify_html,
+        move_images_to_gallery, render_content_with_toc, replace_images_with_alt, sha1_hex,
+        DEFAULT_TITLE, FONT_SCALES,
+    },
+    export::{render_epub, render_standalone_html},
+    peers::{advertise, Advertisement},
+    state::{
+        AppState, ImageMode, ReaderSettings, ReadingProgress, Settings, SettingsReport,
+        WELCOME_TEXT,
+    },
+    url_processor,
+};
 use axum::{
-    extract::State,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, Request, State,
+    },
     http::{
-        header::{CACHE_CONTROL, CONTENT_TYPE, EXPIRES, PRAGMA},
-        HeaderMap,
-        Method,
-        StatusCode,
+        header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, EXPIRES, PRAGMA},
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
     },
-    response::{Html, IntoResponse, Json},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Json, Response},
     routing::{get, post},
-    Router,
+    Form, Router,
 };
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tower::limit::GlobalConcurrencyLimitLayer;
 use tower_http::cors::{Any, CorsLayer};
 
-/// The port on which the web server will listen.
-pub const SERVER_PORT: u16 = 5001;
+/// How many consecutive ports are tried, starting at the configured one, before
+/// the server gives up.
+const PORT_ATTEMPTS: u16 = 10;
+
+/// How long [`ServerHandle::stop`] waits for open requests before aborting the server.
+const SERVER_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Response header carrying the hash of the raw Markdown source.
+const CONTENT_HASH_HEADER: &str = "x-content-hash";
+
+/// How often a connected WebSocket reader is pinged, keeping the connection (and the
+/// app, see idle shutdown) alive while nothing changes.
+const WS_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Response for the GET /status endpoint.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct StatusResponse {
+    safe_mode: bool,
+    clipboard_monitoring: bool,
+    network_fetching: bool,
+}
+
+// Response for the GET /health endpoint.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct HealthResponse {
+    status: String,
+    port: u16,
+}
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct ContentResponse {
     html: String,
     hash: String,
+    // The page title; follows the auto_title setting like the /get page.
+    #[serde(default)]
+    title: String,
+    // Whether the last change only appended text, so the reader keeps its page.
+    #[serde(default)]
+    appended: bool,
+    // Unix time in milliseconds of the last change of the text; 0 if unknown.
+    #[serde(default)]
+    updated_at: u64,
+}
+
+// Response for the GET /api/source endpoint. The hash is computed over the raw Markdown.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SourceResponse {
+    markdown: String,
+    hash: String,
 }
 
 // Payload for the POST /api/content endpoint.
 #[derive(Deserialize, Debug)]
 struct SetTextPayload {
     new_text: String,
+    // Whether `new_text` replaces the shared text or is appended to it as a new paragraph.
+    #[serde(default)]
+    mode: WriteMode,
+    // Source hash (as in GET /api/source) of the text the client edited. If set and
+    // the shared text has changed since, the write is rejected with 409 Conflict.
+    #[serde(default)]
+    base_hash: Option<String>,
+}
+
+// How POST /api/content applies `new_text`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum WriteMode {
+    #[default]
+    Replace,
+    Append,
+}
+
+// Payload for the POST /api/structured endpoint.
+#[derive(Deserialize, Debug)]
+struct StructuredPayload {
+    // If set, becomes the leading H1 of the document.
+    #[serde(default)]
+    title: Option<String>,
+    markdown: String,
+}
+
+// Response for the POST /api/structured endpoint, with the source hash of the new text.
+#[derive(Serialize, Deserialize, Debug)]
+struct HashResponse {
+    hash: String,
+}
+
+// Query string of the GET /get endpoint.
+#[derive(Deserialize, Debug)]
+struct PageParams {
+    /// `?nojs=1` serves a plain scrollable page for browsers without JavaScript.
+    nojs: Option<String>,
+    /// `?from=N&to=M` limit page turning to pages N to M, counted from 1, and open
+    /// the reader at page N. Values that are not numbers are ignored.
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// The page range of `?from=N&to=M` for the reader script, clamped so `from` is at
+/// least 1 and `to` is not before `from`. The script clamps both to the page count,
+/// which only it knows.
+#[derive(Serialize, Debug, PartialEq)]
+struct PageRange {
+    from: Option<u32>,
+    to: Option<u32>,
+}
+
+impl PageRange {
+    fn from_params(params: &PageParams) -> Self {
+        let parse = |value: &Option<String>| value.as_deref()?.trim().parse::<u32>().ok();
+        let from = parse(&params.from).map(|from| from.max(1));
+        let to = parse(&params.to).map(|to| to.max(from.unwrap_or(1)));
+        Self { from, to }
+    }
+}
+
+// Query string or form body for the /api/push endpoint.
+#[derive(Deserialize, Debug)]
+struct PushTextParams {
+    text: String,
 }
 
 // Payload for the POST /api/url endpoint.
 #[derive(Deserialize, Debug)]
 struct FetchUrlPayload {
     url: String,
+    /// Download the page's images into the text instead of linking to them.
+    #[serde(default)]
+    embed_images: bool,
 }
 
 // Generic JSON response for API actions.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 struct ApiResponse {
     message: String,
 }
 
-/// Initializes and runs the Axum web server.
-pub async fn run_server(app_state: Arc<AppState>) {
-    // Explicitly configure CORS to allow POST requests with a JSON content type from any origin.
+/// A running web server, returned by [`run_server`]. Dropping it leaves the
+/// server running; use [`ServerHandle::stop`] to shut it down.
+#[derive(Debug)]
+pub struct ServerHandle {
+    /// The address the server listens on.
+    pub addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+    /// The mDNS announcement for other instances; `None` in safe mode or if mDNS
+    /// is unavailable.
+    advertisement: Option<Advertisement>,
+}
+
+impl ServerHandle {
+    /// Stops accepting connections and waits for open requests to finish, up to
+    /// [`SERVER_SHUTDOWN_TIMEOUT`]; after that the server task is aborted, since
+    /// WebSocket readers never finish on their own.
+    pub async fn stop(mut self) {
+        self.stop_advertising();
+        let _ = self.shutdown.send(());
+        let mut task = self.task;
+        if tokio::time::timeout(SERVER_SHUTDOWN_TIMEOUT, &mut task)
+            .await
+            .is_err()
+        {
+            warn!("Server did not shut down in time, aborting it");
+            task.abort();
+            let _ = task.await;
+        }
+        info!("Server on {} stopped", self.addr);
+    }
+
+    /// Withdraws the server's mDNS announcement, e.g. when the app exits and the
+    /// server is not stopped properly.
+    pub fn stop_advertising(&mut self) {
+        if let Some(advertisement) = self.advertisement.take() {
+            advertisement.stop();
+        }
+    }
+}
+
+/// Binds the configured port (or the next free one) and starts the Axum web
+/// server in a background task.
+///
+/// # Returns
+/// A handle to stop the server, or `None` if no port could be bound.
+pub async fn run_server(app_state: Arc<AppState>) -> Option<ServerHandle> {
+    info!(
+        "Server concurrency limit: {} requests",
+        app_state.max_concurrent_requests
+    );
+    let port = app_state.server_port.load(Ordering::SeqCst);
+    let Some((listener, addr)) = bind_listener(port).await else {
+        error!(
+            "Failed to bind to any port from {} to {}",
+            port,
+            port.saturating_add(PORT_ATTEMPTS - 1)
+        );
+        return None;
+    };
+    if addr.port() != port {
+        warn!(
+            "Port {} is in use, using port {} instead",
+            port,
+            addr.port()
+        );
+    }
+    app_state.server_port.store(addr.port(), Ordering::SeqCst);
+    info!("🚀 E-Ink server listening on http://{}/get", addr);
+    // Other instances find this one for `push_to_peer`; safe mode stays off the network.
+    let advertisement = if app_state.safe_mode {
+        None
+    } else {
+        advertise(addr.port()).map_err(|e| warn!("{}", e)).ok()
+    };
+
+    let app = build_router(app_state);
+    let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(async {
+                let _ = shutdown_signal.await;
+            })
+            .await;
+        if let Err(e) = result {
+            error!("Server error: {}", e);
+        }
+    });
+    Some(ServerHandle {
+        addr,
+        shutdown,
+        task,
+        advertisement,
+    })
+}
+
+/// Stops the running web server, if any, and starts it again, e.g. after it
+/// failed to bind at launch.
+///
+/// # Returns
+/// Whether the new server bound a port.
+pub async fn restart_server(app_state: Arc<AppState>) -> bool {
+    // Held throughout, so concurrent restarts do not race for the port.
+    let mut server = app_state.server.lock().await;
+    if let Some(handle) = server.take() {
+        handle.stop().await;
+    }
+    *server = run_server(app_state.clone()).await;
+    server.is_some()
+}
+
+/// The address the server listens on for `port`: every network interface, so
+/// readers on the local network can connect.
+fn server_addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], port))
+}
+
+/// Binds the first free port of the [`PORT_ATTEMPTS`] ports starting at `port`.
+///
+/// # Returns
+/// The listener and its address, or `None` if every port is taken.
+async fn bind_listener(port: u16) -> Option<(TcpListener, SocketAddr)> {
+    for candidate in port..=port.saturating_add(PORT_ATTEMPTS - 1) {
+        match TcpListener::bind(server_addr(candidate)).await {
+            Ok(listener) => {
+                let addr = listener.local_addr().ok()?;
+                return Some((listener, addr));
+            }
+            Err(e) => warn!("Failed to bind to port {}: {}", candidate, e),
+        }
+    }
+    None
+}
+
+/// Builds the application router with all routes and middleware.
+fn build_router(app_state: Arc<AppState>) -> Router {
+    // Explicitly configure CORS to allow POST and DELETE requests with a JSON content type from any origin.
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers([CONTENT_TYPE]);
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([CONTENT_TYPE])
+        .expose_headers([HeaderName::from_static(CONTENT_HASH_HEADER)]);
+
+    // Requests above the limit wait for a free slot instead of being rejected. The
+    // router applies the layer to every route separately, so the routes must share
+    // one semaphore for the limit to cover the whole server.
+    let concurrency_limit = GlobalConcurrencyLimitLayer::new(app_state.max_concurrent_requests);
+    // Everything that shows the content or reader settings, and `/api/push`, which
+    // any web page could otherwise trigger with a plain GET; other writes stay open
+    // to the editor.
+    let token_required = middleware::from_fn_with_state(app_state.clone(), require_token);
 
-    let app = Router::new()
-        .route("/get", get(get_page_handler))
+    Router::new()
+        .route(
+            "/get",
+            get(get_page_handler).route_layer(token_required.clone()),
+        )
+        .route("/status", get(status_handler))
         .route(
             "/api/content",
-            get(api_content_handler).post(api_set_content_handler),
+            get(api_content_handler)
+                .route_layer(token_required.clone())
+                .post(api_set_content_handler)
+                .delete(api_reset_content_handler),
+        )
+        // Bookmarklet-friendly route: `GET ?text=...` or an urlencoded form POST.
+        .route(
+            "/api/push",
+            get(api_push_query_handler)
+                .post(api_push_form_handler)
+                .route_layer(token_required.clone()),
+        )
+        // Pushes content changes to readers; `/api/content` polling is the fallback.
+        .route("/ws", get(ws_handler).route_layer(token_required.clone()))
+        .route(
+            "/api/hash",
+            get(api_hash_handler).route_layer(token_required.clone()),
+        )
+        .route(
+            "/api/source",
+            get(api_source_handler).route_layer(token_required.clone()),
+        )
+        .route(
+            "/api/stats",
+            get(api_stats_handler).route_layer(token_required.clone()),
         )
+        .route(
+            "/api/toc",
+            get(api_toc_handler).route_layer(token_required.clone()),
+        )
+        .route(
+            "/api/export/epub",
+            get(api_export_epub_handler).route_layer(token_required.clone()),
+        )
+        // The reader reports its page here, for display in the desktop app.
+        .route(
+            "/api/progress",
+            post(api_progress_handler).route_layer(token_required.clone()),
+        )
+        .route("/api/structured", post(api_set_structured_handler))
         // New route for fetching and processing a URL.
         .route("/api/url", post(api_fetch_url_handler))
+        .route(
+            "/api/settings",
+            get(api_settings_handler)
+                .post(api_update_settings_handler)
+                .route_layer(token_required),
+        )
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            record_activity,
+        ))
+        // Added after the activity layer, so monitoring scripts polling it do not
+        // keep the idle shutdown timer from firing.
+        .route("/health", get(health_handler))
         .with_state(app_state)
-        .layer(cors);
+        .layer(cors)
+        .layer(concurrency_limit)
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], SERVER_PORT));
-    info!("🚀 E-Ink server listening on http://{}/get", addr);
+/// Middleware noting the time of every request, for the idle shutdown timer.
+async fn record_activity(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    state.record_request();
+    next.run(request).await
+}
 
-    if let Ok(listener) = TcpListener::bind(addr).await {
-        if let Err(e) = axum::serve(listener, app).await {
-            error!("Server error: {}", e);
+/// Middleware rejecting requests without the right `?token=` query parameter with
+/// `401 Unauthorized`, if access control is enabled (see [`AppState::access_token`]).
+async fn require_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(expected) = &state.access_token {
+        let token = request.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "token")
+                .map(|(_, value)| value.into_owned())
+        });
+        if token.as_deref() != Some(expected.as_str()) {
+            // Only the path is logged, so a wrong token never ends up in the log.
+            warn!(
+                "Rejected request to {} without a valid access token",
+                request.uri().path()
+            );
+            return (
+                StatusCode::UNAUTHORIZED,
+                no_cache_headers(),
+                "Нужен токен доступа: откройте на читалке адрес, показанный в приложении.",
+            )
+                .into_response();
         }
-    } else {
-        error!("Failed to bind to address {}", addr);
     }
+    next.run(request).await
+}
+
+/// Interprets a query flag such as `nojs=1`; `0`, `false`, `no`, `off` and empty mean off.
+fn is_truthy(value: &str) -> bool {
+    !matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "" | "0" | "false" | "no" | "off"
+    )
 }
 
 /// Returns a HeaderMap with directives to prevent caching.
@@ -88,8 +456,63 @@ fn no_cache_headers() -> HeaderMap {
     headers
 }
 
+/// Applies the `image_mode` setting to rendered HTML.
+fn apply_image_mode(html: String, image_mode: ImageMode) -> String {
+    match image_mode {
+        ImageMode::Inline => html,
+        ImageMode::Gallery => move_images_to_gallery(&html),
+        ImageMode::Hidden => replace_images_with_alt(&html),
+    }
+}
+
+/// Applies the reader settings that change the served content HTML but not its
+/// hash: `image_mode` and `minify_html`. The hash is always computed before this,
+/// so switching these settings keeps saved reading positions and causes no reloads.
+fn render_for_reader(html: String, settings: &ReaderSettings) -> String {
+    let html = apply_image_mode(html, settings.image_mode);
+    if settings.minify_html {
+        minify_html(&html)
+    } else {
+        html
+    }
+}
+
+/// [`GET_TEMPLATE`] with [`CONTENT_CSS`] inlined and minified by [`minify_template`],
+/// built on first use.
+fn minified_get_template() -> &'static str {
+    static TEMPLATE: OnceLock<String> = OnceLock::new();
+    TEMPLATE
+        .get_or_init(|| minify_template(&GET_TEMPLATE.replace("{{ content_css }}", CONTENT_CSS)))
+}
+
+/// Shrinks a page template line by line: indentation, blank lines, whole-line
+/// `/* */` comments and whole-line `//` comments inside `<script>` are removed.
+/// Line breaks are kept, so JavaScript statements stay separated.
+fn minify_template(template: &str) -> String {
+    let mut in_script = false;
+    let mut lines = Vec::new();
+
+    for line in template.lines().map(str::trim) {
+        if line.starts_with("<script") {
+            in_script = true;
+        } else if line.starts_with("</script") {
+            in_script = false;
+        }
+        let is_comment = (line.starts_with("/*") && line.ends_with("*/"))
+            || (in_script && line.starts_with("//"));
+        if !line.is_empty() && !is_comment {
+            lines.push(line);
+        }
+    }
+
+    lines.join("\n")
+}
+
 /// Handler for the `/get` route, serving the main reader page.
-async fn get_page_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+async fn get_page_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PageParams>,
+) -> impl IntoResponse {
     info!("Request received for initial page /get");
     let shared_text = match state.shared_text.read() {
         Ok(guard) => guard.clone(),
@@ -105,64 +528,428 @@ async fn get_page_handler(State(state): State<Arc<AppState>>) -> impl IntoRespon
         }
     };
 
-    let (initial_content, initial_hash) = process_markdown(&shared_text);
+    let Settings {
+        reader: settings,
+        content_mode,
+        ..
+    } = state.settings();
+    if params.nojs.as_deref().is_some_and(is_truthy) {
+        info!("Serving the no-JS page variant");
+        let page = render_standalone_html(&shared_text, content_mode, settings.auto_title);
+        let page = if settings.minify_html {
+            minify_html(&page)
+        } else {
+            page
+        };
+        return (no_cache_headers(), Html(page)).into_response();
+    }
+
+    let (initial_content, initial_hash) = state.render(&shared_text);
     info!("Serving initial page with hash: {}", initial_hash);
 
-    let html_template = GET_TEMPLATE
+    let title = content_title(&shared_text, content_mode, settings.auto_title);
+    let initial_content = render_for_reader(initial_content, &settings);
+
+    let template = if settings.minify_html {
+        minified_get_template().to_string()
+    } else {
+        GET_TEMPLATE.replace("{{ content_css }}", CONTENT_CSS)
+    };
+    let html_template = template
         .replace("{{ initial_hash }}", &initial_hash)
         .replace(
-            "{{ initial_content_json }}",
-            &serde_json::to_string(&initial_content).unwrap_or_else(|_| "''".to_string()),
-        );
+            "{{ updated_at }}",
+            &state.updated_at.load(Ordering::SeqCst).to_string(),
+        )
+        .replace("{{ font_scales }}", &font_scales_json())
+        .replace(
+            "{{ access_token }}",
+            state.access_token.as_deref().unwrap_or_default(),
+        )
+        .replace(
+            "{{ settings_json }}",
+            &serde_json::to_string(&settings).unwrap_or_else(|_| "{}".to_string()),
+        )
+        .replace(
+            "{{ page_range }}",
+            &serde_json::to_string(&PageRange::from_params(&params))
+                .unwrap_or_else(|_| "{}".to_string()),
+        )
+        // The content goes in last so that placeholder-like text in it stays untouched.
+        .replace("{{ initial_content }}", &initial_content)
+        // Only the first placeholder, in `<head>`, before any user content.
+        .replacen("{{ title }}", &escape_html(&title), 1);
 
     (no_cache_headers(), Html(html_template)).into_response()
 }
 
+/// The reader's font size steps, in `em`, as a JSON array: the [`FONT_SCALES`] the
+/// desktop app estimates page counts for.
+fn font_scales_json() -> String {
+    serde_json::to_string(&FONT_SCALES.map(|(_, scale)| scale))
+        .unwrap_or_else(|_| "[1.3]".to_string())
+}
+
 /// Handler for the `/api/content` route, providing content updates.
 async fn api_content_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     info!("Polling request received for /api/content");
+    match current_content(&state) {
+        Ok(response) => (StatusCode::OK, no_cache_headers(), Json(response)).into_response(),
+        Err(error_response) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            no_cache_headers(),
+            Json(error_response),
+        )
+            .into_response(),
+    }
+}
+
+/// Renders the shared text for the reader, as sent by `/api/content` and `/ws`.
+///
+/// # Returns
+/// The content, or an error page with a never-matching hash if the shared text
+/// is unreadable.
+fn current_content(state: &AppState) -> Result<ContentResponse, ContentResponse> {
     let shared_text = match state.shared_text.read() {
         Ok(guard) => guard.clone(),
         Err(e) => {
-            warn!("Failed to acquire read lock for /api/content: {}", e);
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_nanos();
-            let error_response = ContentResponse {
+            warn!("Failed to acquire read lock for the reader content: {}", e);
+            return Err(ContentResponse {
                 html: "<h2>Ошибка на сервере</h2><p>Не удалось получить доступ к данным. Попробуйте перезапустить приложение.</p>".to_string(),
-                hash: format!("error-{}", now),
+                hash: error_hash(),
+                title: DEFAULT_TITLE.to_string(),
+                appended: false,
+                updated_at: 0,
+            });
+        }
+    };
+
+    let (html_content, current_hash) = state.render(&shared_text);
+    let Settings {
+        reader: settings,
+        content_mode,
+        ..
+    } = state.settings();
+
+    Ok(ContentResponse {
+        html: render_for_reader(html_content, &settings),
+        hash: current_hash,
+        title: content_title(&shared_text, content_mode, settings.auto_title),
+        appended: state.last_change_appended.load(Ordering::SeqCst),
+        updated_at: state.updated_at.load(Ordering::SeqCst),
+    })
+}
+
+/// Handler for the `/ws` route. Upgrades to a WebSocket that receives the reader
+/// content (as in `/api/content`) on connect and after every change, so readers
+/// need not poll.
+async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
+    info!("WebSocket connection requested on /ws");
+    ws.on_upgrade(move |socket| forward_content_updates(socket, state))
+}
+
+/// Sends the reader content over `socket` until the reader disconnects.
+///
+/// Each connection has its own subscription, so a slow or dead reader never blocks
+/// writers or other readers; if it falls behind, it just gets the latest content.
+/// While connected, the reader counts as server activity for idle shutdown.
+async fn forward_content_updates(mut socket: WebSocket, state: Arc<AppState>) {
+    let mut updates = state.content_updates.subscribe();
+    let mut keepalive = tokio::time::interval(WS_KEEPALIVE_INTERVAL);
+    keepalive.tick().await;
+
+    if send_content(&mut socket, &state).await.is_err() {
+        return;
+    }
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(_) | Err(RecvError::Lagged(_)) => {
+                    if send_content(&mut socket, &state).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = keepalive.tick() => {
+                state.record_request();
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    info!("WebSocket connection on /ws closed");
+}
+
+async fn send_content(socket: &mut WebSocket, state: &AppState) -> Result<(), axum::Error> {
+    let content = current_content(state).unwrap_or_else(|error_response| error_response);
+    let json = serde_json::to_string(&content).unwrap_or_default();
+    socket.send(Message::Text(json)).await
+}
+
+/// Handler for the `GET /api/hash` route, returning just the content hash (as in
+/// `/api/content`) as plain text. Lets minimal clients poll cheaply and fetch
+/// `/api/content` only when the hash changes.
+async fn api_hash_handler(State(state): State<Arc<AppState>>) -> Response {
+    let hash = match state.shared_text.read() {
+        Ok(guard) => state.render(&guard).1,
+        Err(e) => {
+            warn!("Failed to acquire read lock for /api/hash: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                no_cache_headers(),
+                error_hash(),
+            )
+                .into_response();
+        }
+    };
+
+    (StatusCode::OK, no_cache_headers(), hash).into_response()
+}
+
+/// A hash that never matches real content, so polling clients keep retrying while
+/// the shared text is unreadable.
+fn error_hash() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("error-{}", now)
+}
+
+/// Handler for the `GET /api/source` route, returning the raw Markdown and its hash
+/// for sync tools. The hash is also sent in the `X-Content-Hash` header.
+async fn api_source_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    info!("Request received for /api/source");
+    let markdown = match state.shared_text.read() {
+        Ok(guard) => guard.clone(),
+        Err(e) => {
+            warn!("Failed to acquire read lock for /api/source: {}", e);
+            let response = ApiResponse {
+                message: "Ошибка сервера: не удалось прочитать текст.".to_string(),
             };
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 no_cache_headers(),
-                Json(error_response),
+                Json(response),
             )
                 .into_response();
         }
     };
 
-    let (html_content, current_hash) = process_markdown(&shared_text);
+    let hash = sha1_hex(&markdown);
+    let mut headers = no_cache_headers();
+    insert_content_hash(&mut headers, &hash);
 
-    let response = ContentResponse {
-        html: html_content,
-        hash: current_hash,
+    (
+        StatusCode::OK,
+        headers,
+        Json(SourceResponse { markdown, hash }),
+    )
+        .into_response()
+}
+
+/// Handler for the `POST /api/progress` route, where the reader reports the page
+/// it is on. Reports about another text than the current one are rejected with
+/// `409 Conflict`, as the reader is about to load the new text anyway.
+async fn api_progress_handler(
+    State(state): State<Arc<AppState>>,
+    Json(progress): Json<ReadingProgress>,
+) -> StatusCode {
+    log::debug!(
+        "Reader is on page {} of {} ({})",
+        progress.page,
+        progress.total,
+        progress.hash
+    );
+    if state.record_progress(progress) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CONFLICT
+    }
+}
+
+/// Handler for the `GET /api/stats` route, reporting the length, word count,
+/// reading time and source hash of the shared text.
+async fn api_stats_handler(State(state): State<Arc<AppState>>) -> Response {
+    match state.shared_text.read() {
+        Ok(text) => (
+            StatusCode::OK,
+            no_cache_headers(),
+            Json(content_stats(&text)),
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to acquire read lock for /api/stats: {}", e);
+            let response = ApiResponse {
+                message: "Ошибка сервера: не удалось прочитать текст.".to_string(),
+            };
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                no_cache_headers(),
+                Json(response),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Handler for the `GET /api/toc` route, listing the headings of the shared text
+/// with the anchor ids they have on the reader page.
+async fn api_toc_handler(State(state): State<Arc<AppState>>) -> Response {
+    match state.shared_text.read() {
+        Ok(text) => (
+            StatusCode::OK,
+            no_cache_headers(),
+            Json(render_content_with_toc(&text, state.settings().content_mode).1),
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to acquire read lock for /api/toc: {}", e);
+            let response = ApiResponse {
+                message: "Ошибка сервера: не удалось прочитать текст.".to_string(),
+            };
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                no_cache_headers(),
+                Json(response),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Handler for the `GET /api/export/epub` route, downloading the shared text as a
+/// single-chapter EPUB book named after its title.
+async fn api_export_epub_handler(State(state): State<Arc<AppState>>) -> Response {
+    info!("Request received to export the shared text as EPUB");
+    let shared_text = match state.shared_text.read() {
+        Ok(guard) => guard.clone(),
+        Err(e) => {
+            error!("Failed to acquire read lock for /api/export/epub: {}", e);
+            let response = ApiResponse {
+                message: "Ошибка сервера: не удалось прочитать текст.".to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
+        }
     };
+    let settings = state.settings();
+    let (mode, auto_title) = (settings.content_mode, settings.reader.auto_title);
+
+    match render_epub(&shared_text, mode, auto_title) {
+        Ok(book) => {
+            let mut headers = no_cache_headers();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/epub+zip"),
+            );
+            let file_name = format!("{}.epub", content_title(&shared_text, mode, auto_title));
+            if let Ok(value) = HeaderValue::from_str(&attachment_disposition(&file_name)) {
+                headers.insert(CONTENT_DISPOSITION, value);
+            }
+            (StatusCode::OK, headers, book).into_response()
+        }
+        Err(message) => {
+            error!("Failed to export EPUB: {}", message);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse { message }),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Builds a `Content-Disposition` value that downloads as `file_name`. Browsers
+/// use the percent-encoded UTF-8 name; the plain one is an ASCII-only fallback.
+fn attachment_disposition(file_name: &str) -> String {
+    let encoded: String = file_name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
 
-    (StatusCode::OK, no_cache_headers(), Json(response)).into_response()
+fn insert_content_hash(headers: &mut HeaderMap, hash: &str) {
+    if let Ok(value) = HeaderValue::from_str(hash) {
+        headers.insert(CONTENT_HASH_HEADER, value);
+    }
 }
 
-/// Handler for the `POST /api/content` route, updating the shared text.
+/// Handler for the `POST /api/content` route, updating the shared text. With
+/// `"mode": "append"` the new text is added as a paragraph at the end instead of
+/// replacing it.
+///
+/// If the payload has a `base_hash` that no longer matches the shared text, the
+/// write is rejected with `409 Conflict` and the current text is returned instead.
+/// Results longer than the `max_text_chars` setting are rejected with
+/// `413 Payload Too Large`. On success the new source hash is sent in the
+/// `x-content-hash` header.
 async fn api_set_content_handler(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<SetTextPayload>,
-) -> impl IntoResponse {
-    info!("Request received to update content via POST /api/content");
+) -> Response {
+    info!(
+        "Request received to update content via POST /api/content ({:?})",
+        payload.mode
+    );
     match state.shared_text.write() {
         Ok(mut text) => {
-            *text = payload.new_text;
+            let current_hash = sha1_hex(&text);
+            if payload
+                .base_hash
+                .as_ref()
+                .is_some_and(|base_hash| *base_hash != current_hash)
+            {
+                warn!("Rejected POST /api/content: the shared text changed since it was loaded.");
+                let response = SourceResponse {
+                    markdown: text.clone(),
+                    hash: current_hash,
+                };
+                return (StatusCode::CONFLICT, Json(response)).into_response();
+            }
+
+            let new_text = match payload.mode {
+                WriteMode::Replace => payload.new_text,
+                WriteMode::Append => append_paragraph(&text, &payload.new_text),
+            };
+            if let Err(message) = state.ensure_text_fits(&new_text) {
+                return (StatusCode::PAYLOAD_TOO_LARGE, Json(ApiResponse { message }))
+                    .into_response();
+            }
+            state.replace_shared_text(&mut text, new_text);
             info!("Successfully updated shared text from API.");
-            (StatusCode::OK, Json("Content updated successfully."))
+            let mut headers = HeaderMap::new();
+            insert_content_hash(&mut headers, &sha1_hex(&text));
+            (
+                StatusCode::OK,
+                headers,
+                Json("Content updated successfully."),
+            )
+                .into_response()
         }
         Err(e) => {
             error!("Failed to acquire write lock for /api/content: {}", e);
@@ -170,99 +957,271 @@ async fn api_set_content_handler(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json("Failed to update content due to a server error."),
             )
+                .into_response()
         }
     }
 }
 
-/// Handler for the `POST /api/url` route, fetching content and updating state.
-async fn api_fetch_url_handler(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<FetchUrlPayload>,
-) -> impl IntoResponse {
-    info!("Request received to fetch URL: {}", payload.url);
-
-    match url_processor::process_url(&payload.url).await {
-        Ok(markdown_content) => match state.shared_text.write() {
-            Ok(mut text) => {
-                *text = markdown_content;
-                info!("Successfully updated shared text from URL.");
-                let response = ApiResponse {
-                    message: "Отправлено".to_string(),
-                };
-                (StatusCode::OK, Json(response))
-            }
-            Err(e) => {
-                error!("Failed to acquire write lock for AppState: {}", e);
-                let response = ApiResponse {
-                    message: "Ошибка сервера: не удалось обновить текст.".to_string(),
-                };
-                (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
-            }
-        },
+/// Handler for the `DELETE /api/content` route, resetting the shared text to the
+/// welcome message shown on first launch. Like other writes, the reset can be
+/// taken back with undo.
+async fn api_reset_content_handler(State(state): State<Arc<AppState>>) -> Response {
+    info!("Request received to reset content via DELETE /api/content");
+    match state.shared_text.write() {
+        Ok(mut text) => {
+            state.replace_shared_text(&mut text, WELCOME_TEXT.to_string());
+            let mut headers = HeaderMap::new();
+            insert_content_hash(&mut headers, &sha1_hex(&text));
+            (StatusCode::OK, headers, Json("Content reset successfully.")).into_response()
+        }
         Err(e) => {
-            error!("Failed to process URL {}: {}", payload.url, e);
-            let response = ApiResponse { message: e };
-            (StatusCode::BAD_REQUEST, Json(response))
+            error!("Failed to acquire write lock for /api/content: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json("Failed to reset content due to a server error."),
+            )
+                .into_response()
         }
     }
 }
 
-const GET_TEMPLATE: &str = r#"
-<!DOCTYPE html>
-<html lang="ru">
-<head>
-    <meta charset="UTF-8">
-    <title>Текст для чтения</title>
-    <meta name="viewport" content="width=device-width, initial-scale=1.0, user-scalable=no">
-    <style>
-        html, body { 
-            margin: 0; 
-            padding: 0; 
-            width: 100%;
-            height: 100%; 
-            overflow: hidden; /* Prevent vertical scrollbar */
-            font-family: 'Georgia', serif; 
-            color: #111; 
-            background-color: #fdfdfd; 
+/// Appends `addition` to `text` as a new paragraph, separated by a blank line like
+/// clipboard captures added to the editor. Appending to blank text gives `addition`.
+fn append_paragraph(text: &str, addition: &str) -> String {
+    if text.trim().is_empty() {
+        addition.to_string()
+    } else {
+        format!("{}\n\n{}", text, addition)
+    }
+}
+
+/// Handler for the `POST /api/structured` route, replacing the shared text with a
+/// Markdown body and an optional title that becomes its leading H1. Text longer
+/// than the `max_text_chars` setting allows is rejected with `413`.
+async fn api_set_structured_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<StructuredPayload>,
+) -> Response {
+    info!("Request received to set structured content via POST /api/structured");
+    match state.shared_text.write() {
+        Ok(mut text) => {
+            let hash = match state.set_structured_text(
+                &mut text,
+                payload.title.as_deref(),
+                payload.markdown,
+            ) {
+                Ok(hash) => hash,
+                Err(message) => {
+                    return (StatusCode::PAYLOAD_TOO_LARGE, Json(ApiResponse { message }))
+                        .into_response();
+                }
+            };
+            info!("Successfully updated shared text from /api/structured.");
+            let mut headers = HeaderMap::new();
+            insert_content_hash(&mut headers, &hash);
+            (StatusCode::OK, headers, Json(HashResponse { hash })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to acquire write lock for /api/structured: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json("Failed to update content due to a server error."),
+            )
+                .into_response()
         }
+    }
+}
 
-        #content-wrapper {
-            /* Это наш вьюпорт для прокрутки. Он должен быть равен ширине экрана. */
-            height: calc(100vh - 40px);
-            width: 100vw;
-            overflow: hidden;
-            scroll-snap-type: x mandatory;
+/// Handler for the `GET /api/push?text=...` route, used by bookmarklets.
+async fn api_push_query_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PushTextParams>,
+) -> Response {
+    info!("Request received to push text via GET /api/push");
+    push_text(&state, params.text)
+}
+
+/// Handler for the `POST /api/push` route with an `application/x-www-form-urlencoded` body.
+async fn api_push_form_handler(
+    State(state): State<Arc<AppState>>,
+    Form(params): Form<PushTextParams>,
+) -> Response {
+    info!("Request received to push text via POST /api/push");
+    push_text(&state, params.text)
+}
+
+/// Replaces the shared text with pushed text, which can be taken back with undo,
+/// and answers with a tiny HTML page.
+fn push_text(state: &AppState, text: String) -> Response {
+    if let Err(message) = state.ensure_text_fits(&text) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, push_result_page(&message)).into_response();
+    }
+    if text.trim().is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            push_result_page("Пустой текст не отправлен."),
+        )
+            .into_response();
+    }
+
+    let char_count = text.chars().count();
+    let words = count_words(&text);
+    match state.shared_text.write() {
+        Ok(mut shared_text) => {
+            state.replace_shared_text(&mut shared_text, text);
+            state.stats.record_document(words);
+            info!("Successfully updated shared text from /api/push.");
+            let message = format!("Отправлено на читалку ({} символов).", char_count);
+            (StatusCode::OK, push_result_page(&message)).into_response()
+        }
+        Err(e) => {
+            error!("Failed to acquire write lock for /api/push: {}", e);
+            let message = "Ошибка сервера: не удалось обновить текст.";
+            (StatusCode::INTERNAL_SERVER_ERROR, push_result_page(message)).into_response()
         }
+    }
+}
 
-        #content-container {
-            /* Это широкий элемент с колонками. */
-            height: 100%;
-            
-            /* Отступы по бокам ДОЛЖНЫ быть здесь. Это создает отступы для первой и последней страницы. */
-            padding-left: 25px;
-            padding-right: 25px;
-            box-sizing: border-box;
-            
-            /* Ширина КОНТЕНТА внутри одной колонки. */
-            column-width: calc(100vw - 50px);
-            
-            /* Промежуток МЕЖДУ колонками. */
-            column-gap: 50px;
-            
-            /* Стандартные стили текста */
-            font-size: 1.3em; 
-            line-height: 1.6;
-            text-align: justify;
+fn push_result_page(message: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html><html lang=\"ru\"><head><meta charset=\"UTF-8\"><title>Kindle Interactive</title></head><body><p>{}</p></body></html>",
+        escape_html(message)
+    ))
+}
+
+/// Handler for the `POST /api/url` route, fetching content and updating state.
+async fn api_fetch_url_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<FetchUrlPayload>,
+) -> impl IntoResponse {
+    info!("Request received to fetch URL: {}", payload.url);
+
+    if let Err(message) = state.ensure_network_allowed() {
+        warn!("Rejected URL fetch in safe mode: {}", payload.url);
+        return (StatusCode::FORBIDDEN, Json(ApiResponse { message }));
+    }
+    if let Err(message) = state.ensure_fetch_allowed() {
+        return (StatusCode::TOO_MANY_REQUESTS, Json(ApiResponse { message }));
+    }
+
+    let allowlist = url_processor::Allowlist::for_preset(state.settings().sanitize_preset);
+    match url_processor::process_url(&payload.url, payload.embed_images, &allowlist).await {
+        Ok(markdown_content) => {
+            if let Err(message) = state.ensure_text_fits(&markdown_content) {
+                return (StatusCode::PAYLOAD_TOO_LARGE, Json(ApiResponse { message }));
+            }
+            match state.shared_text.write() {
+                Ok(mut text) => {
+                    state.stats.record_fetch();
+                    state.stats.record_document(count_words(&markdown_content));
+                    state.replace_shared_text(&mut text, markdown_content);
+                    info!("Successfully updated shared text from URL.");
+                    let response = ApiResponse {
+                        message: "Отправлено".to_string(),
+                    };
+                    (StatusCode::OK, Json(response))
+                }
+                Err(e) => {
+                    error!("Failed to acquire write lock for AppState: {}", e);
+                    let response = ApiResponse {
+                        message: "Ошибка сервера: не удалось обновить текст.".to_string(),
+                    };
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(response))
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to process URL {}: {}", payload.url, e);
+            let response = ApiResponse {
+                message: e.to_string(),
+            };
+            (StatusCode::BAD_REQUEST, Json(response))
         }
+    }
+}
 
-        #content-container::after {
-            content: '';
-            display: block; /* Важно использовать block, чтобы он занял свою колонку */
-            width: calc(100vw - 50px); /* Ширина контента одной страницы */
-            height: 1px; /* Минимальная высота, чтобы элемент существовал */
-            break-before: column; /* Гарантируем, что он всегда начнет новую колонку */
+/// Handler for the `/status` route, reporting which features are active.
+async fn status_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let response = StatusResponse {
+        safe_mode: state.safe_mode,
+        clipboard_monitoring: !state.safe_mode,
+        network_fetching: !state.safe_mode,
+    };
+    (StatusCode::OK, no_cache_headers(), Json(response))
+}
+
+/// Handler for the `GET /health` liveness check. It takes no locks, so it still
+/// answers if the shared text lock is poisoned.
+async fn health_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let response = HealthResponse {
+        status: "ok".to_string(),
+        port: state.server_port.load(Ordering::SeqCst),
+    };
+    (StatusCode::OK, no_cache_headers(), Json(response))
+}
+
+/// Handler for the `GET /api/settings` route, returning every setting with its
+/// current value and allowed values (as the `get_all_settings` command does).
+async fn api_settings_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        no_cache_headers(),
+        Json(state.settings().report()),
+    )
+}
+
+/// Handler for the `POST /api/settings` route. Accepts any subset of the reader
+/// settings fields and returns the updated settings report, listing any numbers
+/// that were clamped into range. Application settings can only be changed from
+/// the desktop UI and are rejected here.
+async fn api_update_settings_handler(
+    State(state): State<Arc<AppState>>,
+    Json(patch): Json<serde_json::Value>,
+) -> impl IntoResponse {
+    info!("Request received to update reader settings: {}", patch);
+    let mut settings = match state.settings.write() {
+        Ok(guard) => guard,
+        Err(e) => {
+            error!("Failed to acquire write lock for /api/settings: {}", e);
+            let response = ApiResponse {
+                message: "Ошибка сервера: не удалось обновить настройки.".to_string(),
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
         }
-                
+    };
+
+    match settings.reader.merged_with(patch) {
+        Ok(updated) => {
+            settings.reader = updated;
+            let clamped = settings.clamp();
+            state.mark_unsaved();
+            info!("Reader settings updated: {:?}", settings.reader);
+            let report = SettingsReport {
+                clamped,
+                ..settings.report()
+            };
+            (StatusCode::OK, Json(report)).into_response()
+        }
+        Err(e) => {
+            warn!("Rejected invalid reader settings: {}", e);
+            let response = ApiResponse {
+                message: format!("Неверные настройки: {}", e),
+            };
+            (StatusCode::BAD_REQUEST, Json(response)).into_response()
+        }
+    }
+}
+
+/// Typography rules for the rendered content, shared by the reader page and exports.
+pub const CONTENT_CSS: &str = r#"
+        /* Long unbreakable tokens (URLs, minified JSON) must wrap inside the column,
+           otherwise they overflow it and inflate the page count. */
+        #content-container {
+            overflow-wrap: anywhere;
+            word-wrap: break-word;
+            word-break: break-word;
+        }
+
         /* Rules to prevent elements from breaking across columns (pages) */
         #content-container h1, 
         #content-container h2, 
@@ -289,9 +1248,52 @@ const GET_TEMPLATE: &str = r#"
             max-width: 100%; 
             height: auto; 
         }
+
+        #content-container figure {
+            margin: 1em 0;
+            text-align: center;
+        }
+        #content-container figure img {
+            display: block;
+            margin: 0 auto;
+        }
+        #content-container figcaption {
+            margin-top: 0.4em;
+            font-size: 0.85em;
+            font-style: italic;
+            line-height: 1.4;
+            text-align: center;
+        }
+
+        #content-container .image-alt {
+            font-style: italic;
+            color: var(--ki-muted, #555);
+        }
+        #content-container .image-ref {
+            font-size: 0.85em;
+            white-space: nowrap;
+        }
+        #content-container .image-gallery {
+            break-before: column;
+        }
+
+        #content-container details {
+            margin: 1em 0;
+            padding: 0 0.75em;
+            border: 1px solid var(--ki-rule, #ccc);
+        }
+        #content-container summary {
+            /* A large tap target: summaries are opened with a finger on the reader. */
+            padding: 0.5em 0;
+            font-weight: bold;
+            cursor: pointer;
+        }
+        #content-container details[open] summary {
+            border-bottom: 1px solid var(--ki-rule, #ccc);
+        }
         
         #content-container blockquote { 
-            border-left: 4px solid #ccc; 
+            border-left: 4px solid var(--ki-rule, #ccc); 
             padding-left: 1em; 
             margin-left: 0; 
         }
@@ -299,15 +1301,209 @@ const GET_TEMPLATE: &str = r#"
             white-space: pre-wrap !important; 
             word-break: break-word;
             font-size: 0.85em; 
-            background-color: #f3f3f3; 
+            background-color: var(--ki-code-background, #f3f3f3); 
             border-radius: 4px; 
             padding: 2px 4px;
             text-align: left;
         }
         #content-container pre { 
             padding: 1em; 
+        }
+
+        /* Task lists: no bullets, checkboxes drawn as plain squares for E-Ink. */
+        #content-container li.task-list-item {
+            list-style: none;
+        }
+        #content-container input[type="checkbox"] {
+            -webkit-appearance: none;
+            appearance: none;
+            width: 0.8em;
+            height: 0.8em;
+            margin: 0 0.4em 0 -1.2em;
+            border: 2px solid var(--ki-text, #111);
+            border-radius: 0;
+            vertical-align: -0.05em;
+            background-color: transparent;
+        }
+        #content-container input[type="checkbox"]:checked {
+            background-color: var(--ki-text, #111);
+        }
+
+        #content-container dt {
+            font-weight: bold;
+        }
+        #content-container dd {
+            margin: 0 0 0.5em 1.5em;
+        }
+
+        /* Wide code blocks and tables scroll horizontally inside their own box
+           instead of being clipped by the page. */
+        #content-container pre,
+        #content-container table {
+            max-width: 100%;
+            box-sizing: border-box;
             overflow-x: auto;
+            touch-action: pan-x;
+        }
+        #content-container pre {
+            white-space: pre !important;
+        }
+        #content-container pre code {
+            white-space: inherit !important;
+            padding: 0;
+        }
+        #content-container table {
+            display: block;
+            width: max-content;
+            border-collapse: collapse;
+        }
+
+        /* E-Ink browsers often hide scrollbars: mark blocks that really scroll
+           with a visible bar and a double right edge (set by the reader script). */
+        #content-container .is-scrollable {
+            border-right: 3px double var(--ki-text, #111);
+        }
+        #content-container .is-scrollable::-webkit-scrollbar {
+            height: 6px;
+        }
+        #content-container .is-scrollable::-webkit-scrollbar-thumb {
+            background-color: var(--ki-muted, #555);
+        }
+
+        /* A table taller than a page cannot stay in one column. A scrolling box
+           cannot be split across pages, so such tables are fitted to the page
+           width instead and break between rows. */
+        #content-container table.is-tall {
+            display: table;
+            width: 100%;
+            table-layout: fixed;
+            overflow: visible;
+            break-inside: auto;
+        }
+        #content-container table.is-tall tr {
+            break-inside: avoid;
+        }
+"#;
+
+const GET_TEMPLATE: &str = r#"
+<!DOCTYPE html>
+<html lang="ru">
+<head>
+    <meta charset="UTF-8">
+    <title>{{ title }}</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1.0, user-scalable=no">
+    <style>
+        /* Цвета темы; тема выбирается в нижней панели и меняет только эти переменные. */
+        :root {
+            --ki-text: #111;
+            --ki-background: #fdfdfd;
+            --ki-muted: #555;
+            --ki-rule: #ccc;
+            --ki-border: #ddd;
+            --ki-bar-background: rgba(255, 255, 255, 0.9);
+            --ki-menu-background: #fff;
+            --ki-code-background: #f3f3f3;
+        }
+        :root[data-theme="sepia"] {
+            --ki-text: #3b2f21;
+            --ki-background: #f4ecd8;
+            --ki-muted: #6b5a45;
+            --ki-rule: #c9b896;
+            --ki-border: #d8c9a8;
+            --ki-bar-background: rgba(244, 236, 216, 0.9);
+            --ki-menu-background: #f4ecd8;
+            --ki-code-background: #e9dec4;
+        }
+        /* Максимальный контраст: только черный и белый, без серых оттенков,
+           которые E-Ink экраны передают блекло. */
+        :root[data-theme="contrast"] {
+            --ki-text: #000;
+            --ki-background: #fff;
+            --ki-muted: #000;
+            --ki-rule: #000;
+            --ki-border: #000;
+            --ki-bar-background: #fff;
+            --ki-menu-background: #fff;
+            --ki-code-background: #fff;
+        }
+        :root[data-theme="dark"] {
+            --ki-text: #e8e8e8;
+            --ki-background: #111;
+            --ki-muted: #aaa;
+            --ki-rule: #555;
+            --ki-border: #444;
+            --ki-bar-background: rgba(17, 17, 17, 0.9);
+            --ki-menu-background: #1c1c1c;
+            --ki-code-background: #262626;
+        }
+        :root[data-theme="dark"] #content-container a {
+            color: #9ecbff;
+        }
+
+        html, body { 
+            margin: 0; 
+            padding: 0; 
+            width: 100%;
+            height: 100%; 
+            overflow: hidden; /* Prevent vertical scrollbar */
+            font-family: 'Georgia', serif; 
+            color: var(--ki-text); 
+            background-color: var(--ki-background); 
+        }
+
+        #content-wrapper {
+            /* Это наш вьюпорт для прокрутки. Он должен быть равен ширине экрана. */
+            height: calc(100vh - 40px);
+            width: 100vw;
+            overflow: hidden;
+            scroll-snap-type: x mandatory;
+        }
+
+        #content-container {
+            /* Это широкий элемент с колонками. */
+            height: 100%;
+            
+            /* Поле страницы; меняется выбором полей в нижней панели. */
+            --ki-margin: 25px;
+
+            /* Отступы по бокам ДОЛЖНЫ быть здесь. Это создает отступы для первой и последней страницы. */
+            padding-left: var(--ki-margin);
+            padding-right: var(--ki-margin);
+            box-sizing: border-box;
+            
+            /* Ширина КОНТЕНТА внутри одной колонки. */
+            column-width: calc(100vw - 2 * var(--ki-margin));
+            
+            /* Промежуток МЕЖДУ колонками: правое поле одной страницы и левое следующей.
+               Колонка с промежутком ровно занимают экран, на этом держится подсчет страниц. */
+            column-gap: calc(2 * var(--ki-margin));
+            
+            /* Стандартные стили текста */
+            font-size: 1.3em; 
+            line-height: 1.6;
+            text-align: justify;
+        }
+
+        #content-container.ragged {
+            text-align: left;
+        }
+
+        #content-container[data-margin="narrow"] {
+            --ki-margin: 12px;
         }
+        #content-container[data-margin="wide"] {
+            --ki-margin: 50px;
+        }
+
+        #content-container::after {
+            content: '';
+            display: block; /* Важно использовать block, чтобы он занял свою колонку */
+            width: calc(100vw - 2 * var(--ki-margin)); /* Ширина контента одной страницы */
+            height: 1px; /* Минимальная высота, чтобы элемент существовал */
+            break-before: column; /* Гарантируем, что он всегда начнет новую колонку */
+        }
+                
+        {{ content_css }}
 
         /* UI Bar styling (unchanged) */
         #ui-bar { 
@@ -316,8 +1512,8 @@ const GET_TEMPLATE: &str = r#"
             bottom: 0; 
             left: 0; 
             width: 100%; 
-            background-color: rgba(255, 255, 255, 0.9); 
-            border-top: 1px solid #ddd; 
+            background-color: var(--ki-bar-background); 
+            border-top: 1px solid var(--ki-border); 
             display: flex; 
             justify-content: center; 
             align-items: center; 
@@ -325,148 +1521,2206 @@ const GET_TEMPLATE: &str = r#"
             padding: 0 1em; 
             user-select: none; 
             font-family: sans-serif; 
-            color: #555; 
+            color: var(--ki-muted); 
         }
-    </style>
-</head>
-<body>
-    <div id="content-wrapper">
-        <div id="content-container"></div>
-    </div>
-    <div id="ui-bar"><div id="page-counter"></div></div>
-    
-    <script>
-        let currentPage = 0;
-        let totalPages = 0;
-        let currentHash = "{{ initial_hash }}";
-        let isUpdating = false;
 
-        const wrapper = document.getElementById('content-wrapper');
-        const container = document.getElementById('content-container');
-        const pageCounter = document.getElementById('page-counter');
-        
-        function updateLayout() {
-            // Используем Math.ceil для подсчета. Если контент занимает 2.1 страницы,
-            // нам нужно 3 "экрана" для его отображения. Это самый надежный способ.
-            const realTotalPages = Math.ceil(container.scrollWidth / wrapper.clientWidth);
+        [hidden] {
+            display: none !important;
+        }
 
-            // Количество страниц для пользователя = реальное количество минус одна (фиктивная).
-            totalPages = Math.max(1, realTotalPages - 1);
+        /* Minimalist mode: no UI bar, the content takes the whole screen. */
+        #updated-label {
+            margin: 0 1em;
+            font-size: 0.8em;
+            white-space: nowrap;
+        }
 
-            // Ограничиваем currentPage, чтобы пользователь не мог перейти на фиктивную страницу.
-            currentPage = Math.max(0, Math.min(currentPage, totalPages - 1));
-            
-            updateUi();
+        body.ui-bar-hidden #ui-bar {
+            display: none;
+        }
+        body.ui-bar-hidden #content-wrapper {
+            height: 100vh;
         }
 
-        function updateUi() {
-            if (totalPages > 0) {
-                pageCounter.textContent = `Страница ${currentPage + 1} из ${totalPages}`;
-                
-                // Больше никаких сложных формул!
-                // Просто прокручиваем на N экранов. Браузер сам справится с позиционированием.
-                const scrollLeftPosition = currentPage * wrapper.clientWidth;
+        /* Book-style paragraphs: no spacing, indented first line. */
+        #content-container.paragraphs-indented p {
+            margin: 0;
+            text-indent: 1.5em;
+        }
+        #content-container.paragraphs-indented h1 + p,
+        #content-container.paragraphs-indented h2 + p,
+        #content-container.paragraphs-indented h3 + p {
+            text-indent: 0;
+        }
 
-                wrapper.scrollTo({
-                    left: scrollLeftPosition,
-                    behavior: 'auto'
-                });
-            } else {
-                pageCounter.textContent = 'Нет страниц';
-            }
+        /* Heavier body text for faint screens. Georgia has no medium face, so
+           "medium" thickens the strokes instead of switching to bold. */
+        #content-container[data-font-weight="medium"] {
+            -webkit-text-stroke: 0.3px currentColor;
+        }
+        #content-container[data-font-weight="bold"] {
+            font-weight: bold;
+        }
+
+        /* Две колонки на экран: промежуток остается равен двум полям, поэтому две
+           колонки и два промежутка по-прежнему ровно занимают ширину экрана. */
+        #content-container.columns-2 {
+            column-width: auto;
+            column-count: 2;
+        }
+        #content-container.columns-2::after {
+            width: auto;
+        }
+
+        /* Абзацы не разрываются между страницами, кроме абзацев выше страницы:
+           их отмечает JS, иначе такой абзац некуда было бы поместить. */
+        #content-container.avoid-paragraph-breaks p {
+            break-inside: avoid;
+        }
+        #content-container.avoid-paragraph-breaks p.is-tall {
+            break-inside: auto;
+        }
+
+        /* Page indicator doubles as the toggle for the "jump to page" form. */
+        #page-indicator {
+            display: flex;
+            align-items: center;
+            gap: 0.75em;
+            cursor: pointer;
+        }
+
+        #progress-bar {
+            width: 40vw;
+            height: 8px;
+            border: 1px solid var(--ki-muted);
+            box-sizing: border-box;
+        }
+
+        #progress-fill {
+            width: 0;
+            height: 100%;
+            background-color: var(--ki-muted);
+        }
+
+        #page-indicator.progress-text #progress-bar,
+        #page-indicator.progress-bar #page-counter {
+            display: none;
+        }
+
+        #ui-bar .font-size-button {
+            min-width: 2.5em;
+            margin: 0 0.75em;
+            font-size: 1em;
+            background: none;
+            border: 1px solid var(--ki-rule);
+            color: inherit;
+        }
+
+        #align-toggle,
+        #columns-toggle,
+        #margin-select,
+        #theme-select {
+            margin: 0 0.75em;
+            font-size: 1em;
+            background: none;
+            border: 1px solid var(--ki-rule);
+            color: inherit;
+        }
+
+        #page-jump {
+            display: flex;
+            align-items: center;
+            gap: 0.5em;
+        }
+
+        #page-jump input {
+            width: 4em;
+            font-size: 1em;
+            text-align: center;
+        }
+
+        #page-jump button {
+            font-size: 1em;
+        }
+
+        /* Меню читалки: открывается нажатием на среднюю треть экрана. */
+        #reader-menu {
+            position: fixed;
+            top: 5vh;
+            left: 10vw;
+            width: 80vw;
+            max-height: calc(90vh - 40px);
+            overflow-y: auto;
+            box-sizing: border-box;
+            padding: 1em 1.5em;
+            background-color: var(--ki-menu-background);
+            border: 2px solid var(--ki-text);
+            font-family: sans-serif;
+            font-size: 1.1em;
+        }
+
+        #reader-menu h2,
+        #menu-toc-section summary {
+            font-size: 1.1em;
+            font-weight: bold;
+            margin: 0.5em 0;
+        }
+
+        #menu-toc-section summary {
+            cursor: pointer;
+        }
+
+        #menu-toc a {
+            display: block;
+            padding: 0.4em 0;
+            color: var(--ki-text);
+            text-decoration: none;
+            border-bottom: 1px solid var(--ki-border);
+        }
+
+        #menu-toc .toc-level-2 {
+            padding-left: 1em;
+        }
+
+        #menu-toc .toc-level-3 {
+            padding-left: 2em;
+        }
+
+        #menu-settings label {
+            display: flex;
+            justify-content: space-between;
+            align-items: center;
+            padding: 0.4em 0;
+        }
+
+        #menu-settings select,
+        #reader-menu button {
+            font-size: 1em;
+        }
+    </style>
+    <noscript>
+        <style>
+            /* Без JavaScript пагинация не работает: показываем текст одной прокручиваемой колонкой. */
+            html, body {
+                height: auto;
+                overflow: auto;
+            }
+            #content-wrapper {
+                height: auto;
+                width: auto;
+                overflow: visible;
+            }
+            #content-container {
+                height: auto;
+                max-width: 40em;
+                margin: 0 auto;
+                padding: 25px;
+                column-width: auto;
+            }
+            #content-container::after,
+            #ui-bar {
+                display: none;
+            }
+        </style>
+    </noscript>
+</head>
+<body>
+    <div id="content-wrapper">
+        <div id="content-container">{{ initial_content }}</div>
+    </div>
+    <div id="ui-bar">
+        <button type="button" id="font-smaller" class="font-size-button" title="Уменьшить шрифт">A−</button>
+        <div id="page-indicator" class="progress-text" title="Перейти к странице">
+            <div id="page-counter"></div>
+            <div id="progress-bar"><div id="progress-fill"></div></div>
+        </div>
+        <span id="updated-label"></span>
+        <form id="page-jump" hidden>
+            <input type="number" id="page-jump-input" min="1" inputmode="numeric">
+            <button type="submit">Перейти</button>
+        </form>
+        <button type="button" id="font-larger" class="font-size-button" title="Увеличить шрифт">A+</button>
+        <button type="button" id="align-toggle"></button>
+        <button type="button" id="columns-toggle"></button>
+        <select id="margin-select" title="Поля">
+            <option value="narrow">Узкие поля</option>
+            <option value="normal">Обычные поля</option>
+            <option value="wide">Широкие поля</option>
+        </select>
+        <select id="theme-select" title="Тема">
+            <option value="light">Светлая</option>
+            <option value="sepia">Сепия</option>
+            <option value="contrast">Контрастная</option>
+            <option value="dark">Темная</option>
+        </select>
+    </div>
+    <div id="reader-menu" hidden>
+        <details id="menu-toc-section" open>
+            <summary>Оглавление</summary>
+            <nav id="menu-toc"></nav>
+        </details>
+        <h2>Настройки</h2>
+        <form id="menu-settings">
+            <label>Абзацы
+                <select name="paragraph_style">
+                    <option value="spaced">С отступом между абзацами</option>
+                    <option value="indented">Книжные</option>
+                </select>
+            </label>
+            <label>Прогресс
+                <select name="progress_style">
+                    <option value="text">Текст</option>
+                    <option value="bar">Полоса</option>
+                    <option value="both">Текст и полоса</option>
+                </select>
+            </label>
+            <label>Насыщенность шрифта
+                <select name="font_weight">
+                    <option value="normal">Обычная</option>
+                    <option value="medium">Средняя</option>
+                    <option value="bold">Жирная</option>
+                </select>
+            </label>
+            <label>Нижняя панель
+                <input type="checkbox" name="show_ui_bar">
+            </label>
+            <label>Колонок на экране
+                <select name="columns_per_page" data-type="number">
+                    <option value="1">Одна</option>
+                    <option value="2">Две</option>
+                </select>
+            </label>
+            <label>Изображения
+                <select name="image_mode">
+                    <option value="inline">В тексте</option>
+                    <option value="gallery">В конце документа</option>
+                    <option value="hidden">Скрыть</option>
+                </select>
+            </label>
+            <label>Обновлять экран не чаще
+                <select name="repaint_throttle_ms" data-type="number">
+                    <option value="0">Сразу</option>
+                    <option value="5000">Раза в 5 секунд</option>
+                    <option value="15000">Раза в 15 секунд</option>
+                    <option value="30000">Раза в 30 секунд</option>
+                    <option value="60000">Раза в минуту</option>
+                </select>
+            </label>
+            <label>Не разрывать абзацы
+                <input type="checkbox" name="avoid_paragraph_breaks">
+            </label>
+            <label>Заголовок из первой строки
+                <input type="checkbox" name="auto_title">
+            </label>
+            <label>Сжимать страницу для медленного Wi-Fi
+                <input type="checkbox" name="minify_html">
+            </label>
+        </form>
+        <button type="button" id="menu-close">Закрыть</button>
+    </div>
+    
+    <script>
+        let currentPage = 0;
+        let totalPages = 0;
+        let columnsPerPage = 1;
+        let currentHash = "{{ initial_hash }}";
+        // Время последнего изменения текста на компьютере (мс Unix), 0 — неизвестно.
+        let updatedAt = {{ updated_at }};
+        let isUpdating = false;
+        // Токен доступа из адреса страницы, если сервер его требует (KI_REQUIRE_TOKEN).
+        const ACCESS_TOKEN = "{{ access_token }}";
+        // Диапазон страниц из адреса (?from=N&to=M, с единицы): листание не выходит
+        // за его пределы. Номера за пределами документа прижимаются к его краям.
+        const PAGE_RANGE = {{ page_range }};
+        // Ограничение частоты перерисовок: обновления, пришедшие раньше срока,
+        // откладываются, и по истечении интервала показывается последнее из них.
+        let repaintThrottleMs = 0;
+        let lastRepaintAt = Date.now();
+        let pendingContent = null;
+        let pendingRepaintTimeout = null;
+
+        const wrapper = document.getElementById('content-wrapper');
+        const container = document.getElementById('content-container');
+        const pageIndicator = document.getElementById('page-indicator');
+        const updatedLabel = document.getElementById('updated-label');
+        const pageCounter = document.getElementById('page-counter');
+        const progressFill = document.getElementById('progress-fill');
+        const pageJump = document.getElementById('page-jump');
+        const pageJumpInput = document.getElementById('page-jump-input');
+        const fontSmaller = document.getElementById('font-smaller');
+        const fontLarger = document.getElementById('font-larger');
+        const themeSelect = document.getElementById('theme-select');
+        const alignToggle = document.getElementById('align-toggle');
+        const columnsToggle = document.getElementById('columns-toggle');
+        const marginSelect = document.getElementById('margin-select');
+        const readerMenu = document.getElementById('reader-menu');
+        const menuToc = document.getElementById('menu-toc');
+        const menuSettings = document.getElementById('menu-settings');
+        
+        // Таблицы и абзацы выше страницы разрешено разрывать между страницами, а блоки,
+        // которые реально прокручиваются по горизонтали, получают видимую отметку.
+        function markScrollableBlocks() {
+            container.querySelectorAll('table, p').forEach((block) => {
+                block.classList.remove('is-tall');
+                if (block.offsetHeight > wrapper.clientHeight) {
+                    block.classList.add('is-tall');
+                }
+            });
+            container.querySelectorAll(SCROLLABLE_BLOCKS).forEach((block) => {
+                const scrolls = block.scrollWidth > block.clientWidth;
+                block.classList.toggle('is-scrollable', scrolls && !block.classList.contains('is-tall'));
+            });
+        }
+
+        function updateLayout() {
+            markScrollableBlocks();
+
+            // Считаем колонки, а не экраны: при двух колонках на экран фиктивная колонка
+            // занимает только половину последнего экрана. Math.ceil нужен, потому что
+            // последняя колонка может быть заполнена лишь частично.
+            const columnWidth = wrapper.clientWidth / columnsPerPage;
+            const realTotalColumns = Math.ceil(container.scrollWidth / columnWidth);
+
+            // Количество страниц для пользователя: колонки без фиктивной, по columnsPerPage на экран.
+            totalPages = Math.max(1, Math.ceil((realTotalColumns - 1) / columnsPerPage));
+
+            // Ограничиваем currentPage, чтобы пользователь не мог перейти на фиктивную страницу.
+            currentPage = clampToRange(currentPage);
+            
+            updateUi();
+        }
+
+        function updateUi() {
+            updateUpdatedLabel();
+            if (totalPages > 0) {
+                pageCounter.textContent = `Страница ${currentPage + 1} из ${totalPages}`;
+                progressFill.style.width = `${((currentPage + 1) / totalPages) * 100}%`;
+                
+                // Больше никаких сложных формул!
+                // Просто прокручиваем на N экранов. Браузер сам справится с позиционированием.
+                const scrollLeftPosition = currentPage * wrapper.clientWidth;
+
+                wrapper.scrollTo({
+                    left: scrollLeftPosition,
+                    behavior: 'auto'
+                });
+            } else {
+                pageCounter.textContent = 'Нет страниц';
+                progressFill.style.width = '0';
+            }
+        }
+
+        // Подпись «обновлено N минут назад». Обновляется при листании и при новом
+        // содержимом, а не по таймеру, чтобы экран E-Ink не перерисовывался зря.
+        function updateUpdatedLabel() {
+            updatedLabel.textContent = updatedAt ? `обновлено ${timeAgo(Date.now() - updatedAt)}` : '';
+        }
+
+        function timeAgo(milliseconds) {
+            const minutes = Math.floor(milliseconds / 60000);
+            if (minutes < 1) return 'только что';
+            if (minutes < 60) return `${minutes} ${plural(minutes, 'минуту', 'минуты', 'минут')} назад`;
+            const hours = Math.floor(minutes / 60);
+            if (hours < 24) return `${hours} ${plural(hours, 'час', 'часа', 'часов')} назад`;
+            const days = Math.floor(hours / 24);
+            return `${days} ${plural(days, 'день', 'дня', 'дней')} назад`;
+        }
+
+        function plural(count, one, few, many) {
+            const lastTwo = count % 100;
+            const last = count % 10;
+            if (lastTwo >= 11 && lastTwo <= 14) return many;
+            if (last === 1) return one;
+            if (last >= 2 && last <= 4) return few;
+            return many;
+        }
+
+        function firstPage() {
+            return Math.max(0, Math.min((PAGE_RANGE.from || 1) - 1, totalPages - 1));
+        }
+
+        function lastPage() {
+            const last = PAGE_RANGE.to ? PAGE_RANGE.to - 1 : totalPages - 1;
+            return Math.max(firstPage(), Math.min(last, totalPages - 1));
+        }
+
+        function clampToRange(pageIndex) {
+            return Math.max(firstPage(), Math.min(pageIndex, lastPage()));
         }
 
         function showPage(pageIndex) {
-            if (isUpdating || pageIndex < 0 || pageIndex >= totalPages) return;
+            if (isUpdating || pageIndex < firstPage() || pageIndex > lastPage()) return;
             currentPage = pageIndex;
             updateUi();
+            savePosition();
+            reportProgress();
+        }
+
+        // Сообщает приложению на компьютере, на какой странице читалка. Ошибки не
+        // важны: следующее перелистывание сообщит страницу снова.
+        function reportProgress() {
+            if (!currentHash || totalPages < 1) return;
+            fetch(withToken('/api/progress'), {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ hash: currentHash, page: currentPage + 1, total: totalPages }),
+            }).catch(() => {});
+        }
+
+        function withToken(url) {
+            if (!ACCESS_TOKEN) return url;
+            return url + (url.includes('?') ? '&' : '?') + 'token=' + encodeURIComponent(ACCESS_TOKEN);
+        }
+
+        // Последняя страница каждого документа хранится в localStorage по хэшу контента,
+        // чтобы перезагрузка страницы в браузере читалки не сбрасывала позицию.
+        const POSITION_KEY_PREFIX = 'ki-position:';
+        const MAX_SAVED_POSITIONS = 50;
+
+        function savedPage(hash) {
+            try {
+                const saved = JSON.parse(localStorage.getItem(POSITION_KEY_PREFIX + hash));
+                return saved && Number.isInteger(saved.page) ? saved.page : 0;
+            } catch (error) {
+                return 0;
+            }
+        }
+
+        // Страница, с которой открывается документ: сохраненная, если она входит в
+        // диапазон из адреса, иначе его начало.
+        function startPage(hash) {
+            const page = savedPage(hash);
+            if (!PAGE_RANGE.from) return page;
+            const inRange = page >= PAGE_RANGE.from - 1 && (!PAGE_RANGE.to || page <= PAGE_RANGE.to - 1);
+            return inRange ? page : PAGE_RANGE.from - 1;
+        }
+
+        function savePosition() {
+            try {
+                localStorage.setItem(
+                    POSITION_KEY_PREFIX + currentHash,
+                    JSON.stringify({ page: currentPage, time: Date.now() })
+                );
+                pruneSavedPositions();
+            } catch (error) {
+                // localStorage может быть недоступен или переполнен; позиция просто не сохранится.
+            }
+        }
+
+        function pruneSavedPositions() {
+            const positions = [];
+            for (let i = 0; i < localStorage.length; i++) {
+                const key = localStorage.key(i);
+                if (!key || !key.startsWith(POSITION_KEY_PREFIX)) continue;
+                let time = 0;
+                try {
+                    time = JSON.parse(localStorage.getItem(key)).time || 0;
+                } catch (error) {}
+                positions.push({ key, time });
+            }
+            positions.sort((a, b) => b.time - a.time);
+            positions.slice(MAX_SAVED_POSITIONS).forEach(({ key }) => localStorage.removeItem(key));
+        }
+
+        // Блоки, которые прокручиваются по горизонтали сами по себе.
+        const SCROLLABLE_BLOCKS = 'pre, table';
+        let panBlock = null;
+        let panStartScroll = 0;
+
+        function rememberPanStart(event) {
+            panBlock = event.target.closest(SCROLLABLE_BLOCKS);
+            panStartScroll = panBlock ? panBlock.scrollLeft : 0;
+        }
+
+        // Клик, завершивший прокрутку блока кода или таблицы, не должен листать страницу.
+        function endsBlockPan(event) {
+            const block = event.target.closest(SCROLLABLE_BLOCKS);
+            return block !== null && block === panBlock && block.scrollLeft !== panStartScroll;
+        }
+
+        // Свайп листает страницу, если палец прошел по горизонтали не меньше
+        // SWIPE_MIN_DISTANCE и заметно больше, чем по вертикали.
+        const SWIPE_MIN_DISTANCE = 50;
+        let swipeStart = null;
+        let swiped = false;
+
+        function rememberSwipeStart(event) {
+            swiped = false;
+            const ignored = event.touches.length !== 1
+                || event.target.closest(`#ui-bar, #reader-menu, ${SCROLLABLE_BLOCKS}`);
+            swipeStart = ignored ? null : { x: event.touches[0].clientX, y: event.touches[0].clientY };
+        }
+
+        function finishSwipe(event) {
+            if (!swipeStart || !readerMenu.hidden) return;
+            const touch = event.changedTouches[0];
+            const dx = touch.clientX - swipeStart.x;
+            const dy = touch.clientY - swipeStart.y;
+            swipeStart = null;
+            if (Math.abs(dx) < SWIPE_MIN_DISTANCE || Math.abs(dx) < 2 * Math.abs(dy)) return;
+
+            // Некоторые браузеры после касания присылают click — он не должен листать еще раз.
+            swiped = true;
+            showPage(currentPage + (dx < 0 ? 1 : -1));
         }
 
         function setupNavigation() {
+            document.body.addEventListener('mousedown', rememberPanStart);
+            document.body.addEventListener('touchstart', rememberPanStart, { passive: true });
+            document.body.addEventListener('touchstart', rememberSwipeStart, { passive: true });
+            document.body.addEventListener('touchend', finishSwipe, { passive: true });
+
             document.body.addEventListener('click', (event) => {
-                if (event.target.closest('#ui-bar') || event.button !== 0) return;
-                
+                // Нажатие на заголовок <summary> раскрывает раздел, а не листает страницу.
+                if (event.target.closest('#ui-bar, #reader-menu, summary') || event.button !== 0) return;
+                if (endsBlockPan(event)) return;
+                if (swiped) {
+                    swiped = false;
+                    return;
+                }
+
+                // Пока меню открыто, нажатие мимо него только закрывает меню.
+                if (!readerMenu.hidden) {
+                    closeMenu();
+                    return;
+                }
+
+                // Ссылки внутри документа (на иллюстрации и обратно) открывают страницу
+                // с целью, а не прокручивают контейнер мимо пагинации.
+                const anchor = event.target.closest("a[href^='#']");
+                if (anchor && container.contains(anchor)) {
+                    event.preventDefault();
+                    const target = document.getElementById(decodeURIComponent(anchor.hash.slice(1)));
+                    if (target) showPage(Math.min(pageOf(target), totalPages - 1));
+                    return;
+                }
+
+                // Экран делится на три зоны: левая листает назад, правая вперед,
+                // средняя открывает меню.
                 const rect = document.body.getBoundingClientRect();
-                if (event.clientX > rect.left + rect.width / 2) {
+                const zone = Math.floor(((event.clientX - rect.left) / rect.width) * TAP_ZONES);
+                if (zone <= 0) {
+                    showPage(currentPage - 1);
+                } else if (zone >= TAP_ZONES - 1) {
                     showPage(currentPage + 1);
                 } else {
-                    showPage(currentPage - 1);
+                    openMenu();
+                }
+            });
+
+            // Кнопки листания на многих читалках приходят как нажатия клавиш.
+            document.addEventListener('keydown', (event) => {
+                if (event.altKey || event.ctrlKey || event.metaKey) return;
+                // Клавиши в полях ввода (номер страницы, настройки) не листают.
+                if (event.target.closest('input, select, textarea') || !readerMenu.hidden) return;
+                const target = pageForKey(event);
+                if (target === null) return;
+                // Пробел и PageDown иначе прокрутили бы контейнер мимо пагинации.
+                event.preventDefault();
+                // Во время обновления содержимого showPage ничего не делает.
+                showPage(target);
+            });
+        }
+
+        // Страница, на которую ведет клавиша, или null, если клавиша не листает.
+        function pageForKey(event) {
+            switch (event.key) {
+                case 'ArrowRight':
+                case 'PageDown':
+                    return currentPage + 1;
+                case 'ArrowLeft':
+                case 'PageUp':
+                    return currentPage - 1;
+                case ' ':
+                    return currentPage + (event.shiftKey ? -1 : 1);
+                case 'Home':
+                    return firstPage();
+                case 'End':
+                    return lastPage();
+                default:
+                    return null;
+            }
+        }
+
+        const TAP_ZONES = 3;
+
+        // Номер страницы, на которой начинается элемент внутри колонок.
+        function pageOf(element) {
+            const offset = element.getBoundingClientRect().left - wrapper.getBoundingClientRect().left;
+            return Math.max(0, Math.floor((offset + wrapper.scrollLeft) / wrapper.clientWidth));
+        }
+
+        function buildToc() {
+            menuToc.replaceChildren();
+            container.querySelectorAll('h1, h2, h3').forEach((heading) => {
+                const page = pageOf(heading);
+                const link = document.createElement('a');
+                link.href = `#${heading.id}`;
+                link.className = `toc-level-${heading.tagName.substring(1)}`;
+                link.textContent = `${heading.textContent} — ${page + 1}`;
+                link.addEventListener('click', (event) => {
+                    event.preventDefault();
+                    closeMenu();
+                    showPage(Math.min(page, totalPages - 1));
+                });
+                menuToc.appendChild(link);
+            });
+            if (!menuToc.firstChild) {
+                menuToc.textContent = 'В документе нет заголовков.';
+            }
+        }
+
+        function fillSettingsForm(settings) {
+            menuSettings.elements.paragraph_style.value = settings.paragraph_style || 'spaced';
+            menuSettings.elements.progress_style.value = settings.progress_style || 'text';
+            menuSettings.elements.font_weight.value = settings.font_weight || 'normal';
+            menuSettings.elements.show_ui_bar.checked = settings.show_ui_bar !== false;
+            menuSettings.elements.avoid_paragraph_breaks.checked = settings.avoid_paragraph_breaks === true;
+            menuSettings.elements.auto_title.checked = settings.auto_title !== false;
+            menuSettings.elements.minify_html.checked = settings.minify_html === true;
+            menuSettings.elements.columns_per_page.value = String(settings.columns_per_page || 1);
+            menuSettings.elements.image_mode.value = settings.image_mode || 'inline';
+            menuSettings.elements.repaint_throttle_ms.value = String(settings.repaint_throttle_ms || 0);
+        }
+
+        function openMenu() {
+            closePageJump();
+            buildToc();
+            readerMenu.hidden = false;
+        }
+
+        function closeMenu() {
+            readerMenu.hidden = true;
+        }
+
+        function setupMenu() {
+            document.getElementById('menu-close').addEventListener('click', closeMenu);
+
+            menuSettings.addEventListener('change', async (event) => {
+                const field = event.target;
+                let value = field.type === 'checkbox' ? field.checked : field.value;
+                if (field.dataset.type === 'number') value = Number(value);
+                try {
+                    const response = await fetch(withToken('/api/settings'), {
+                        method: 'POST',
+                        headers: { 'Content-Type': 'application/json' },
+                        body: JSON.stringify({ [field.name]: value }),
+                    });
+                    if (!response.ok) return;
+                    applySettings((await response.json()).values);
+                    if (field.name === 'image_mode' || field.name === 'auto_title') {
+                        // Изображения и заголовок формирует сервер: перезагружаем контент. Хэш
+                        // при этом не меняется, поэтому позиция восстановится из localStorage.
+                        savePosition();
+                        currentHash = null;
+                        await checkForUpdates(true);
+                    } else {
+                        updateLayout();
+                    }
+                } catch (error) {
+                    console.error('Ошибка при сохранении настроек:', error);
+                }
+            });
+        }
+
+        function openPageJump() {
+            if (totalPages <= 1) return;
+            pageJumpInput.max = totalPages;
+            pageJumpInput.value = currentPage + 1;
+            pageIndicator.hidden = true;
+            pageJump.hidden = false;
+            pageJumpInput.focus();
+            pageJumpInput.select();
+        }
+
+        function closePageJump() {
+            pageJump.hidden = true;
+            pageIndicator.hidden = false;
+        }
+
+        function setupPageJump() {
+            pageIndicator.addEventListener('click', openPageJump);
+
+            pageJump.addEventListener('submit', (event) => {
+                event.preventDefault();
+                const pageNumber = parseInt(pageJumpInput.value, 10);
+                if (isNaN(pageNumber) || pageNumber < 1 || pageNumber > totalPages) {
+                    // Оставляем форму открытой, чтобы можно было исправить номер.
+                    pageJumpInput.select();
+                    return;
+                }
+                closePageJump();
+                showPage(pageNumber - 1);
+            });
+
+            pageJumpInput.addEventListener('keydown', (event) => {
+                if (event.key === 'Escape') closePageJump();
+            });
+        }
+
+        // Размер шрифта выбирается на каждом устройстве отдельно и хранится в localStorage.
+        // Шаги те же, что в оценке числа страниц в приложении.
+        const FONT_SIZE_KEY = 'ki-font-size';
+        const FONT_SIZES = {{ font_scales }};
+        const DEFAULT_FONT_SIZE = 1.3;
+        let fontSizeIndex = Math.max(0, FONT_SIZES.indexOf(DEFAULT_FONT_SIZE));
+
+        function applyFontSize() {
+            container.style.fontSize = `${FONT_SIZES[fontSizeIndex]}em`;
+            fontSmaller.disabled = fontSizeIndex === 0;
+            fontLarger.disabled = fontSizeIndex === FONT_SIZES.length - 1;
+        }
+
+        function loadFontSize() {
+            try {
+                const saved = FONT_SIZES.indexOf(Number(localStorage.getItem(FONT_SIZE_KEY)));
+                if (saved >= 0) fontSizeIndex = saved;
+            } catch (error) {
+                // Без localStorage используется размер по умолчанию.
+            }
+            applyFontSize();
+        }
+
+        // Применяет изменение оформления, от которого зависит число страниц, и
+        // остается на том же месте текста в процентах.
+        function relayoutKeepingPlace(applyChange) {
+            const progress = totalPages > 1 ? currentPage / (totalPages - 1) : 0;
+            applyChange();
+            updateLayout();
+            currentPage = clampToRange(Math.round(progress * (totalPages - 1)));
+            updateUi();
+            savePosition();
+        }
+
+        function changeFontSize(step) {
+            const index = Math.max(0, Math.min(fontSizeIndex + step, FONT_SIZES.length - 1));
+            if (isUpdating || index === fontSizeIndex) return;
+
+            relayoutKeepingPlace(() => {
+                fontSizeIndex = index;
+                applyFontSize();
+                try {
+                    localStorage.setItem(FONT_SIZE_KEY, String(FONT_SIZES[fontSizeIndex]));
+                } catch (error) {
+                    // Размер просто не сохранится.
+                }
+            });
+        }
+
+        function setupFontSize() {
+            fontSmaller.addEventListener('click', () => changeFontSize(-1));
+            fontLarger.addEventListener('click', () => changeFontSize(1));
+        }
+
+        // Выравнивание по ширине на узких колонках оставляет широкие пробелы, поэтому
+        // его можно заменить выравниванием по левому краю. Хранится на устройстве.
+        const ALIGN_KEY = 'ki-align';
+        let justified = true;
+
+        function applyAlignment() {
+            container.classList.toggle('ragged', !justified);
+            alignToggle.textContent = justified ? 'По ширине' : 'Слева';
+            alignToggle.title = justified
+                ? 'Текст выровнен по ширине. Выровнять по левому краю'
+                : 'Текст выровнен по левому краю. Выровнять по ширине';
+        }
+
+        function loadAlignment() {
+            try {
+                justified = localStorage.getItem(ALIGN_KEY) !== 'left';
+            } catch (error) {
+                // Без localStorage текст выравнивается по ширине.
+            }
+            applyAlignment();
+        }
+
+        function setupAlignment() {
+            alignToggle.addEventListener('click', () => {
+                if (isUpdating) return;
+                relayoutKeepingPlace(() => {
+                    justified = !justified;
+                    applyAlignment();
+                    try {
+                        localStorage.setItem(ALIGN_KEY, justified ? 'justify' : 'left');
+                    } catch (error) {
+                        // Выравнивание просто не сохранится.
+                    }
+                });
+            });
+        }
+
+        // Поля страницы зависят от устройства и того, как его держат, поэтому тоже
+        // хранятся на устройстве.
+        const MARGIN_KEY = 'ki-margin';
+        const MARGINS = ['narrow', 'normal', 'wide'];
+        const DEFAULT_MARGIN = 'normal';
+
+        function applyMargin(margin) {
+            if (margin === DEFAULT_MARGIN) {
+                delete container.dataset.margin;
+            } else {
+                container.dataset.margin = margin;
+            }
+            marginSelect.value = margin;
+        }
+
+        function loadMargin() {
+            let margin = DEFAULT_MARGIN;
+            try {
+                const saved = localStorage.getItem(MARGIN_KEY);
+                if (MARGINS.includes(saved)) margin = saved;
+            } catch (error) {
+                // Без localStorage используются обычные поля.
+            }
+            applyMargin(margin);
+        }
+
+        function setupMargin() {
+            marginSelect.addEventListener('change', () => {
+                const margin = MARGINS.includes(marginSelect.value) ? marginSelect.value : DEFAULT_MARGIN;
+                if (isUpdating) {
+                    marginSelect.value = container.dataset.margin || DEFAULT_MARGIN;
+                    return;
+                }
+                relayoutKeepingPlace(() => {
+                    applyMargin(margin);
+                    try {
+                        localStorage.setItem(MARGIN_KEY, margin);
+                    } catch (error) {
+                        // Поля просто не сохранятся.
+                    }
+                });
+            });
+        }
+
+        // Число колонок — общая настройка читалки, как и в меню; кнопка в панели лишь
+        // переключает ее, сохраняя место в тексте.
+        function setupColumns() {
+            columnsToggle.addEventListener('click', async () => {
+                if (isUpdating) return;
+                try {
+                    const response = await fetch(withToken('/api/settings'), {
+                        method: 'POST',
+                        headers: { 'Content-Type': 'application/json' },
+                        body: JSON.stringify({ columns_per_page: columnsPerPage === 2 ? 1 : 2 }),
+                    });
+                    if (!response.ok) return;
+                    const values = (await response.json()).values;
+                    relayoutKeepingPlace(() => applySettings(values));
+                } catch (error) {
+                    console.error('Ошибка при сохранении настроек:', error);
+                }
+            });
+        }
+
+        // Тема, как и размер шрифта, выбирается на каждом устройстве отдельно.
+        const THEME_KEY = 'ki-theme';
+        const THEMES = ['light', 'sepia', 'contrast', 'dark'];
+        const DEFAULT_THEME = 'light';
+
+        function applyTheme(theme) {
+            if (theme === DEFAULT_THEME) {
+                delete document.documentElement.dataset.theme;
+            } else {
+                document.documentElement.dataset.theme = theme;
+            }
+            themeSelect.value = theme;
+        }
+
+        function loadTheme() {
+            let theme = DEFAULT_THEME;
+            try {
+                const saved = localStorage.getItem(THEME_KEY);
+                if (THEMES.includes(saved)) theme = saved;
+            } catch (error) {
+                // Без localStorage используется светлая тема.
+            }
+            applyTheme(theme);
+        }
+
+        function setupTheme() {
+            themeSelect.addEventListener('change', () => {
+                const theme = THEMES.includes(themeSelect.value) ? themeSelect.value : DEFAULT_THEME;
+                applyTheme(theme);
+                try {
+                    localStorage.setItem(THEME_KEY, theme);
+                } catch (error) {
+                    // Тема просто не сохранится.
                 }
             });
         }
 
-        async function checkForUpdates() {
-            if (isUpdating) return;
-            try {
-                const response = await fetch(`/api/content?_=${new Date().getTime()}`);
-                if (!response.ok) return;
-                const data = await response.json();
-                
-                if (data.hash !== currentHash) {
-                    isUpdating = true;
-                    console.log("Получено обновление контента. Новый хэш:", data.hash);
-                    currentHash = data.hash;
-                    
-                    container.innerHTML = data.html;
-                    
-                    setTimeout(() => {
-                        currentPage = 0; // Сброс на первую страницу при обновлении
-                        updateLayout();
-                        isUpdating = false;
-                    }, 100); 
-                }
-            } catch (error) {
-                console.error('Ошибка при проверке обновлений:', error);
-                isUpdating = false;
+        function applySettings(settings) {
+            container.classList.toggle('paragraphs-indented', settings.paragraph_style === 'indented');
+            pageIndicator.className = `progress-${settings.progress_style || 'text'}`;
+            document.body.classList.toggle('ui-bar-hidden', settings.show_ui_bar === false);
+            container.classList.toggle('avoid-paragraph-breaks', settings.avoid_paragraph_breaks === true);
+            // Число колонок меняет формулу подсчета страниц в updateLayout.
+            columnsPerPage = settings.columns_per_page === 2 ? 2 : 1;
+            container.classList.toggle('columns-2', columnsPerPage === 2);
+            columnsToggle.textContent = columnsPerPage === 2 ? '2 колонки' : '1 колонка';
+            // Жирность меняет ширину строк; вызывающий код пересчитывает страницы после применения.
+            container.dataset.fontWeight = settings.font_weight || 'normal';
+            repaintThrottleMs = Number(settings.repaint_throttle_ms) || 0;
+            fillSettingsForm(settings);
+        }
+
+        async function loadSettings() {
+            try {
+                const response = await fetch(withToken(`/api/settings?_=${new Date().getTime()}`));
+                if (!response.ok) return;
+                // Ответ описывает все настройки; читалке нужны только текущие значения.
+                applySettings((await response.json()).values);
+            } catch (error) {
+                console.error('Ошибка при загрузке настроек:', error);
+            }
+        }
+
+        async function repaint(data) {
+            isUpdating = true;
+            lastRepaintAt = Date.now();
+            console.log("Получено обновление контента. Новый хэш:", data.hash);
+            currentHash = data.hash;
+            updatedAt = data.updated_at || 0;
+
+            await loadSettings();
+            container.innerHTML = data.html;
+            if (data.title) document.title = data.title;
+
+            const previousPage = currentPage;
+            setTimeout(() => {
+                // Новый документ открывается с начала, уже читанный — с сохраненной страницы.
+                // Текст, дописанный в конец, не сдвигает прочитанное: остаемся на месте.
+                currentPage = data.appended ? previousPage : startPage(currentHash);
+                updateLayout();
+                isUpdating = false;
+                reportProgress();
+            }, 100);
+        }
+
+        function cancelPendingRepaint() {
+            clearTimeout(pendingRepaintTimeout);
+            pendingRepaintTimeout = null;
+            pendingContent = null;
+        }
+
+        // Показывает отложенное обновление, даже если новых изменений больше не было.
+        async function flushPendingRepaint() {
+            if (isUpdating) {
+                pendingRepaintTimeout = setTimeout(flushPendingRepaint, 100);
+                return;
+            }
+            const data = pendingContent;
+            cancelPendingRepaint();
+            if (data && data.hash !== currentHash) {
+                try {
+                    await repaint(data);
+                } catch (error) {
+                    console.error('Ошибка при обновлении контента:', error);
+                    isUpdating = false;
+                }
+            }
+        }
+
+        // force: перерисовать сразу, без учета repaint_throttle_ms (по действию пользователя).
+        async function checkForUpdates(force = false) {
+            if (isUpdating) return;
+            try {
+                // Сначала спрашиваем только хеш: полный HTML загружается, лишь когда он изменился.
+                if (!force) {
+                    const hashResponse = await fetch(withToken(`/api/hash?_=${new Date().getTime()}`));
+                    if (!hashResponse.ok) return;
+                    const hash = await hashResponse.text();
+                    if (hash === currentHash) {
+                        cancelPendingRepaint();
+                        return;
+                    }
+                    // Отложенная перерисовка уже покажет этот текст.
+                    if (pendingContent && pendingContent.hash === hash) return;
+                }
+                const response = await fetch(withToken(`/api/content?_=${new Date().getTime()}`));
+                if (!response.ok) return;
+                await showContent(await response.json(), force);
+            } catch (error) {
+                console.error('Ошибка при проверке обновлений:', error);
+                isUpdating = false;
+            }
+        }
+
+        // Показывает контент из опроса или WebSocket с учетом repaint_throttle_ms.
+        async function showContent(data, force = false) {
+            if (data.hash === currentHash) {
+                // Контент вернулся к показанному: отложенная перерисовка не нужна.
+                cancelPendingRepaint();
+                return;
+            }
+
+            const wait = force ? 0 : lastRepaintAt + repaintThrottleMs - Date.now();
+            if (wait <= 0 && !isUpdating) {
+                cancelPendingRepaint();
+                await repaint(data);
+                return;
+            }
+
+            // Во время перерисовки обновление не теряется: flushPendingRepaint дождется ее конца.
+            pendingContent = data;
+            if (pendingRepaintTimeout === null) {
+                pendingRepaintTimeout = setTimeout(flushPendingRepaint, Math.max(wait, 100));
+            }
+        }
+
+        // Обновления приходят через WebSocket /ws; пока соединения нет (старый браузер,
+        // обрыв связи), контент запрашивается раз в 3 секунды, а подключение повторяется.
+        const POLL_INTERVAL_MS = 3000;
+        const WS_RETRY_MS = 30000;
+        let pollInterval = null;
+
+        function startPolling() {
+            if (pollInterval === null) {
+                pollInterval = setInterval(() => checkForUpdates(), POLL_INTERVAL_MS);
+            }
+        }
+
+        function stopPolling() {
+            clearInterval(pollInterval);
+            pollInterval = null;
+        }
+
+        function connectUpdates() {
+            if (!('WebSocket' in window)) {
+                startPolling();
+                return;
+            }
+            let socket;
+            try {
+                const protocol = location.protocol === 'https:' ? 'wss:' : 'ws:';
+                socket = new WebSocket(withToken(`${protocol}//${location.host}/ws`));
+            } catch (error) {
+                console.error('WebSocket недоступен:', error);
+                startPolling();
+                return;
+            }
+            socket.onopen = () => stopPolling();
+            socket.onmessage = async (event) => {
+                try {
+                    await showContent(JSON.parse(event.data));
+                } catch (error) {
+                    console.error('Ошибка при обновлении контента:', error);
+                    isUpdating = false;
+                }
+            };
+            socket.onclose = () => {
+                startPolling();
+                setTimeout(connectUpdates, WS_RETRY_MS);
+            };
+        }
+
+        function initialize(initialSettings) {
+            // Контент уже отрендерен сервером в #content-container, чтобы страница
+            // читалась и в браузерах без JavaScript.
+            isUpdating = true;
+            applySettings(initialSettings);
+            loadTheme();
+            loadFontSize();
+            loadAlignment();
+            loadMargin();
+            currentPage = startPage(currentHash);
+            
+            setTimeout(() => {
+                updateLayout();
+                setupNavigation();
+                setupPageJump();
+                setupFontSize();
+                setupAlignment();
+                setupMargin();
+                setupColumns();
+                setupTheme();
+                setupMenu();
+                startPolling();
+                connectUpdates();
+                isUpdating = false;
+                reportProgress();
+            }, 100);
+
+            // Раскрытие или сворачивание <details> меняет длину текста. Событие toggle
+            // не всплывает, поэтому слушаем его на фазе перехвата.
+            container.addEventListener('toggle', () => {
+                if (!isUpdating) updateLayout();
+            }, true);
+
+            let resizeTimeout;
+            window.addEventListener('resize', () => {
+                clearTimeout(resizeTimeout);
+                resizeTimeout = setTimeout(updateLayout, 250);
+            });
+        }
+        
+        document.addEventListener('DOMContentLoaded', () => {
+            initialize({{ settings_json }});
+        });
+    </script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::process_markdown;
+    use crate::rate_limit::{RateLimiter, FETCH_RATE_WINDOW};
+    use crate::state::AppState;
+    use axum::{body::Body, http::Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt; // for `oneshot`
+
+    // Helper to build the app router for testing
+    fn test_app_router() -> Router {
+        let app_state = Arc::new(AppState::default());
+        Router::new()
+            .route("/get", get(get_page_handler))
+            .route("/api/content", get(api_content_handler))
+            .with_state(app_state)
+    }
+
+    #[tokio::test]
+    async fn api_content_handler_returns_no_cache_headers() {
+        let app = test_app_router();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/content")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CACHE_CONTROL).unwrap(),
+            "no-cache, no-store, must-revalidate"
+        );
+        assert_eq!(response.headers().get(PRAGMA).unwrap(), "no-cache");
+        assert_eq!(response.headers().get(EXPIRES).unwrap(), "0");
+    }
+
+    #[tokio::test]
+    async fn get_page_handler_returns_no_cache_headers() {
+        let app = test_app_router();
+
+        let response = app
+            .oneshot(Request::builder().uri("/get").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CACHE_CONTROL).unwrap(),
+            "no-cache, no-store, must-revalidate"
+        );
+        assert_eq!(response.headers().get(PRAGMA).unwrap(), "no-cache");
+        assert_eq!(response.headers().get(EXPIRES).unwrap(), "0");
+    }
+
+    #[tokio::test]
+    async fn get_page_handler_renders_content_for_browsers_without_js() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() =
+            "Текст {{ settings_json }} *как есть*".to_string();
+        let app = build_router(app_state);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/get").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains(
+            "<div id=\"content-container\"><p>Текст {{ settings_json }} <em>как есть</em></p></div>"
+        ));
+        assert!(page.contains("<noscript>"));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/get?nojs=1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("<em>как есть</em>"));
+        assert!(!page.contains("<script"));
+    }
+
+    #[tokio::test]
+    async fn get_page_handler_clamps_page_range_instead_of_rejecting_it() {
+        let app = build_router(Arc::new(AppState::default()));
+        for (query, expected) in [
+            ("", "const PAGE_RANGE = {\"from\":null,\"to\":null};"),
+            (
+                "?from=0&to=abc",
+                "const PAGE_RANGE = {\"from\":1,\"to\":null};",
+            ),
+            ("?from=5&to=3", "const PAGE_RANGE = {\"from\":5,\"to\":5};"),
+            ("?from=2&to=7", "const PAGE_RANGE = {\"from\":2,\"to\":7};"),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(format!("/get{query}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let page = String::from_utf8(body.to_vec()).unwrap();
+            assert!(page.contains(expected), "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn toggling_scratch_switches_served_content_and_back() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Статья".to_string();
+        let app = build_router(app_state.clone());
+        let fetch_content = |app: Router| async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/content")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<ContentResponse>(&body).unwrap()
+        };
+
+        let article = fetch_content(app.clone()).await;
+        assert!(app_state.toggle_scratch().unwrap());
+        *app_state.shared_text.write().unwrap() = "Заметка".to_string();
+        let scratch = fetch_content(app.clone()).await;
+        assert_ne!(scratch.hash, article.hash);
+        assert!(scratch.html.contains("Заметка"));
+
+        // Switching counts as a change, so only the text is compared.
+        assert!(!app_state.toggle_scratch().unwrap());
+        let restored = fetch_content(app.clone()).await;
+        assert_eq!((restored.html, restored.hash), (article.html, article.hash));
+        assert!(app_state.toggle_scratch().unwrap());
+        let restored = fetch_content(app).await;
+        assert_eq!((restored.html, restored.hash), (scratch.html, scratch.hash));
+    }
+
+    #[tokio::test]
+    async fn get_page_handler_serves_wide_tables_as_scrollable_blocks() {
+        let header: Vec<String> = (1..=12).map(|i| format!("Колонка {}", i)).collect();
+        let row: Vec<String> = (1..=12)
+            .map(|i| format!("значение_{}_без_пробелов", i))
+            .collect();
+        let table = format!(
+            "| {} |\n|{}\n| {} |",
+            header.join(" | "),
+            "---|".repeat(12),
+            row.join(" | ")
+        );
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = table;
+
+        let response = build_router(app_state)
+            .oneshot(Request::builder().uri("/get").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(page.contains("<th>Колонка 12</th>"));
+        assert!(page.contains("<td>значение_12_без_пробелов</td>"));
+        assert!(page.contains("#content-container .is-scrollable"));
+        assert!(page.contains("#content-container table.is-tall"));
+        assert!(page.contains("markScrollableBlocks();"));
+    }
+
+    #[tokio::test]
+    async fn concurrency_limited_router_serves_request_burst() {
+        let app_state = AppState {
+            max_concurrent_requests: 2,
+            ..AppState::default()
+        };
+        let app = build_router(Arc::new(app_state));
+
+        let mut requests = tokio::task::JoinSet::new();
+        for _ in 0..20 {
+            requests.spawn(
+                app.clone().oneshot(
+                    Request::builder()
+                        .uri("/api/content")
+                        .body(Body::empty())
+                        .unwrap(),
+                ),
+            );
+        }
+
+        while let Some(response) = requests.join_next().await {
+            assert_eq!(response.unwrap().unwrap().status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_is_shared_across_routes() {
+        let app_state = AppState {
+            max_concurrent_requests: 2,
+            ..AppState::default()
+        };
+        let app = build_router(Arc::new(app_state));
+
+        // Writes whose bodies never arrive keep their handlers, and their slots, busy.
+        let mut stalled = tokio::task::JoinSet::new();
+        for uri in ["/api/content", "/api/structured"] {
+            let body = Body::from_stream(futures_util::stream::pending::<
+                Result<Vec<u8>, std::io::Error>,
+            >());
+            stalled.spawn(
+                app.clone().oneshot(
+                    Request::builder()
+                        .method(Method::POST)
+                        .uri(uri)
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(body)
+                        .unwrap(),
+                ),
+            );
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let status = || {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(200), status())
+                .await
+                .is_err(),
+            "a request to a third route should wait for a free slot"
+        );
+
+        stalled.abort_all();
+        while stalled.join_next().await.is_some() {}
+        let response = tokio::time::timeout(std::time::Duration::from_secs(5), status())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn health_handler_answers_even_with_poisoned_text_lock() {
+        let app_state = Arc::new(AppState::default());
+        app_state.server_port.store(8181, Ordering::SeqCst);
+        let poisoner = app_state.clone();
+        std::thread::spawn(move || {
+            let _guard = poisoner.shared_text.write().unwrap();
+            panic!("poison the shared text lock");
+        })
+        .join()
+        .unwrap_err();
+        assert!(app_state.shared_text.is_poisoned());
+
+        let response = build_router(app_state)
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let health: HealthResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            health,
+            HealthResponse {
+                status: "ok".to_string(),
+                port: 8181,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn safe_mode_is_reported_and_blocks_url_fetching() {
+        let app_state = AppState {
+            safe_mode: true,
+            ..AppState::default()
+        };
+        let app = build_router(Arc::new(app_state));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let status: StatusResponse = serde_json::from_slice(&body).unwrap();
+        assert!(status.safe_mode);
+        assert!(!status.network_fetching);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/url")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"url":"https://example.com"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn api_url_rejects_malformed_url_with_message() {
+        let app_state = Arc::new(AppState::default());
+        let original_text = app_state.shared_text.read().unwrap().clone();
+
+        let response = build_router(app_state.clone())
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/url")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"url":"not a url"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert!(response.status().is_client_error());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let api_response: ApiResponse = serde_json::from_slice(&body).unwrap();
+        assert!(api_response.message.contains("Неверный URL"));
+        assert!(api_response.message.contains("not a url"));
+        assert_eq!(*app_state.shared_text.read().unwrap(), original_text);
+    }
+
+    #[tokio::test]
+    async fn api_url_rejects_fetches_over_the_rate_limit() {
+        let app_state = Arc::new(AppState {
+            fetch_limiter: RateLimiter::new(2, FETCH_RATE_WINDOW),
+            ..AppState::default()
+        });
+        let app = build_router(app_state);
+        let fetch = || {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/url")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"url":"not a url"}"#))
+                    .unwrap(),
+            )
+        };
+
+        // Failed fetches count as well, so a looping client cannot get around the limit.
+        for _ in 0..2 {
+            assert_eq!(fetch().await.unwrap().status(), StatusCode::BAD_REQUEST);
+        }
+        let response = fetch().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let api_response: ApiResponse = serde_json::from_slice(&body).unwrap();
+        assert!(api_response.message.contains("не больше 2 в минуту"));
+    }
+
+    #[tokio::test]
+    async fn api_url_rejects_pages_over_the_length_limit() {
+        let article = format!(
+            "<html><head><title>Long</title></head><body><article>{}</article></body></html>",
+            "<p>A long paragraph of article text that keeps going on and on.</p>".repeat(100)
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let site = Router::new().route("/article", get(move || async move { Html(article) }));
+        tokio::spawn(async move { axum::serve(listener, site).await.unwrap() });
+
+        let app_state = Arc::new(AppState::default());
+        app_state
+            .update_settings(|settings| settings.max_text_chars = 1_000)
+            .unwrap();
+        let response = build_router(app_state.clone())
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/url")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "url": format!("http://{}/article", addr) })
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(*app_state.shared_text.read().unwrap(), WELCOME_TEXT);
+    }
+
+    #[tokio::test]
+    async fn api_push_sets_text_from_query_and_form() {
+        let app_state = Arc::new(AppState::default());
+        let app = build_router(app_state.clone());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/push?text=%D0%9F%D1%80%D0%B8%D0%B2%D0%B5%D1%82%2C+%2Aworld%2A")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*app_state.shared_text.read().unwrap(), "Привет, *world*");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/push")
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from("text=from+a+form"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*app_state.shared_text.read().unwrap(), "from a form");
+        // Pushes are recorded for undo like any other replacement.
+        assert_eq!(app_state.undo().unwrap(), "Привет, *world*");
+
+        let stats = app_state.stats.report(0);
+        assert_eq!((stats.documents, stats.words, stats.fetches), (2, 5, 0));
+    }
+
+    #[tokio::test]
+    async fn api_push_rejects_overlong_text() {
+        let app_state = Arc::new(AppState::default());
+        app_state
+            .update_settings(|settings| settings.max_text_chars = 1_000)
+            .unwrap();
+        let app = build_router(app_state.clone());
+        let original = app_state.shared_text.read().unwrap().clone();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/push")
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from(format!("text={}", "a".repeat(1_001))))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(*app_state.shared_text.read().unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn api_settings_accepts_partial_update() {
+        let app = build_router(Arc::new(AppState::default()));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/settings")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        r#"{"paragraph_style":"indented","font_weight":"bold","avoid_paragraph_breaks":true,"columns_per_page":2}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/settings")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let values = &report["values"];
+        assert_eq!(values["paragraph_style"], "indented");
+        assert_eq!(values["font_weight"], "bold");
+        assert_eq!(values["avoid_paragraph_breaks"], true);
+        assert_eq!(values["columns_per_page"], 2);
+        assert_eq!(values["show_ui_bar"], true);
+    }
+
+    #[tokio::test]
+    async fn api_settings_lists_every_setting_with_options() {
+        let app = build_router(Arc::new(AppState::default()));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/settings")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let settings = report["settings"].as_array().unwrap();
+        let image_mode = settings
+            .iter()
+            .find(|setting| setting["key"] == "image_mode")
+            .unwrap();
+        assert_eq!(image_mode["scope"], "reader");
+        assert_eq!(image_mode["type"], "choice");
+        assert_eq!(image_mode["value"], "inline");
+        assert_eq!(
+            image_mode["options"],
+            serde_json::json!(["inline", "gallery", "hidden"])
+        );
+        let send_on_copy = settings
+            .iter()
+            .find(|setting| setting["key"] == "send_on_copy")
+            .unwrap();
+        assert_eq!(send_on_copy["scope"], "app");
+        assert_eq!(send_on_copy["type"], "bool");
+    }
+
+    #[tokio::test]
+    async fn api_settings_clamps_out_of_range_numbers() {
+        let app_state = Arc::new(AppState::default());
+        let response = build_router(app_state.clone())
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/settings")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"repaint_throttle_ms":86400000}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["values"]["repaint_throttle_ms"], 300_000);
+        assert_eq!(
+            report["clamped"],
+            serde_json::json!([
+                {"key": "repaint_throttle_ms", "requested": 86_400_000u64, "value": 300_000}
+            ])
+        );
+        assert_eq!(app_state.settings().reader.repaint_throttle_ms, 300_000);
+    }
+
+    #[tokio::test]
+    async fn api_settings_rejects_app_settings() {
+        let app_state = Arc::new(AppState::default());
+        let response = build_router(app_state.clone())
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/settings")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"send_on_copy":true}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!app_state.settings().send_on_copy);
+    }
+
+    #[tokio::test]
+    async fn api_settings_rejects_unknown_values() {
+        let app = build_router(Arc::new(AppState::default()));
+
+        for body in [
+            r#"{"paragraph_style":"zigzag"}"#,
+            r#"{"columns_per_page":3}"#,
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::POST)
+                        .uri("/api/settings")
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_reset_the_idle_timer() {
+        let app_state = Arc::new(AppState::default());
+        app_state.last_request_at.store(0, Ordering::Relaxed);
+        assert!(app_state.idle_duration().as_secs() > 60);
+
+        let response = build_router(app_state.clone())
+            .oneshot(
+                Request::builder()
+                    .uri("/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(app_state.idle_duration().as_secs() < 60);
+    }
+
+    #[tokio::test]
+    async fn api_content_reports_when_and_how_the_text_last_changed() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Первый абзац.".to_string();
+        let app = build_router(app_state.clone());
+        let save_and_fetch = |new_text: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .method(Method::POST)
+                            .uri("/api/content")
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(Body::from(
+                                serde_json::json!({ "new_text": new_text }).to_string(),
+                            ))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .uri("/api/content")
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<ContentResponse>(&body).unwrap()
+            }
+        };
+
+        app_state.updated_at.store(0, Ordering::SeqCst);
+        let content = save_and_fetch("Первый абзац.\n\nВторой абзац.").await;
+        assert!(content.appended);
+        assert!(content.updated_at > 0);
+        assert_eq!(
+            content.updated_at,
+            app_state.updated_at.load(Ordering::SeqCst)
+        );
+        let content = save_and_fetch("Совсем другой текст.").await;
+        assert!(!content.appended);
+
+        // Writers that replace the text reset the flag.
+        save_and_fetch("Совсем другой текст. Еще.").await;
+        app_state.toggle_scratch().unwrap();
+        let (Ok(content) | Err(content)) = current_content(&app_state);
+        assert!(!content.appended);
+    }
+
+    #[tokio::test]
+    async fn api_set_content_replaces_or_appends_by_mode() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "  \n".to_string();
+        let app = build_router(app_state.clone());
+        let post = |body: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method(Method::POST)
+                            .uri("/api/content")
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(Body::from(body.to_string()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+
+        // Appending to blank text adds no leading blank line.
+        post(serde_json::json!({ "new_text": "Первый.", "mode": "append" })).await;
+        assert_eq!(*app_state.shared_text.read().unwrap(), "Первый.");
+        post(serde_json::json!({ "new_text": "Второй.", "mode": "append" })).await;
+        assert_eq!(*app_state.shared_text.read().unwrap(), "Первый.\n\nВторой.");
+        assert!(app_state.last_change_appended.load(Ordering::SeqCst));
+
+        post(serde_json::json!({ "new_text": "Новый.", "mode": "replace" })).await;
+        assert_eq!(*app_state.shared_text.read().unwrap(), "Новый.");
+        post(serde_json::json!({ "new_text": "Без режима." })).await;
+        assert_eq!(*app_state.shared_text.read().unwrap(), "Без режима.");
+    }
+
+    #[tokio::test]
+    async fn api_content_delete_resets_to_the_welcome_text() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Статья".to_string();
+        let mut updates = app_state.content_updates.subscribe();
+        let app = build_router(app_state.clone());
+
+        // Deleting twice gives the same result.
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::DELETE)
+                        .uri("/api/content")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                *app_state.shared_text.read().unwrap(),
+                *AppState::default().shared_text.read().unwrap()
+            );
+            assert_eq!(updates.try_recv().unwrap(), sha1_hex(WELCOME_TEXT));
+        }
+    }
+
+    #[tokio::test]
+    async fn api_set_content_rejects_text_over_the_length_limit() {
+        let app_state = Arc::new(AppState::default());
+        app_state
+            .update_settings(|settings| settings.max_text_chars = 1_000)
+            .unwrap();
+        let original_text = app_state.shared_text.read().unwrap().clone();
+        let post = |new_text: String| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/api/content")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "new_text": new_text }).to_string(),
+                ))
+                .unwrap()
+        };
+        let app = build_router(app_state.clone());
+
+        let response = app.clone().oneshot(post("я".repeat(1_001))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let api_response: ApiResponse = serde_json::from_slice(&body).unwrap();
+        assert!(api_response.message.contains("1001"));
+        assert_eq!(*app_state.shared_text.read().unwrap(), original_text);
+
+        let response = app.oneshot(post("я".repeat(1_000))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn api_set_content_rejects_stale_base_hash() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Исходный текст".to_string();
+        let app = build_router(app_state.clone());
+        let post = |body: serde_json::Value| {
+            Request::builder()
+                .method(Method::POST)
+                .uri("/api/content")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // Another writer changed the text after the client loaded it.
+        let loaded_hash = sha1_hex("Исходный текст");
+        *app_state.shared_text.write().unwrap() = "Текст из буфера обмена".to_string();
+
+        let response = app
+            .clone()
+            .oneshot(post(serde_json::json!({
+                "new_text": "Правка",
+                "base_hash": loaded_hash,
+            })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let current: SourceResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(current.markdown, "Текст из буфера обмена");
+        assert_eq!(
+            *app_state.shared_text.read().unwrap(),
+            "Текст из буфера обмена"
+        );
+
+        // Retrying on top of the current text succeeds and reports the new hash.
+        let response = app
+            .clone()
+            .oneshot(post(serde_json::json!({
+                "new_text": "Правка",
+                "base_hash": current.hash,
+            })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_HASH_HEADER).unwrap(),
+            sha1_hex("Правка").as_str()
+        );
+
+        // Without a base hash the last write wins.
+        let response = app
+            .oneshot(post(serde_json::json!({ "new_text": "Без проверки" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*app_state.shared_text.read().unwrap(), "Без проверки");
+    }
+
+    #[tokio::test]
+    async fn api_content_applies_image_mode_without_changing_hash() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Текст ![Схема](scheme.png)".to_string();
+        app_state.settings.write().unwrap().reader.image_mode = ImageMode::Hidden;
+        let app = build_router(app_state.clone());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/content")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let content: ContentResponse = serde_json::from_slice(&body).unwrap();
+
+        assert!(!content.html.contains("<img"));
+        assert!(content
+            .html
+            .contains("<span class=\"image-alt\">Схема</span>"));
+        assert_eq!(
+            content.hash,
+            process_markdown("Текст ![Схема](scheme.png)").1
+        );
+    }
+
+    #[tokio::test]
+    async fn api_structured_makes_title_the_leading_h1() {
+        let app_state = Arc::new(AppState::default());
+        let app = build_router(app_state.clone());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/structured")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({
+                            "title": "Новый заголовок",
+                            "markdown": "# Старый заголовок\n\nТекст статьи.",
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let expected = "# Новый заголовок\n\nТекст статьи.";
+        assert_eq!(*app_state.shared_text.read().unwrap(), expected);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let result: HashResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(result.hash, sha1_hex(expected));
+        assert_eq!(app_state.stats.report(0).documents, 1);
+
+        // Like other writes, the structured text can be taken back.
+        assert_eq!(app_state.undo().unwrap(), WELCOME_TEXT);
+    }
+
+    #[tokio::test]
+    async fn api_structured_rejects_overlong_text() {
+        let app_state = Arc::new(AppState::default());
+        app_state
+            .update_settings(|settings| settings.max_text_chars = 1_000)
+            .unwrap();
+        let app = build_router(app_state.clone());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/structured")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "markdown": "a".repeat(1_001) }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(*app_state.shared_text.read().unwrap(), WELCOME_TEXT);
+        assert_eq!(app_state.stats.report(0).documents, 0);
+    }
+
+    #[tokio::test]
+    async fn get_page_title_is_derived_without_changing_content() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Письмо от *Анны*\n\nТекст.".to_string();
+        let app = build_router(app_state.clone());
+        let get_uri = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get_uri("/get")).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8_lossy(&body);
+        assert!(page.contains("<title>Письмо от Анны</title>"));
+        assert!(!page.contains("<h1>"));
+
+        app_state.settings.write().unwrap().reader.auto_title = false;
+        let response = app.oneshot(get_uri("/api/content")).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let content: ContentResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(content.title, DEFAULT_TITLE);
+    }
+
+    #[tokio::test]
+    async fn minify_html_shrinks_pages_without_changing_hash() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() =
+            "# Код\n\nАбзац\nтекста.\n\n```\nif x {\n    y();\n}\n```\n".to_string();
+        let app = build_router(app_state.clone());
+        let fetch = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                String::from_utf8(body.to_vec()).unwrap()
             }
-        }
+        };
 
-        function initialize(initialContent) {
-            isUpdating = true;
-            container.innerHTML = initialContent;
-            
-            setTimeout(() => {
-                updateLayout();
-                setupNavigation();
-                setInterval(checkForUpdates, 3000);
-                isUpdating = false;
-            }, 100);
+        let page = fetch("/get").await;
+        let content: ContentResponse = serde_json::from_str(&fetch("/api/content").await).unwrap();
+        app_state.settings.write().unwrap().reader.minify_html = true;
+        let minified_page = fetch("/get").await;
+        let minified: ContentResponse = serde_json::from_str(&fetch("/api/content").await).unwrap();
 
-            let resizeTimeout;
-            window.addEventListener('resize', () => {
-                clearTimeout(resizeTimeout);
-                resizeTimeout = setTimeout(updateLayout, 250);
-            });
-        }
-        
-        document.addEventListener('DOMContentLoaded', () => {
-            initialize({{ initial_content_json }});
-        });
-    </script>
-</body>
-</html>
-"#;
+        assert_eq!(minified.hash, content.hash);
+        assert!(minified.html.len() < content.html.len());
+        assert!(minified_page.len() < page.len());
+        let code = "<pre><code>if x {\n    y();\n}\n</code></pre>";
+        assert!(minified.html.contains(code));
+        assert!(minified_page.contains(code));
+        assert!(minified_page.contains(&format!("let currentHash = \"{}\";", content.hash)));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::state::AppState;
-    use axum::{body::Body, http::Request};
-    use http_body_util::BodyExt;
-    use serde::Deserialize;
-    use tower::ServiceExt; // for `oneshot`
+    #[tokio::test]
+    async fn api_hash_matches_api_content_hash() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "# Заголовок\n\nТекст.".to_string();
+        let app = build_router(app_state);
+        let get_uri = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
 
-    // Helper to build the app router for testing
-    fn test_app_router() -> Router {
+        let response = app.clone().oneshot(get_uri("/api/hash")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        assert_eq!(
+            response.headers()[CACHE_CONTROL],
+            "no-cache, no-store, must-revalidate"
+        );
+        let hash = response.into_body().collect().await.unwrap().to_bytes();
+
+        let response = app.oneshot(get_uri("/api/content")).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let content: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(content["hash"], String::from_utf8(hash.to_vec()).unwrap());
+        assert_eq!(content["hash"], process_markdown("# Заголовок\n\nТекст.").1);
+    }
+
+    #[tokio::test]
+    async fn api_source_handler_returns_markdown_with_source_hash() {
         let app_state = Arc::new(AppState::default());
-        Router::new()
-            .route("/get", get(get_page_handler))
-            .route("/api/content", get(api_content_handler))
-            .with_state(app_state)
+        *app_state.shared_text.write().unwrap() = "# Заголовок\n\nТекст".to_string();
+        let app = build_router(app_state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/source")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let header_hash = response.headers().get("x-content-hash").unwrap().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let source: SourceResponse = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(source.markdown, "# Заголовок\n\nТекст");
+        assert_eq!(source.hash, sha1_hex("# Заголовок\n\nТекст"));
+        assert_eq!(header_hash, source.hash.as_str());
+        assert_ne!(source.hash, process_markdown(&source.markdown).1);
     }
 
     #[tokio::test]
-    async fn api_content_handler_returns_no_cache_headers() {
+    async fn api_content_handler_returns_json_with_correct_structure() {
         let app = test_app_router();
 
         let response = app
@@ -479,54 +3733,425 @@ mod tests {
             .await
             .unwrap();
 
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let content_response: ContentResponse = serde_json::from_slice(&body).unwrap();
+
+        // Check if fields exist and have expected types (from default state)
+        let (expected_html, expected_hash) =
+            process_markdown(&AppState::default().shared_text.read().unwrap());
+
+        assert_eq!(content_response.html, expected_html);
+        assert_eq!(content_response.hash, expected_hash);
+    }
+
+    async fn next_content<S>(client: &mut S) -> ContentResponse
+    where
+        S: futures_util::Stream<
+                Item = Result<
+                    tokio_tungstenite::tungstenite::Message,
+                    tokio_tungstenite::tungstenite::Error,
+                >,
+            > + Unpin,
+    {
+        use futures_util::StreamExt;
+        loop {
+            let message = tokio::time::timeout(std::time::Duration::from_secs(5), client.next())
+                .await
+                .expect("no WebSocket message in time")
+                .unwrap()
+                .unwrap();
+            if let tokio_tungstenite::tungstenite::Message::Text(json) = message {
+                return serde_json::from_str(&json).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_pushes_content_changes_to_every_reader() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Старый текст".to_string();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::serve(listener, build_router(app_state.clone()));
+        tokio::spawn(async move { server.await.unwrap() });
+
+        let url = format!("ws://{}/ws", addr);
+        let (mut first, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut second, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (_, old_hash) = process_markdown("Старый текст");
+        assert_eq!(next_content(&mut first).await.hash, old_hash);
+        assert_eq!(next_content(&mut second).await.hash, old_hash);
+
+        let response = build_router(app_state)
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/content")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"new_text":"Новый текст"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+
+        let (new_html, new_hash) = process_markdown("Новый текст");
+        for client in [&mut first, &mut second] {
+            let content = next_content(client).await;
+            assert_eq!(content.hash, new_hash);
+            assert_eq!(content.html, new_html);
+        }
+    }
+
+    #[test]
+    fn server_addr_listens_on_every_interface_at_the_given_port() {
+        let addr = server_addr(8123);
+        assert!(addr.ip().is_unspecified());
+        assert_eq!(addr.port(), 8123);
+    }
+
+    #[tokio::test]
+    async fn bind_listener_skips_a_taken_port() {
+        let taken = std::net::TcpListener::bind(server_addr(0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        match bind_listener(port).await {
+            Some((_listener, addr)) => {
+                assert!(addr.port() > port);
+                assert!(addr.port() < port.saturating_add(PORT_ATTEMPTS));
+            }
+            // Only if the following ports happen to be taken as well.
+            None => assert!(port > u16::MAX - PORT_ATTEMPTS),
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_server_frees_the_port_and_binds_it_again() {
+        let app_state = Arc::new(AppState::default());
+        app_state.server_port.store(0, Ordering::SeqCst);
+
+        assert!(restart_server(app_state.clone()).await);
+        let port = app_state.server_port.load(Ordering::SeqCst);
+        assert_ne!(port, 0);
+
+        // The stopped server must release its port, or the new one would move on.
+        assert!(restart_server(app_state.clone()).await);
+        assert_eq!(app_state.server_port.load(Ordering::SeqCst), port);
+        let status = reqwest::get(format!("http://127.0.0.1:{}/status", port))
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, reqwest::StatusCode::OK);
+
+        app_state.server.lock().await.take().unwrap().stop().await;
+        assert!(reqwest::get(format!("http://127.0.0.1:{}/status", port))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn reader_endpoints_require_the_access_token_when_enabled() {
+        let app_state = Arc::new(AppState {
+            access_token: Some("secret".to_string()),
+            ..AppState::default()
+        });
+        let app = build_router(app_state);
+        let status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        for uri in ["/get", "/api/content", "/api/source", "/api/settings"] {
+            assert_eq!(status(uri).await, StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+        assert_eq!(status("/get?token=wrong").await, StatusCode::UNAUTHORIZED);
         assert_eq!(
-            response.headers().get(CACHE_CONTROL).unwrap(),
-            "no-cache, no-store, must-revalidate"
+            status("/api/content?_=1&token=wrong").await,
+            StatusCode::UNAUTHORIZED
         );
-        assert_eq!(response.headers().get(PRAGMA).unwrap(), "no-cache");
-        assert_eq!(response.headers().get(EXPIRES).unwrap(), "0");
+
+        assert_eq!(status("/get?token=secret").await, StatusCode::OK);
+        assert_eq!(
+            status("/api/content?_=1&token=secret").await,
+            StatusCode::OK
+        );
+        assert_eq!(status("/status").await, StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn get_page_handler_returns_no_cache_headers() {
-        let app = test_app_router();
+    async fn api_push_requires_the_access_token_when_enabled() {
+        let app_state = Arc::new(AppState {
+            access_token: Some("secret".to_string()),
+            ..AppState::default()
+        });
+        let app = build_router(app_state.clone());
+        let original = app_state.shared_text.read().unwrap().clone();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/push?text=injected")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/push")
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from("text=injected"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(*app_state.shared_text.read().unwrap(), original);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/push?token=secret&text=pushed")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*app_state.shared_text.read().unwrap(), "pushed");
+    }
+
+    #[tokio::test]
+    async fn push_to_peer_succeeds_against_a_token_protected_peer() {
+        let peer_state = Arc::new(AppState {
+            access_token: Some("secret".to_string()),
+            ..AppState::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::serve(listener, build_router(peer_state.clone()));
+        tokio::spawn(async move { server.await.unwrap() });
+
+        let hash = crate::peers::push_to_peer("127.0.0.1", addr.port(), "Текст для соседа")
+            .await
+            .unwrap();
+
+        assert_eq!(hash, sha1_hex("Текст для соседа"));
+        assert_eq!(*peer_state.shared_text.read().unwrap(), "Текст для соседа");
+    }
+
+    #[tokio::test]
+    async fn reader_page_passes_the_access_token_on() {
+        let app_state = Arc::new(AppState {
+            access_token: Some("secret".to_string()),
+            ..AppState::default()
+        });
+
+        let response = build_router(app_state)
+            .oneshot(
+                Request::builder()
+                    .uri("/get?token=secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(page.contains(r#"const ACCESS_TOKEN = "secret";"#));
+    }
+
+    #[tokio::test]
+    async fn reader_endpoints_are_open_without_an_access_token() {
+        let response = build_router(Arc::new(AppState::default()))
+            .oneshot(Request::builder().uri("/get").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reader_page_offers_the_estimated_font_sizes() {
+        let response = build_router(Arc::new(AppState::default()))
+            .oneshot(Request::builder().uri("/get").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+
+        let sizes: Vec<f32> = serde_json::from_str(&font_scales_json()).unwrap();
+        assert_eq!(sizes, FONT_SCALES.map(|(_, scale)| scale));
+        assert!(sizes.contains(&1.3));
+        assert!(page.contains(&format!("const FONT_SIZES = {};", font_scales_json())));
+    }
+
+    #[tokio::test]
+    async fn api_toc_lists_headings_with_the_ids_on_the_page() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() =
+            "# Книга\n\nТекст.\n\n## Глава 1\n\nТекст.\n\n### Часть *первая*".to_string();
+        let app = build_router(app_state);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/toc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let toc: Vec<crate::core::TocEntry> = serde_json::from_slice(&body).unwrap();
+        let ids: Vec<&str> = toc.iter().map(|entry| entry.id.as_str()).collect();
+        assert_eq!(ids, vec!["книга", "глава-1", "часть-первая"]);
+        assert_eq!(toc[2].level, 3);
+        assert_eq!(toc[2].text, "Часть первая");
 
         let response = app
             .oneshot(Request::builder().uri("/get").body(Body::empty()).unwrap())
             .await
             .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        for id in ids {
+            assert!(page.contains(&format!(" id=\"{}\"", id)), "{}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn api_export_epub_returns_a_valid_book() {
+        use std::io::Read;
 
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() =
+            "# Моя книга\n\nПервый абзац.\n\n## Глава 1\n\nТекст главы.".to_string();
+
+        let response = build_router(app_state)
+            .oneshot(
+                Request::builder()
+                    .uri("/api/export/epub")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
         assert_eq!(
-            response.headers().get(CACHE_CONTROL).unwrap(),
-            "no-cache, no-store, must-revalidate"
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/epub+zip"
         );
-        assert_eq!(response.headers().get(PRAGMA).unwrap(), "no-cache");
-        assert_eq!(response.headers().get(EXPIRES).unwrap(), "0");
+        let disposition = response.headers().get(CONTENT_DISPOSITION).unwrap();
+        assert!(disposition.to_str().unwrap().ends_with(
+            "filename*=UTF-8''%D0%9C%D0%BE%D1%8F%20%D0%BA%D0%BD%D0%B8%D0%B3%D0%B0.epub"
+        ));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+        let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        for name in [
+            "mimetype",
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/nav.xhtml",
+            "OEBPS/chapter.xhtml",
+            "OEBPS/style.css",
+        ] {
+            assert!(names.iter().any(|entry| entry == name), "{}", name);
+        }
+
+        let mut mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), zip::CompressionMethod::Stored);
+        let mut contents = String::new();
+        mimetype.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "application/epub+zip");
+        drop(mimetype);
+
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert!(read("OEBPS/content.opf").contains("<dc:title>Моя книга</dc:title>"));
+        assert!(read("OEBPS/chapter.xhtml").contains("<p>Текст главы.</p>"));
+        assert!(read("OEBPS/nav.xhtml").contains("<a href=\"chapter.xhtml#глава-1\">Глава 1</a>"));
     }
 
     #[tokio::test]
-    async fn api_content_handler_returns_json_with_correct_structure() {
-        let app = test_app_router();
+    async fn api_progress_keeps_reports_only_for_the_current_text() {
+        let app_state = Arc::new(AppState::default());
+        *app_state.shared_text.write().unwrap() = "Первый текст".to_string();
+        let app = build_router(app_state.clone());
+        let report = |hash: String, page: u32| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/api/progress")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "hash": hash, "page": page, "total": 12 }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+        };
+        let (_, first_hash) = process_markdown("Первый текст");
 
-        let response = app
+        let response = report(first_hash.clone(), 40).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let progress = app_state.reading_progress().unwrap();
+        assert_eq!((progress.page, progress.total), (12, 12));
+
+        let response = report("stale".to_string(), 3).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(app_state.reading_progress().unwrap().page, 12);
+
+        // Once the text changes, the old report no longer describes the reader.
+        *app_state.shared_text.write().unwrap() = "Второй текст".to_string();
+        assert_eq!(app_state.reading_progress(), None);
+        let response = report(first_hash, 3).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn api_stats_reports_word_count_and_reading_time() {
+        let app_state = Arc::new(AppState::default());
+        let markdown = format!("# Статья\n\n{}", "слово ".repeat(399));
+        *app_state.shared_text.write().unwrap() = markdown.clone();
+
+        let response = build_router(app_state)
             .oneshot(
                 Request::builder()
-                    .uri("/api/content")
+                    .uri("/api/stats")
                     .body(Body::empty())
                     .unwrap(),
             )
             .await
             .unwrap();
-
+        assert_eq!(response.status(), StatusCode::OK);
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        let content_response: ContentResponse = serde_json::from_slice(&body).unwrap();
+        let stats: crate::core::ContentStats = serde_json::from_slice(&body).unwrap();
 
-        // Check if fields exist and have expected types (from default state)
-        let (expected_html, expected_hash) = process_markdown(&AppState::default().shared_text.read().unwrap());
-        
-        assert_eq!(content_response.html, expected_html);
-        assert_eq!(content_response.hash, expected_hash);
+        assert_eq!(stats.words, 400);
+        assert_eq!(stats.reading_minutes, 2);
+        assert_eq!(stats.chars, markdown.chars().count());
+        assert_eq!(stats.hash, sha1_hex(&markdown));
     }
 }
@@ -1,25 +1,1629 @@
-use std::sync::atomic::AtomicBool;
+use crate::core::ContentMode;
+use crate::history::History;
+use crate::library::{Document, DocumentInfo, Library};
+use crate::rate_limit::{RateLimiter, DEFAULT_FETCHES_PER_MINUTE, FETCH_RATE_WINDOW};
+use crate::server::ServerHandle;
+use crate::url_processor::SanitizePreset;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Mutex as AsyncMutex};
+
+/// The default maximum number of requests the web server handles at the same time.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// The port the web server tries first unless `KI_PORT` says otherwise.
+pub const DEFAULT_SERVER_PORT: u16 = 5001;
+
+/// The default number of characters of captured text shown in log messages: none,
+/// since the log file outlives the clipboard and may hold passwords or other
+/// private text. Only the kind and length of a capture are logged then.
+pub const DEFAULT_LOG_PREVIEW_CHARS: usize = 0;
+
+/// The default maximum length of the shared text, in characters.
+pub const DEFAULT_MAX_TEXT_CHARS: u64 = 500_000;
+
+/// The default time between two clipboard checks, in milliseconds.
+pub const DEFAULT_CLIPBOARD_INTERVAL_MS: u64 = 500;
+
+/// The text shown on first launch, before anything has been sent to the reader.
+pub const WELCOME_TEXT: &str = "## Добро пожаловать!\n\nЭто редактор для вашей E-Ink читалки. Введите текст в формате Markdown здесь, и он появится на странице, которую вы откроете на читалке.";
+
+/// How many content change notifications a slow WebSocket reader may fall behind
+/// by. Readers only need the latest one, so a small buffer is enough.
+const CONTENT_UPDATES_CAPACITY: usize = 16;
+
+/// How paragraphs are separated on the reader page.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParagraphStyle {
+    /// Paragraphs are separated by a blank line.
+    #[default]
+    Spaced,
+    /// Book style: no spacing, the first line of each paragraph is indented.
+    Indented,
+}
+
+/// How reading progress is shown in the reader's UI bar.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressStyle {
+    /// "Страница X из Y" text.
+    #[default]
+    Text,
+    /// A thin bar filled in proportion to the current page.
+    Bar,
+    /// Both the bar and the text.
+    Both,
+}
+
+/// How heavy the body text is drawn; heavier text is easier to read on faint E-Ink screens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontWeight {
+    #[default]
+    Normal,
+    /// Slightly darkened strokes, for fonts without a medium face.
+    Medium,
+    Bold,
+}
+
+/// How images are presented on the reader page.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// Images stay where they are in the text.
+    #[default]
+    Inline,
+    /// Images are moved to an appendix at the end, with numbered links in the text.
+    Gallery,
+    /// Images are replaced by their alt text.
+    Hidden,
+}
+
+/// How many text columns the reader shows per screen. Serialized as the number 1 or 2.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum ColumnsPerPage {
+    /// One column, for phone-sized and portrait readers.
+    #[default]
+    One,
+    /// Two narrower columns side by side, for large landscape screens.
+    Two,
+}
+
+impl TryFrom<u8> for ColumnsPerPage {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(ColumnsPerPage::One),
+            2 => Ok(ColumnsPerPage::Two),
+            _ => Err(format!("columns_per_page must be 1 or 2, got {}", value)),
+        }
+    }
+}
+
+impl From<ColumnsPerPage> for u8 {
+    fn from(columns: ColumnsPerPage) -> Self {
+        match columns {
+            ColumnsPerPage::One => 1,
+            ColumnsPerPage::Two => 2,
+        }
+    }
+}
+
+/// Presentation settings applied by the web reader.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReaderSettings {
+    pub paragraph_style: ParagraphStyle,
+    pub progress_style: ProgressStyle,
+    /// Whether the bottom UI bar is shown; when hidden the content uses the full height.
+    pub show_ui_bar: bool,
+    pub font_weight: FontWeight,
+    /// Keep paragraphs whole on one page where possible, at the cost of some blank
+    /// space at the bottom of pages. Paragraphs taller than a page still break.
+    pub avoid_paragraph_breaks: bool,
+    pub columns_per_page: ColumnsPerPage,
+    pub image_mode: ImageMode,
+    /// The minimum time between two repaints of the reader, in milliseconds. Content
+    /// changes arriving sooner are coalesced and the latest one is shown once the
+    /// interval has passed, which keeps E-Ink screens from flashing during bursts
+    /// of updates. 0 repaints on every change.
+    pub repaint_throttle_ms: u64,
+    /// Derive a title from the first line of text for documents without an H1. The
+    /// title is only used for the page title and exports, never added to the text.
+    pub auto_title: bool,
+    /// Strip insignificant whitespace from the served page and content, to save
+    /// transfer time on slow Wi-Fi. Content hashes are unaffected.
+    pub minify_html: bool,
+}
+
+impl Default for ReaderSettings {
+    fn default() -> Self {
+        Self {
+            paragraph_style: ParagraphStyle::default(),
+            progress_style: ProgressStyle::default(),
+            show_ui_bar: true,
+            font_weight: FontWeight::default(),
+            avoid_paragraph_breaks: false,
+            columns_per_page: ColumnsPerPage::default(),
+            image_mode: ImageMode::default(),
+            repaint_throttle_ms: 0,
+            auto_title: true,
+            minify_html: false,
+        }
+    }
+}
+
+impl ReaderSettings {
+    /// Returns a copy of the settings with the fields present in `patch` overwritten.
+    ///
+    /// # Arguments
+    /// * `patch` - A JSON object containing any subset of the settings fields.
+    ///
+    /// # Returns
+    /// The updated settings, or an error if `patch` contains unknown fields or invalid values.
+    pub fn merged_with(&self, patch: serde_json::Value) -> Result<Self, serde_json::Error> {
+        let mut merged = serde_json::to_value(self)?;
+        if let (Some(target), serde_json::Value::Object(fields)) = (merged.as_object_mut(), patch) {
+            target.extend(fields);
+        }
+        serde_json::from_value(merged)
+    }
+}
+
+/// The allowed `repaint_throttle_ms`, in milliseconds: up to five minutes.
+pub const REPAINT_THROTTLE_MS_RANGE: RangeInclusive<u64> = 0..=300_000;
+
+/// The allowed `log_preview_chars`.
+pub const LOG_PREVIEW_CHARS_RANGE: RangeInclusive<u64> = 0..=10_000;
+
+/// The allowed `clipboard_interval_ms`: at least 100 ms, so the monitor never
+/// busy-loops, and at most 10 seconds, so captures still feel responsive.
+pub const CLIPBOARD_INTERVAL_MS_RANGE: RangeInclusive<u64> = 100..=10_000;
+
+/// The allowed `max_text_chars`.
+pub const MAX_TEXT_CHARS_RANGE: RangeInclusive<u64> = 1_000..=10_000_000;
+
+/// The allowed `idle_shutdown_minutes`: up to one day.
+pub const IDLE_SHUTDOWN_MINUTES_RANGE: RangeInclusive<u64> = 0..=24 * 60;
+
+/// All user-adjustable settings of the application, read and written through
+/// [`AppState::settings`] by every setting handler. Serialized flat: the reader
+/// settings appear next to the application flags.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Settings {
+    /// Presentation settings for the web reader; the only ones readers can change
+    /// over `POST /api/settings`.
+    #[serde(flatten)]
+    pub reader: ReaderSettings,
+    /// Replace the shared text with clipboard content (sends it to the e-reader).
+    pub send_on_copy: bool,
+    /// Append clipboard content to the editor (does not send).
+    pub add_to_editor_on_copy: bool,
+    /// Escape Markdown syntax in captured text that looks like plain text.
+    pub escape_plain_text_on_copy: bool,
+    /// Empty the system clipboard once captured text has been sent or added to the
+    /// editor. Destructive: the copied text is gone from the clipboard afterwards.
+    pub clear_clipboard_after_capture: bool,
+    /// Maximum number of characters of captured content written to the log; 0 logs
+    /// only its kind and length.
+    pub log_preview_chars: usize,
+    /// Time between two clipboard checks, in milliseconds. Shorter intervals pick
+    /// up copies sooner at the cost of more CPU use.
+    pub clipboard_interval_ms: u64,
+    /// The longest text, in characters, accepted as the shared text from the
+    /// editor, the clipboard or `POST /api/content`. Longer texts are rejected, as
+    /// rendering them on every update makes slower machines stutter. Embedded
+    /// images do not count.
+    pub max_text_chars: u64,
+    /// Minutes without web server requests after which the application exits; 0 disables it.
+    pub idle_shutdown_minutes: u64,
+    /// Whether the shared text is Markdown or an HTML fragment to show as-is.
+    pub content_mode: ContentMode,
+    /// Which tags and attributes fetched pages keep when they are sanitized.
+    pub sanitize_preset: SanitizePreset,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            reader: ReaderSettings::default(),
+            send_on_copy: false,
+            add_to_editor_on_copy: false,
+            escape_plain_text_on_copy: true,
+            clear_clipboard_after_capture: false,
+            log_preview_chars: DEFAULT_LOG_PREVIEW_CHARS,
+            clipboard_interval_ms: DEFAULT_CLIPBOARD_INTERVAL_MS,
+            max_text_chars: DEFAULT_MAX_TEXT_CHARS,
+            idle_shutdown_minutes: idle_shutdown_minutes_from_env(),
+            content_mode: ContentMode::default(),
+            sanitize_preset: SanitizePreset::default(),
+        }
+    }
+}
+
+/// Who may change a setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingScope {
+    /// A reader setting, changeable from the reader menu and `POST /api/settings`.
+    Reader,
+    /// An application setting, changeable only from the desktop UI.
+    App,
+}
+
+/// The kind of value a setting takes, with its allowed values.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SettingKind {
+    Bool,
+    Choice { options: Vec<serde_json::Value> },
+    Number { min: u64, max: u64 },
+}
+
+/// One setting with its current value, as listed by [`Settings::report`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SettingInfo {
+    /// The field name, as used in `/api/settings` and the setter commands.
+    pub key: &'static str,
+    pub scope: SettingScope,
+    pub value: serde_json::Value,
+    #[serde(flatten)]
+    pub kind: SettingKind,
+}
+
+/// A numeric setting that was out of range and has been clamped into it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ClampedSetting {
+    pub key: &'static str,
+    /// The rejected value.
+    pub requested: u64,
+    /// The value stored instead: the nearest bound of the allowed range.
+    pub value: u64,
+}
+
+/// Every setting with its current value and allowed values, for rendering
+/// controls generically.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SettingsReport {
+    /// The current values, keyed by setting name.
+    pub values: serde_json::Value,
+    pub settings: Vec<SettingInfo>,
+    /// The settings the last change had to clamp, so the UI can warn about them.
+    pub clamped: Vec<ClampedSetting>,
+}
+
+impl Settings {
+    /// Clamps every numeric setting into its allowed range. Called after every
+    /// change and when settings are loaded, so out-of-range values never stick.
+    ///
+    /// # Returns
+    /// The settings that were out of range, with their old and new values.
+    pub fn clamp(&mut self) -> Vec<ClampedSetting> {
+        let mut clamped = Vec::new();
+        clamp_number(
+            &mut clamped,
+            "repaint_throttle_ms",
+            &mut self.reader.repaint_throttle_ms,
+            &REPAINT_THROTTLE_MS_RANGE,
+        );
+        let mut log_preview_chars = self.log_preview_chars as u64;
+        clamp_number(
+            &mut clamped,
+            "log_preview_chars",
+            &mut log_preview_chars,
+            &LOG_PREVIEW_CHARS_RANGE,
+        );
+        self.log_preview_chars = log_preview_chars as usize;
+        clamp_number(
+            &mut clamped,
+            "clipboard_interval_ms",
+            &mut self.clipboard_interval_ms,
+            &CLIPBOARD_INTERVAL_MS_RANGE,
+        );
+        clamp_number(
+            &mut clamped,
+            "max_text_chars",
+            &mut self.max_text_chars,
+            &MAX_TEXT_CHARS_RANGE,
+        );
+        clamp_number(
+            &mut clamped,
+            "idle_shutdown_minutes",
+            &mut self.idle_shutdown_minutes,
+            &IDLE_SHUTDOWN_MINUTES_RANGE,
+        );
+        clamped
+    }
+
+    /// Lists every setting with its current value and allowed values.
+    pub fn report(&self) -> SettingsReport {
+        let values = serde_json::to_value(self).unwrap_or_default();
+        let settings = setting_kinds()
+            .into_iter()
+            .map(|(key, scope, kind)| SettingInfo {
+                key,
+                scope,
+                value: values[key].clone(),
+                kind,
+            })
+            .collect();
+        SettingsReport {
+            values,
+            settings,
+            clamped: Vec::new(),
+        }
+    }
+}
+
+fn clamp_number(
+    clamped: &mut Vec<ClampedSetting>,
+    key: &'static str,
+    value: &mut u64,
+    range: &RangeInclusive<u64>,
+) {
+    let requested = *value;
+    *value = requested.clamp(*range.start(), *range.end());
+    if *value != requested {
+        log::warn!(
+            "Setting {} = {} is out of range, clamped to {}",
+            key,
+            requested,
+            *value
+        );
+        clamped.push(ClampedSetting {
+            key,
+            requested,
+            value: *value,
+        });
+    }
+}
+
+fn number(range: &RangeInclusive<u64>) -> SettingKind {
+    SettingKind::Number {
+        min: *range.start(),
+        max: *range.end(),
+    }
+}
+
+fn choice<T: Serialize>(options: &[T]) -> SettingKind {
+    SettingKind::Choice {
+        options: options
+            .iter()
+            .filter_map(|option| serde_json::to_value(option).ok())
+            .collect(),
+    }
+}
+
+/// The scope and allowed values of every field of [`Settings`].
+fn setting_kinds() -> Vec<(&'static str, SettingScope, SettingKind)> {
+    use SettingScope::{App, Reader};
+
+    vec![
+        (
+            "paragraph_style",
+            Reader,
+            choice(&[ParagraphStyle::Spaced, ParagraphStyle::Indented]),
+        ),
+        (
+            "progress_style",
+            Reader,
+            choice(&[ProgressStyle::Text, ProgressStyle::Bar, ProgressStyle::Both]),
+        ),
+        ("show_ui_bar", Reader, SettingKind::Bool),
+        (
+            "font_weight",
+            Reader,
+            choice(&[FontWeight::Normal, FontWeight::Medium, FontWeight::Bold]),
+        ),
+        ("avoid_paragraph_breaks", Reader, SettingKind::Bool),
+        (
+            "columns_per_page",
+            Reader,
+            choice(&[ColumnsPerPage::One, ColumnsPerPage::Two]),
+        ),
+        (
+            "image_mode",
+            Reader,
+            choice(&[ImageMode::Inline, ImageMode::Gallery, ImageMode::Hidden]),
+        ),
+        (
+            "repaint_throttle_ms",
+            Reader,
+            number(&REPAINT_THROTTLE_MS_RANGE),
+        ),
+        ("auto_title", Reader, SettingKind::Bool),
+        ("minify_html", Reader, SettingKind::Bool),
+        ("send_on_copy", App, SettingKind::Bool),
+        ("add_to_editor_on_copy", App, SettingKind::Bool),
+        ("escape_plain_text_on_copy", App, SettingKind::Bool),
+        ("clear_clipboard_after_capture", App, SettingKind::Bool),
+        ("log_preview_chars", App, number(&LOG_PREVIEW_CHARS_RANGE)),
+        (
+            "clipboard_interval_ms",
+            App,
+            number(&CLIPBOARD_INTERVAL_MS_RANGE),
+        ),
+        ("max_text_chars", App, number(&MAX_TEXT_CHARS_RANGE)),
+        (
+            "idle_shutdown_minutes",
+            App,
+            number(&IDLE_SHUTDOWN_MINUTES_RANGE),
+        ),
+        (
+            "content_mode",
+            App,
+            choice(&[ContentMode::Markdown, ContentMode::Html]),
+        ),
+        (
+            "sanitize_preset",
+            App,
+            choice(&[
+                SanitizePreset::Standard,
+                SanitizePreset::TextOnly,
+                SanitizePreset::Full,
+            ]),
+        ),
+    ]
+}
+
+/// Usage counters for the personal stats panel, cheap to update from any thread.
+///
+/// The counters add up over all sessions: they are saved with the state (see
+/// [`UsageTotals`]) and continue from the saved values on the next launch.
+pub struct UsageStats {
+    documents: AtomicU64,
+    words: AtomicU64,
+    fetches: AtomicU64,
+    /// Time spent in the application in earlier sessions.
+    earlier_uptime_seconds: u64,
+    started_at: Instant,
+}
+
+/// The counters of [`UsageStats`] as saved in the state file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageTotals {
+    pub documents: u64,
+    pub words: u64,
+    pub fetches: u64,
+    pub uptime_seconds: u64,
+}
+
+/// A snapshot of [`UsageStats`], as returned to the UI.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StatsReport {
+    /// Documents sent to the reader from outside the editor: clipboard captures,
+    /// fetched pages, pushes and imports.
+    pub documents: u64,
+    /// Total words in those documents.
+    pub words: u64,
+    /// Pages successfully fetched from a URL.
+    pub fetches: u64,
+    /// Seconds spent in the application, over all sessions.
+    pub uptime_seconds: u64,
+    /// Seconds since the shared text last changed; see [`AppState::updated_at`].
+    pub content_age_seconds: u64,
+}
+
+impl Default for UsageStats {
+    fn default() -> Self {
+        Self::from_totals(UsageTotals::default())
+    }
+}
+
+impl UsageStats {
+    /// Counters continuing from `totals`, saved by an earlier session.
+    pub fn from_totals(totals: UsageTotals) -> Self {
+        Self {
+            documents: AtomicU64::new(totals.documents),
+            words: AtomicU64::new(totals.words),
+            fetches: AtomicU64::new(totals.fetches),
+            earlier_uptime_seconds: totals.uptime_seconds,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// The counters to save, including the time spent in this session so far.
+    pub fn totals(&self) -> UsageTotals {
+        UsageTotals {
+            documents: self.documents.load(Ordering::Relaxed),
+            words: self.words.load(Ordering::Relaxed),
+            fetches: self.fetches.load(Ordering::Relaxed),
+            uptime_seconds: self.earlier_uptime_seconds + self.started_at.elapsed().as_secs(),
+        }
+    }
+
+    /// Counts a document of `words` words sent to the reader.
+    pub fn record_document(&self, words: usize) {
+        self.documents.fetch_add(1, Ordering::Relaxed);
+        self.words.fetch_add(words as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a page successfully fetched from a URL.
+    pub fn record_fetch(&self) {
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes a snapshot of the counters; `updated_at` is [`AppState::updated_at`].
+    pub fn report(&self, updated_at: u64) -> StatsReport {
+        let totals = self.totals();
+        StatsReport {
+            documents: totals.documents,
+            words: totals.words,
+            fetches: totals.fetches,
+            uptime_seconds: totals.uptime_seconds,
+            content_age_seconds: unix_now_millis().saturating_sub(updated_at) / 1000,
+        }
+    }
+}
+
+/// The page a reader is on, as reported by `POST /api/progress`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadingProgress {
+    /// The content hash of the text the reader shows, as sent by `/api/content`.
+    pub hash: String,
+    /// The current page, counted from 1.
+    pub page: u32,
+    /// The number of pages at the reader's layout.
+    pub total: u32,
+}
+
+/// A page re-fetched periodically by [`crate::watch`]. Saved with the state, so the
+/// watch resumes after a restart.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedPage {
+    pub url: String,
+    /// Seconds between fetches.
+    pub interval_secs: u64,
+}
+
+/// The last rendering of the shared text, kept by [`AppState::render`].
+pub struct RenderedText {
+    source: String,
+    mode: ContentMode,
+    html: String,
+    hash: String,
+}
 
 /// The shared, thread-safe state of the application.
 pub struct AppState {
     /// The Markdown text content shared with the web reader.
     pub shared_text: RwLock<String>,
-    /// Flag to enable replacing shared text with clipboard content (sends to e-reader).
-    pub send_on_copy: Arc<AtomicBool>,
-    /// Flag to enable appending clipboard content to the editor (does not send).
-    pub add_to_editor_on_copy: Arc<AtomicBool>,
+    /// The buffer not currently shown: the scratch text while the article is active,
+    /// or the article while the scratch buffer is active. See [`AppState::toggle_scratch`].
+    pub inactive_text: RwLock<String>,
+    /// Whether `shared_text` currently holds the scratch buffer.
+    pub scratch_active: AtomicBool,
+    /// The saved documents. The active one's text is `shared_text` (or
+    /// `inactive_text` while the scratch buffer is active).
+    pub library: RwLock<Library>,
+    /// Earlier versions of the shared text for [`AppState::undo`]. Cleared when the
+    /// shared text switches to another document or buffer.
+    pub history: RwLock<History>,
+    /// The user-adjustable settings. Prefer [`AppState::settings`] for reading.
+    pub settings: RwLock<Settings>,
+    /// Maximum number of requests the web server handles concurrently; the rest wait in a queue.
+    pub max_concurrent_requests: usize,
+    /// The web server port: the configured one (`KI_PORT`) until the server has
+    /// started, then the one actually bound, which may be higher if it was taken.
+    pub server_port: AtomicU16,
+    /// The running web server, `None` until it has started or if it failed to bind.
+    /// See [`crate::server::restart_server`].
+    pub server: AsyncMutex<Option<ServerHandle>>,
+    /// Limits how often pages are fetched from URLs, to protect the remote sites and
+    /// the connection from misbehaving clients. See [`AppState::ensure_fetch_allowed`].
+    pub fetch_limiter: RateLimiter,
+    /// Safe mode: no clipboard monitoring and no outbound fetching. Set once at launch.
+    pub safe_mode: bool,
+    /// The secret readers must pass as `?token=` to see the content, generated at
+    /// launch if `KI_REQUIRE_TOKEN` is set. `None` leaves the reader open to the network.
+    pub access_token: Option<String>,
+    /// Unix time, in seconds, of the last web server request.
+    pub last_request_at: Arc<AtomicU64>,
+    /// Usage counters shown in the stats panel.
+    pub stats: UsageStats,
+    /// The page being re-fetched periodically, if any. See [`crate::watch`].
+    pub watched_page: RwLock<Option<WatchedPage>>,
+    /// Changed whenever a page watch starts or stops; a running watch task exits
+    /// once this no longer matches the value it was started with.
+    pub watch_generation: AtomicU64,
+    /// Receives the source hash of the shared text after every change; the web
+    /// server forwards it to readers connected over WebSocket.
+    /// Use [`AppState::notify_content_changed`] to send.
+    pub content_updates: broadcast::Sender<String>,
+    /// The page the reader last reported; see [`AppState::record_progress`].
+    pub reading_progress: RwLock<Option<ReadingProgress>>,
+    /// Receives every accepted progress report, for the desktop UI.
+    pub progress_updates: broadcast::Sender<ReadingProgress>,
+    /// Unix time, in milliseconds, of the last change of the shared text, shown to
+    /// readers as "обновлено N минут назад". Set by [`AppState::notify_content_changed`]
+    /// and [`AppState::replace_shared_text`].
+    pub updated_at: AtomicU64,
+    /// Whether the last change of the shared text only appended to it, so readers
+    /// can stay on their page. See [`AppState::replace_shared_text`].
+    pub last_change_appended: AtomicBool,
+    /// The shared text as last rendered, so readers polling unchanged text do not
+    /// have it parsed again each time. Cleared on every change of the shared text.
+    pub render_cache: RwLock<Option<RenderedText>>,
+    /// Set when the shared text or the settings changed since the last
+    /// [`AppState::persist`], so periodic saving can skip unchanged state.
+    pub unsaved_changes: AtomicBool,
 }
 
 impl Default for AppState {
     /// Provides a default initial state for the application.
     fn default() -> Self {
         Self {
-            shared_text: RwLock::new(
-                "## Добро пожаловать!\n\nЭто редактор для вашей E-Ink читалки. Введите текст в формате Markdown здесь, и он появится на странице, которую вы откроете на читалке.".to_string(),
-            ),
-            send_on_copy: Arc::new(AtomicBool::new(false)),
-            add_to_editor_on_copy: Arc::new(AtomicBool::new(false)),
+            shared_text: RwLock::new(WELCOME_TEXT.to_string()),
+            inactive_text: RwLock::new(String::new()),
+            scratch_active: AtomicBool::new(false),
+            library: RwLock::new(Library::default()),
+            history: RwLock::new(History::default()),
+            settings: RwLock::new(loaded_settings()),
+            max_concurrent_requests: max_concurrent_requests_from_env(),
+            server_port: AtomicU16::new(server_port_from_env()),
+            server: AsyncMutex::new(None),
+            fetch_limiter: RateLimiter::new(fetches_per_minute_from_env(), FETCH_RATE_WINDOW),
+            safe_mode: safe_mode_from_env(),
+            access_token: env_flag("KI_REQUIRE_TOKEN").then(generate_access_token),
+            last_request_at: Arc::new(AtomicU64::new(unix_now())),
+            stats: UsageStats::default(),
+            watched_page: RwLock::new(None),
+            watch_generation: AtomicU64::new(0),
+            content_updates: broadcast::channel(CONTENT_UPDATES_CAPACITY).0,
+            reading_progress: RwLock::new(None),
+            progress_updates: broadcast::channel(CONTENT_UPDATES_CAPACITY).0,
+            updated_at: AtomicU64::new(unix_now_millis()),
+            last_change_appended: AtomicBool::new(false),
+            render_cache: RwLock::new(None),
+            unsaved_changes: AtomicBool::new(false),
+        }
+    }
+}
+
+impl AppState {
+    /// Restores the shared text and settings saved by [`AppState::persist`] at
+    /// `path`. A missing file gives the defaults; an unreadable or corrupt one also
+    /// gives the defaults and is renamed to `*.corrupt`, so it is kept for
+    /// inspection instead of being overwritten by the next save.
+    pub fn load_or_default(path: &Path) -> Self {
+        let state = Self::default();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("No saved state at {}, starting fresh.", path.display());
+                return state;
+            }
+            Err(e) => {
+                log::warn!("Failed to read saved state {}: {}", path.display(), e);
+                return state;
+            }
+        };
+        let persisted = match serde_json::from_str::<PersistedState>(&contents) {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                let backup = path.with_extension("json.corrupt");
+                log::warn!(
+                    "Saved state {} is corrupt ({}), starting fresh; moved it to {}.",
+                    path.display(),
+                    e,
+                    backup.display()
+                );
+                if let Err(e) = std::fs::rename(path, &backup) {
+                    log::warn!("Failed to move corrupt saved state aside: {}", e);
+                }
+                return state;
+            }
+        };
+
+        let mut settings = Settings {
+            reader: persisted.reader,
+            send_on_copy: persisted.send_on_copy,
+            add_to_editor_on_copy: persisted.add_to_editor_on_copy,
+            escape_plain_text_on_copy: persisted.escape_plain_text_on_copy,
+            clear_clipboard_after_capture: persisted.clear_clipboard_after_capture,
+            log_preview_chars: persisted.log_preview_chars,
+            clipboard_interval_ms: persisted.clipboard_interval_ms,
+            max_text_chars: persisted.max_text_chars,
+            content_mode: persisted.content_mode,
+            sanitize_preset: persisted.sanitize_preset,
+            ..Settings::default()
+        };
+        settings.clamp();
+        log::info!("Restored saved state from {}.", path.display());
+        Self {
+            shared_text: RwLock::new(persisted.shared_text),
+            inactive_text: RwLock::new(persisted.inactive_text),
+            scratch_active: AtomicBool::new(persisted.scratch_active),
+            library: RwLock::new(Library::from_saved(
+                persisted.documents,
+                persisted.active_document,
+            )),
+            settings: RwLock::new(settings),
+            updated_at: AtomicU64::new(persisted.updated_at),
+            stats: UsageStats::from_totals(persisted.stats),
+            watched_page: RwLock::new(persisted.watched_page),
+            ..state
+        }
+    }
+
+    /// Saves the shared text and settings to `path` as JSON, for
+    /// [`AppState::load_or_default`] on the next launch. The file is replaced
+    /// atomically, so a crash while saving never leaves a half-written file.
+    pub fn persist(&self, path: &Path) -> Result<(), String> {
+        // Cleared first: a change made while saving marks the state unsaved again.
+        self.unsaved_changes.store(false, Ordering::SeqCst);
+        let settings = self.settings();
+        // Locked in the same order as by the library commands and the scratch toggle.
+        let shared = self
+            .shared_text
+            .read()
+            .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
+        let inactive_text = self
+            .inactive_text
+            .read()
+            .map_err(|e| format!("Failed to acquire read lock: {}", e))?
+            .clone();
+        // Read under the text lock, which the toggle holds while it switches buffers.
+        let scratch_active = self.scratch_active.load(Ordering::SeqCst);
+        let shared_text = shared.clone();
+        let library = self
+            .library
+            .read()
+            .map_err(|e| format!("Failed to acquire read lock: {}", e))?
+            .clone();
+        drop(shared);
+        let persisted = PersistedState {
+            shared_text,
+            inactive_text,
+            scratch_active,
+            active_document: library.active_index(),
+            documents: library.documents().to_vec(),
+            reader: settings.reader,
+            send_on_copy: settings.send_on_copy,
+            add_to_editor_on_copy: settings.add_to_editor_on_copy,
+            escape_plain_text_on_copy: settings.escape_plain_text_on_copy,
+            clear_clipboard_after_capture: settings.clear_clipboard_after_capture,
+            log_preview_chars: settings.log_preview_chars,
+            clipboard_interval_ms: settings.clipboard_interval_ms,
+            max_text_chars: settings.max_text_chars,
+            content_mode: settings.content_mode,
+            sanitize_preset: settings.sanitize_preset,
+            updated_at: self.updated_at.load(Ordering::SeqCst),
+            stats: self.stats.totals(),
+            watched_page: self
+                .watched_page
+                .read()
+                .map_err(|e| format!("Failed to acquire read lock: {}", e))?
+                .clone(),
+        };
+        let json = serde_json::to_string(&persisted)
+            .map_err(|e| format!("Failed to serialize state: {}", e))?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, json)
+            .and_then(|()| std::fs::rename(&temp_path, path))
+            .map_err(|e| {
+                self.mark_unsaved();
+                format!("Failed to save state to {}: {}", path.display(), e)
+            })
+    }
+
+    /// Marks the state as changed, so it is saved by the next periodic save.
+    pub fn mark_unsaved(&self) {
+        self.unsaved_changes.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns a copy of the current settings, or the defaults if the lock is poisoned.
+    pub fn settings(&self) -> Settings {
+        self.settings
+            .read()
+            .map(|settings| settings.clone())
+            .unwrap_or_else(|e| {
+                log::warn!("Failed to acquire read lock for settings: {}", e);
+                Settings::default()
+            })
+    }
+
+    /// Changes the settings with `update`, then clamps numeric settings into range.
+    ///
+    /// # Returns
+    /// The settings that had to be clamped, or an error if the lock is poisoned.
+    pub fn update_settings(
+        &self,
+        update: impl FnOnce(&mut Settings),
+    ) -> Result<Vec<ClampedSetting>, String> {
+        let mut settings = self
+            .settings
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock for settings: {}", e))?;
+        update(&mut settings);
+        self.mark_unsaved();
+        Ok(settings.clamp())
+    }
+
+    /// Returns an error if outbound network requests are disabled by safe mode.
+    pub fn ensure_network_allowed(&self) -> Result<(), String> {
+        if self.safe_mode {
+            Err("Загрузка из сети отключена в безопасном режиме.".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts a page fetch against the fetch rate limit (`KI_FETCHES_PER_MINUTE`).
+    ///
+    /// # Returns
+    /// An error for the user if the limit is exhausted; the page must not be fetched.
+    pub fn ensure_fetch_allowed(&self) -> Result<(), String> {
+        if self.fetch_limiter.try_acquire() {
+            return Ok(());
+        }
+        log::warn!(
+            "Fetch rate limit of {} per minute exceeded.",
+            self.fetch_limiter.capacity()
+        );
+        Err(format!(
+            "Слишком много загрузок: не больше {} в минуту. Попробуйте чуть позже.",
+            self.fetch_limiter.capacity()
+        ))
+    }
+
+    /// Returns an error for the user if `text` is longer than the `max_text_chars`
+    /// setting allows. Embedded images are not counted.
+    pub fn ensure_text_fits(&self, text: &str) -> Result<(), String> {
+        let max_chars = self.settings().max_text_chars;
+        let chars = crate::core::count_chars_without_data_uris(text) as u64;
+        if chars > max_chars {
+            log::warn!(
+                "Rejected text of {} characters, the maximum is {}.",
+                chars,
+                max_chars
+            );
+            Err(format!(
+                "Текст слишком длинный: {} символов, допускается не больше {}.",
+                chars, max_chars
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Switches between the article and the scratch buffer by swapping `shared_text`
+    /// with `inactive_text`, so the reader, the editor and the API all follow the
+    /// active buffer and neither text is lost.
+    ///
+    /// # Returns
+    /// `true` if the scratch buffer is active after the switch.
+    pub fn toggle_scratch(&self) -> Result<bool, String> {
+        let mut shared = self
+            .shared_text
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+        let mut inactive = self
+            .inactive_text
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+
+        std::mem::swap(&mut *shared, &mut *inactive);
+        self.clear_history();
+        self.notify_content_changed(&shared);
+        Ok(!self.scratch_active.fetch_xor(true, Ordering::SeqCst))
+    }
+
+    /// Lists the saved documents, marking the active one.
+    pub fn list_documents(&self) -> Result<Vec<DocumentInfo>, String> {
+        self.library
+            .read()
+            .map(|library| library.list())
+            .map_err(|e| format!("Failed to acquire read lock: {}", e))
+    }
+
+    /// Adds an empty document named `name` and makes it active.
+    ///
+    /// # Returns
+    /// The updated document list.
+    pub fn new_document(&self, name: &str) -> Result<Vec<DocumentInfo>, String> {
+        self.change_library(|library, shared| {
+            library.create(name, shared);
+            Ok(true)
+        })
+    }
+
+    /// Makes the document `id` active, so the reader and the editor show it.
+    ///
+    /// # Returns
+    /// The updated document list.
+    pub fn switch_document(&self, id: u64) -> Result<Vec<DocumentInfo>, String> {
+        self.change_library(|library, shared| library.switch(id, shared))
+    }
+
+    /// Deletes the document `id`; if it was active, a neighbouring one becomes active.
+    ///
+    /// # Returns
+    /// The updated document list.
+    pub fn delete_document(&self, id: u64) -> Result<Vec<DocumentInfo>, String> {
+        self.change_library(|library, shared| library.delete(id, shared))
+    }
+
+    /// Applies `change` to the library and the shared text, notifying readers if
+    /// `change` returns `true` (the active document changed). Refused while the
+    /// scratch buffer is active, as `shared_text` then holds the scratch text.
+    fn change_library(
+        &self,
+        change: impl FnOnce(&mut Library, &mut String) -> Result<bool, String>,
+    ) -> Result<Vec<DocumentInfo>, String> {
+        let mut shared = self
+            .shared_text
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+        if self.scratch_active.load(Ordering::SeqCst) {
+            return Err("Сначала вернитесь из черновика к статье.".to_string());
+        }
+        let mut library = self
+            .library
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+
+        let active_changed = change(&mut library, &mut shared)?;
+        self.mark_unsaved();
+        if active_changed {
+            self.clear_history();
+            self.notify_content_changed(&shared);
+        }
+        Ok(library.list())
+    }
+
+    /// Restores the shared text as it was before the last change, like an undo in
+    /// the editor, and notifies readers.
+    ///
+    /// # Returns
+    /// The restored text, or an error if there is nothing to undo.
+    pub fn undo(&self) -> Result<String, String> {
+        self.move_in_history(History::undo, "Нечего отменять.")
+    }
+
+    /// Takes back the last [`AppState::undo`] and notifies readers.
+    ///
+    /// # Returns
+    /// The restored text, or an error if there is nothing to redo.
+    pub fn redo(&self) -> Result<String, String> {
+        self.move_in_history(History::redo, "Нечего возвращать.")
+    }
+
+    fn move_in_history(
+        &self,
+        step: impl FnOnce(&mut History, &mut String) -> bool,
+        nothing_to_do: &str,
+    ) -> Result<String, String> {
+        let mut shared = self
+            .shared_text
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+        let mut history = self
+            .history
+            .write()
+            .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
+        if !step(&mut history, &mut shared) {
+            return Err(nothing_to_do.to_string());
+        }
+        self.notify_content_changed(&shared);
+        Ok(shared.clone())
+    }
+
+    fn clear_history(&self) {
+        if let Ok(mut history) = self.history.write() {
+            history.clear();
+        }
+    }
+
+    /// Replaces the text in `shared` (the locked `shared_text`) with `new_text` and
+    /// notifies readers, recording whether the new text only appends to the old one.
+    /// The old text is kept for [`AppState::undo`]. Use for writes the user may
+    /// want to take back, like saves from the editor, clipboard captures and
+    /// fetched pages.
+    pub fn replace_shared_text(&self, shared: &mut String, new_text: String) {
+        let appended = crate::core::is_append(shared, &new_text);
+        let previous = std::mem::replace(shared, new_text);
+        if previous != *shared {
+            if let Ok(mut history) = self.history.write() {
+                history.record(previous);
+            }
+        }
+        self.notify(shared, appended);
+    }
+
+    /// Replaces the text in `shared` (the locked `shared_text`) with `markdown`,
+    /// making `title` its leading H1 if given (an existing leading H1 is replaced),
+    /// and counts it as a new document. Like other writes, it can be undone.
+    ///
+    /// # Returns
+    /// The source hash of the new text, or an error for the user if it is longer
+    /// than the `max_text_chars` setting allows; the text is then left as it is.
+    pub fn set_structured_text(
+        &self,
+        shared: &mut String,
+        title: Option<&str>,
+        markdown: String,
+    ) -> Result<String, String> {
+        let markdown = match title {
+            Some(title) => crate::core::set_leading_title(&markdown, title),
+            None => markdown,
+        };
+        self.ensure_text_fits(&markdown)?;
+        let hash = crate::core::sha1_hex(&markdown);
+        self.stats
+            .record_document(crate::core::count_words(&markdown));
+        self.replace_shared_text(shared, markdown);
+        Ok(hash)
+    }
+
+    /// Tells connected readers that the shared text is now `text` and marks it for
+    /// saving. Call after every write to `shared_text` not made with
+    /// [`AppState::replace_shared_text`]; the change counts as a full replace.
+    /// Never blocks.
+    pub fn notify_content_changed(&self, text: &str) {
+        self.notify(text, false);
+    }
+
+    fn notify(&self, text: &str, appended: bool) {
+        if let Ok(mut cache) = self.render_cache.write() {
+            *cache = None;
+        }
+        self.last_change_appended.store(appended, Ordering::SeqCst);
+        self.updated_at.store(unix_now_millis(), Ordering::SeqCst);
+        self.mark_unsaved();
+        if self.content_updates.receiver_count() > 0 {
+            let _ = self.content_updates.send(crate::core::sha1_hex(text));
+        }
+    }
+
+    /// Renders `markdown`, normally the shared text, with [`render_content`] in the
+    /// current content mode, reusing the previous result if it was for the same
+    /// text and mode.
+    ///
+    /// # Returns
+    /// The HTML and its hash, as returned by [`render_content`].
+    ///
+    /// [`render_content`]: crate::core::render_content
+    pub fn render(&self, markdown: &str) -> (String, String) {
+        let mode = self.settings().content_mode;
+        if let Ok(cache) = self.render_cache.read() {
+            if let Some(cached) = cache
+                .as_ref()
+                .filter(|cached| cached.source == markdown && cached.mode == mode)
+            {
+                return (cached.html.clone(), cached.hash.clone());
+            }
+        }
+
+        let (html, hash) = crate::core::render_content(markdown, mode);
+        if let Ok(mut cache) = self.render_cache.write() {
+            *cache = Some(RenderedText {
+                source: markdown.to_string(),
+                mode,
+                html: html.clone(),
+                hash: hash.clone(),
+            });
+        }
+        (html, hash)
+    }
+
+    /// The content hash readers currently get for the shared text.
+    fn current_hash(&self) -> Option<String> {
+        let text = self.shared_text.read().ok()?;
+        Some(self.render(&text).1)
+    }
+
+    /// Remembers the page a reader is on. Reports about another text than the
+    /// current one (e.g. from a reader that has not picked up a change yet) are
+    /// ignored. The page is clamped into `1..=total`.
+    ///
+    /// # Returns
+    /// Whether the report was accepted.
+    pub fn record_progress(&self, mut progress: ReadingProgress) -> bool {
+        if self.current_hash().as_deref() != Some(progress.hash.as_str()) {
+            return false;
+        }
+        progress.total = progress.total.max(1);
+        progress.page = progress.page.clamp(1, progress.total);
+        let Ok(mut current) = self.reading_progress.write() else {
+            return false;
+        };
+        *current = Some(progress.clone());
+        if self.progress_updates.receiver_count() > 0 {
+            let _ = self.progress_updates.send(progress);
+        }
+        true
+    }
+
+    /// Returns the page the reader last reported, if it still shows the current text.
+    pub fn reading_progress(&self) -> Option<ReadingProgress> {
+        let progress = self.reading_progress.read().ok()?.clone()?;
+        (self.current_hash()? == progress.hash).then_some(progress)
+    }
+
+    /// Marks the web server as just used, restarting the idle shutdown timer.
+    pub fn record_request(&self) {
+        self.last_request_at.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// Returns how long ago the web server last handled a request.
+    pub fn idle_duration(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.last_request_at.load(Ordering::Relaxed)))
+    }
+}
+
+/// The contents of the saved state file. Missing fields take their defaults, so
+/// files written by older versions still load. `idle_shutdown_minutes` is not
+/// saved: it is configured by the environment at launch.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct PersistedState {
+    /// The text of the active buffer: the active document, or the scratch text.
+    shared_text: String,
+    /// The buffer not shown, see [`AppState::inactive_text`].
+    inactive_text: String,
+    scratch_active: bool,
+    /// The other documents' texts; the active one's is empty here.
+    documents: Vec<Document>,
+    active_document: usize,
+    reader: ReaderSettings,
+    send_on_copy: bool,
+    add_to_editor_on_copy: bool,
+    escape_plain_text_on_copy: bool,
+    clear_clipboard_after_capture: bool,
+    log_preview_chars: usize,
+    clipboard_interval_ms: u64,
+    max_text_chars: u64,
+    content_mode: ContentMode,
+    sanitize_preset: SanitizePreset,
+    /// See [`AppState::updated_at`]; files without it count the text as changed at launch.
+    updated_at: u64,
+    stats: UsageTotals,
+    watched_page: Option<WatchedPage>,
+}
+
+impl Default for PersistedState {
+    fn default() -> Self {
+        let settings = Settings::default();
+        Self {
+            shared_text: WELCOME_TEXT.to_string(),
+            inactive_text: String::new(),
+            scratch_active: false,
+            documents: Vec::new(),
+            active_document: 0,
+            reader: settings.reader,
+            send_on_copy: settings.send_on_copy,
+            add_to_editor_on_copy: settings.add_to_editor_on_copy,
+            escape_plain_text_on_copy: settings.escape_plain_text_on_copy,
+            clear_clipboard_after_capture: settings.clear_clipboard_after_capture,
+            log_preview_chars: settings.log_preview_chars,
+            clipboard_interval_ms: settings.clipboard_interval_ms,
+            max_text_chars: settings.max_text_chars,
+            content_mode: settings.content_mode,
+            sanitize_preset: settings.sanitize_preset,
+            updated_at: unix_now_millis(),
+            stats: UsageTotals::default(),
+            watched_page: None,
         }
     }
 }
+
+/// The settings to start with, clamped like every later change so an out-of-range
+/// value from the environment is corrected.
+fn loaded_settings() -> Settings {
+    let mut settings = Settings::default();
+    settings.clamp();
+    settings
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The current Unix time in milliseconds, as kept in [`AppState::updated_at`].
+fn unix_now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Reads the server concurrency limit from the `KI_MAX_CONCURRENCY` environment variable,
+/// falling back to `DEFAULT_MAX_CONCURRENT_REQUESTS` if it is unset or not a positive number.
+fn max_concurrent_requests_from_env() -> usize {
+    std::env::var("KI_MAX_CONCURRENCY")
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|&limit| limit > 0)
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS)
+}
+
+/// Reads the fetch rate limit from the `KI_FETCHES_PER_MINUTE` environment variable,
+/// falling back to [`DEFAULT_FETCHES_PER_MINUTE`] if it is unset or not a positive number.
+fn fetches_per_minute_from_env() -> u32 {
+    std::env::var("KI_FETCHES_PER_MINUTE")
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .filter(|&limit| limit > 0)
+        .unwrap_or(DEFAULT_FETCHES_PER_MINUTE)
+}
+
+/// Reads the web server port from the `KI_PORT` environment variable, falling back
+/// to [`DEFAULT_SERVER_PORT`] if it is unset or not a valid, non-zero port.
+fn server_port_from_env() -> u16 {
+    std::env::var("KI_PORT")
+        .ok()
+        .and_then(|value| parse_port(&value))
+        .unwrap_or(DEFAULT_SERVER_PORT)
+}
+
+fn parse_port(value: &str) -> Option<u16> {
+    value.trim().parse::<u16>().ok().filter(|&port| port > 0)
+}
+
+/// Reads the idle shutdown timeout, in minutes, from the `KI_IDLE_SHUTDOWN_MINUTES`
+/// environment variable. Unset or invalid values disable idle shutdown.
+fn idle_shutdown_minutes_from_env() -> u64 {
+    std::env::var("KI_IDLE_SHUTDOWN_MINUTES")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+/// Reads the `KI_SAFE_MODE` environment variable. See [`env_flag`].
+fn safe_mode_from_env() -> bool {
+    env_flag("KI_SAFE_MODE")
+}
+
+/// Reads an on/off environment variable. Any value other than empty, `0`, `false`,
+/// `no` or `off` means on.
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| {
+        !matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "" | "0" | "false" | "no" | "off"
+        )
+    })
+}
+
+/// Generates a random 128-bit access token as 32 hex digits. The randomness comes
+/// from the per-process random keys of the standard library's hasher.
+fn generate_access_token() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            );
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_report_describes_every_setting() {
+        let report = Settings::default().report();
+
+        let values = report.values.as_object().unwrap();
+        assert_eq!(report.settings.len(), values.len());
+        for setting in &report.settings {
+            assert_eq!(
+                values.get(setting.key),
+                Some(&setting.value),
+                "{}",
+                setting.key
+            );
+        }
+    }
+
+    #[test]
+    fn clamp_moves_numbers_into_range() {
+        let mut settings = Settings::default();
+        settings.reader.repaint_throttle_ms = u64::MAX;
+        settings.log_preview_chars = 1_000_000;
+        settings.clipboard_interval_ms = 0;
+        settings.idle_shutdown_minutes = 60;
+
+        let clamped = settings.clamp();
+
+        assert_eq!(settings.reader.repaint_throttle_ms, 300_000);
+        assert_eq!(settings.log_preview_chars, 10_000);
+        assert_eq!(settings.clipboard_interval_ms, 100);
+        assert_eq!(settings.idle_shutdown_minutes, 60);
+        assert_eq!(
+            clamped,
+            vec![
+                ClampedSetting {
+                    key: "repaint_throttle_ms",
+                    requested: u64::MAX,
+                    value: 300_000,
+                },
+                ClampedSetting {
+                    key: "log_preview_chars",
+                    requested: 1_000_000,
+                    value: 10_000,
+                },
+                ClampedSetting {
+                    key: "clipboard_interval_ms",
+                    requested: 0,
+                    value: 100,
+                },
+            ]
+        );
+        assert!(settings.clamp().is_empty());
+    }
+
+    #[test]
+    fn update_settings_reports_clamped_values() {
+        let state = AppState::default();
+
+        let clamped = state
+            .update_settings(|settings| settings.idle_shutdown_minutes = 100_000)
+            .unwrap();
+
+        assert_eq!(state.settings().idle_shutdown_minutes, 24 * 60);
+        assert_eq!(clamped.len(), 1);
+        assert_eq!(clamped[0].key, "idle_shutdown_minutes");
+        assert_eq!(clamped[0].requested, 100_000);
+    }
+
+    fn temp_state_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ki-state-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("state.json")
+    }
+
+    #[test]
+    fn persisted_state_survives_a_restart() {
+        let path = temp_state_path("roundtrip");
+        let state = AppState::default();
+        *state.shared_text.write().unwrap() = "# Статья\n\nТекст.".to_string();
+        state.updated_at.store(1_700_000_000_000, Ordering::SeqCst);
+        state
+            .update_settings(|settings| {
+                settings.send_on_copy = true;
+                settings.add_to_editor_on_copy = true;
+                settings.reader.minify_html = true;
+                settings.content_mode = ContentMode::Html;
+                settings.sanitize_preset = SanitizePreset::TextOnly;
+            })
+            .unwrap();
+        assert!(state.unsaved_changes.load(Ordering::SeqCst));
+
+        state.persist(&path).unwrap();
+        assert!(!state.unsaved_changes.load(Ordering::SeqCst));
+
+        let restored = AppState::load_or_default(&path);
+        assert_eq!(*restored.shared_text.read().unwrap(), "# Статья\n\nТекст.");
+        assert_eq!(restored.settings(), state.settings());
+        assert_eq!(
+            restored.updated_at.load(Ordering::SeqCst),
+            1_700_000_000_000
+        );
+        assert!(!restored.unsaved_changes.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn usage_stats_add_up_across_restarts() {
+        let path = temp_state_path("stats");
+        let state = AppState {
+            stats: UsageStats::from_totals(UsageTotals {
+                uptime_seconds: 3_600,
+                ..UsageTotals::default()
+            }),
+            ..AppState::default()
+        };
+        state.stats.record_document(120);
+        state.stats.record_fetch();
+
+        state.persist(&path).unwrap();
+        let restored = AppState::load_or_default(&path);
+        restored.stats.record_document(30);
+
+        let report = restored.stats.report(0);
+        assert_eq!(
+            (report.documents, report.words, report.fetches),
+            (2, 150, 1)
+        );
+        assert!(report.uptime_seconds >= 3_600);
+    }
+
+    #[test]
+    fn watched_page_survives_a_restart() {
+        let path = temp_state_path("watch");
+        let state = AppState::default();
+        let page = WatchedPage {
+            url: "https://example.com/news".to_string(),
+            interval_secs: 600,
+        };
+        *state.watched_page.write().unwrap() = Some(page.clone());
+
+        state.persist(&path).unwrap();
+        let restored = AppState::load_or_default(&path);
+
+        assert_eq!(*restored.watched_page.read().unwrap(), Some(page));
+    }
+
+    #[test]
+    fn scratch_buffer_and_article_survive_a_restart() {
+        let path = temp_state_path("scratch");
+        let state = AppState::default();
+        *state.shared_text.write().unwrap() = "# Статья".to_string();
+        state.toggle_scratch().unwrap();
+        *state.shared_text.write().unwrap() = "Черновик".to_string();
+
+        state.persist(&path).unwrap();
+        let restored = AppState::load_or_default(&path);
+        assert_eq!(*restored.shared_text.read().unwrap(), "Черновик");
+        assert!(restored.scratch_active.load(Ordering::SeqCst));
+
+        assert!(!restored.toggle_scratch().unwrap());
+        assert_eq!(*restored.shared_text.read().unwrap(), "# Статья");
+        assert_eq!(*restored.inactive_text.read().unwrap(), "Черновик");
+    }
+
+    #[test]
+    fn document_library_is_saved_and_switching_notifies_readers() {
+        let path = temp_state_path("library");
+        let state = AppState::default();
+        let mut updates = state.content_updates.subscribe();
+        *state.shared_text.write().unwrap() = "Первый".to_string();
+
+        let documents = state.new_document("Второй").unwrap();
+        let second = documents[1].id;
+        assert!(documents[1].active);
+        assert_eq!(updates.try_recv().unwrap(), crate::core::sha1_hex(""));
+        *state.shared_text.write().unwrap() = "Второй текст".to_string();
+        state.persist(&path).unwrap();
+
+        let restored = AppState::load_or_default(&path);
+        assert_eq!(restored.list_documents().unwrap(), documents);
+        assert_eq!(*restored.shared_text.read().unwrap(), "Второй текст");
+        restored.switch_document(documents[0].id).unwrap();
+        assert_eq!(*restored.shared_text.read().unwrap(), "Первый");
+
+        // While the scratch buffer is shown, the library cannot take its text.
+        restored.toggle_scratch().unwrap();
+        assert!(restored.switch_document(second).is_err());
+        assert!(restored.delete_document(second).is_err());
+    }
+
+    #[test]
+    fn undo_restores_replaced_text_until_the_document_changes() {
+        let state = AppState::default();
+        let updates = state.content_updates.subscribe();
+        for text in ["Первый", "Второй"] {
+            let mut shared = state.shared_text.write().unwrap();
+            state.replace_shared_text(&mut shared, text.to_string());
+        }
+
+        assert_eq!(state.undo().unwrap(), "Первый");
+        assert_eq!(state.redo().unwrap(), "Второй");
+        assert!(state.redo().is_err());
+        assert_eq!(updates.len(), 4);
+
+        state.new_document("Другой").unwrap();
+        assert!(state.undo().is_err());
+    }
+
+    #[test]
+    fn render_reuses_the_result_for_unchanged_text() {
+        let state = AppState::default();
+        let text = format!(
+            "# Длинная статья\n\n{}",
+            "Абзац *текста*.\n\n".repeat(1_000)
+        );
+        *state.shared_text.write().unwrap() = text.clone();
+
+        let first = state.render(&text);
+        assert_eq!(first, crate::core::process_markdown(&text));
+
+        // Mark the cached rendering, so a repeated render shows whether it was reused.
+        state.render_cache.write().unwrap().as_mut().unwrap().html = "из кэша".to_string();
+        let second = state.render(&text);
+        assert_eq!(second, ("из кэша".to_string(), first.1.clone()));
+
+        // Any write of the shared text invalidates the cache.
+        state.notify_content_changed(&text);
+        assert_eq!(state.render(&text), first);
+        // A different text is rendered anew even without a notification.
+        assert_ne!(state.render("Другой текст").1, first.1);
+        // So is the same text in another content mode.
+        state.render(&text);
+        state
+            .update_settings(|settings| settings.content_mode = ContentMode::Html)
+            .unwrap();
+        assert_eq!(
+            state.render(&text),
+            crate::core::render_content(&text, ContentMode::Html)
+        );
+    }
+
+    #[test]
+    fn missing_or_corrupt_state_file_gives_defaults() {
+        let path = temp_state_path("corrupt");
+        let fresh = AppState::load_or_default(&path);
+        assert_eq!(*fresh.shared_text.read().unwrap(), WELCOME_TEXT);
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{\"shared_text\": ").unwrap();
+        let state = AppState::load_or_default(&path);
+
+        assert_eq!(*state.shared_text.read().unwrap(), WELCOME_TEXT);
+        assert_eq!(state.settings(), Settings::default());
+        assert!(!path.exists());
+        assert!(path.with_extension("json.corrupt").exists());
+    }
+
+    #[test]
+    fn restored_settings_are_clamped_and_missing_fields_default() {
+        let path = temp_state_path("clamp");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            r#"{"shared_text":"Текст","log_preview_chars":1000000}"#,
+        )
+        .unwrap();
+
+        let settings = AppState::load_or_default(&path).settings();
+
+        assert_eq!(
+            settings.log_preview_chars as u64,
+            *LOG_PREVIEW_CHARS_RANGE.end()
+        );
+        assert_eq!(settings.reader, ReaderSettings::default());
+        assert!(settings.escape_plain_text_on_copy);
+    }
+
+    #[test]
+    fn ensure_text_fits_counts_data_text_outside_images() {
+        let state = AppState::default();
+        let pasted = format!("Смотрите data:{}", "x".repeat(600_000));
+        assert!(state.ensure_text_fits(&pasted).is_err());
+
+        let image = format!("![](data:image/png;base64,{})", "A".repeat(600_000));
+        assert!(state.ensure_text_fits(&image).is_ok());
+    }
+
+    #[test]
+    fn parse_port_accepts_only_valid_non_zero_ports() {
+        assert_eq!(parse_port(" 8080 "), Some(8080));
+        assert_eq!(parse_port("0"), None);
+        assert_eq!(parse_port("70000"), None);
+        assert_eq!(parse_port("порт"), None);
+    }
+
+    #[test]
+    fn access_tokens_are_random_hex() {
+        let token = generate_access_token();
+        assert_eq!(token.len(), 32);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, generate_access_token());
+    }
+}
@@ -1,93 +1,140 @@
+use crate::core::decode_html_entities;
+use crate::images::{embed_article_images, IMAGE_EMBED_TIMEOUT};
 use ammonia::Builder;
 use readability::extractor;
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use url::Url;
 
+/// Why a page could not be fetched or turned into Markdown.
+///
+/// `Display` produces the user-facing (Russian) message shown in the UI.
+#[derive(Debug)]
+pub enum UrlProcessError {
+    /// The input is not a usable URL.
+    InvalidUrl(String),
+    /// The HTTP client could not be created.
+    Client(String),
+    /// The request failed before a response arrived, e.g. a connection error or timeout.
+    Network(reqwest::Error),
+    /// The server answered with a non-success status code.
+    HttpStatus(u16),
+    /// The response body could not be read.
+    Body(reqwest::Error),
+    /// The readability extractor failed on the page.
+    Extraction(String),
+    /// The extractor found no main content.
+    EmptyExtraction,
+    /// The extracted content was empty after sanitizing and conversion to Markdown.
+    EmptyAfterConversion,
+}
+
+impl UrlProcessError {
+    /// Returns `true` if the request timed out, so trying again may succeed.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            UrlProcessError::Network(e) | UrlProcessError::Body(e) => e.is_timeout(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for UrlProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlProcessError::InvalidUrl(detail) => write!(f, "Неверный URL: {}", detail),
+            UrlProcessError::Client(detail) => write!(f, "{}", detail),
+            UrlProcessError::Network(e) => write!(f, "Ошибка загрузки страницы: {}", e),
+            UrlProcessError::HttpStatus(code) => {
+                let status = StatusCode::from_u16(*code)
+                    .map(|status| status.to_string())
+                    .unwrap_or_else(|_| code.to_string());
+                write!(f, "Ошибка загрузки: сервер ответил со статусом {}", status)
+            }
+            UrlProcessError::Body(e) => write!(f, "Ошибка чтения тела ответа: {}", e),
+            UrlProcessError::Extraction(detail) => {
+                write!(f, "Ошибка извлечения контента: {}", detail)
+            }
+            UrlProcessError::EmptyExtraction => {
+                write!(f, "Не удалось извлечь основное содержимое со страницы.")
+            }
+            UrlProcessError::EmptyAfterConversion => {
+                write!(
+                    f,
+                    "Извлеченное содержимое оказалось пустым после обработки."
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrlProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UrlProcessError::Network(e) | UrlProcessError::Body(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 /// Fetches a URL, extracts the main content, sanitizes it, and converts it to Markdown.
 ///
 /// This function now uses a multi-stage process for higher quality output:
 /// 1. Fetch the URL using `reqwest`.
 /// 2. Extract the core article content using `readability`.
-/// 3. Sanitize the extracted HTML using `ammonia`, allowing only a curated set of
-///    tags and attributes suitable for a clean reading experience. This removes
-///    scripts, styles, and unwanted clutter.
-/// 4. Convert the clean HTML to Markdown using `html2md`.
+/// 3. Sanitize the extracted HTML using `ammonia`, allowing only the tags and
+///    attributes of `allowlist` (by default a curated set suitable for a clean
+///    reading experience). This removes scripts, styles, and unwanted clutter.
+/// 4. Optionally download the article's images and embed them as `data:` URIs,
+///    for readers that cannot load images from other servers.
+/// 5. Convert the clean HTML to Markdown using `html2md`.
 ///
 /// # Arguments
 /// * `url_str` - The URL of the article to process.
+/// * `embed_images` - Whether to embed the images; those that fail to download
+///   keep pointing at their original address.
+/// * `allowlist` - The tags and attributes to keep.
 ///
 /// # Returns
-/// A `Result` containing the processed Markdown string on success, or an error string on failure.
-pub async fn process_url(url_str: &str) -> Result<String, String> {
-    let url = Url::parse(url_str).map_err(|e| format!("Неверный URL: {}", e))?;
-
-    let client = reqwest::Client::builder()
-        .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0")
-        .timeout(std::time::Duration::from_secs(20))
-        .build()
-        .map_err(|e| format!("Ошибка создания HTTP клиента: {}", e))?;
-
-    let response = client
-        .get(url.clone())
-        .send()
-        .await
-        .map_err(|e| format!("Ошибка загрузки страницы: {}", e))?;
-
-    if !response.status().is_success() {
-        return Err(format!(
-            "Ошибка загрузки: сервер ответил со статусом {}",
-            response.status()
-        ));
-    }
-
-    let content_bytes = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Ошибка чтения тела ответа: {}", e))?;
+/// A `Result` containing the processed Markdown string on success, or the reason it failed.
+pub async fn process_url(
+    url_str: &str,
+    embed_images: bool,
+    allowlist: &Allowlist,
+) -> Result<String, UrlProcessError> {
+    let (url, html) = fetch_page(url_str).await?;
 
-    let mut reader = &content_bytes[..];
+    let mut reader = html.as_bytes();
     let product = extractor::extract(&mut reader, &url)
-        .map_err(|e| format!("Ошибка извлечения контента: {}", e))?;
+        .map_err(|e| UrlProcessError::Extraction(e.to_string()))?;
 
     let extracted_html = product.content;
     if extracted_html.trim().is_empty() {
-        return Err("Не удалось извлечь основное содержимое со страницы.".to_string());
+        return Err(UrlProcessError::EmptyExtraction);
     }
 
-    // CORRECTED: `tag_attributes` expects a single HashMap argument.
-    let mut tag_attrs = HashMap::new();
-    tag_attrs.insert("a", ["href"].iter().cloned().collect::<HashSet<_>>());
-    tag_attrs.insert(
-        "img",
-        ["src", "alt", "title"]
-            .iter()
-            .cloned()
-            .collect::<HashSet<_>>(),
-    );
-
-    // Sanitize the extracted HTML to keep only essential tags for reading.
-    let cleaned_html = Builder::new()
-        .tags(
-            [
-                "h1", "h2", "h3", "h4", "h5", "h6", "p", "br", "hr", "strong", "em", "b", "i",
-                "u", "del", "s", "strike", "blockquote", "ul", "ol", "li", "pre", "code", "img",
-                "figure", "figcaption", "table", "thead", "tbody", "tr", "th", "td", "a",
-            ]
-            .iter()
-            .cloned()
-            .collect::<HashSet<_>>(),
-        )
-        .tag_attributes(tag_attrs) // CORRECTED: Pass the HashMap here.
-        .link_rel(None) // Don't add rel="noopener noreferrer"
-        // CORRECTED: `strip_unallowed_tags` does not exist; stripping is the default behavior.
+    // html2md drops <figure>/<figcaption> and glues the caption to the image; a
+    // separate italic paragraph survives the conversion and is turned back into a
+    // caption when the Markdown is rendered.
+    let cleaned_html = allowlist
         .clean(&extracted_html)
-        .to_string();
+        .replace("<figcaption>", "<p><em>")
+        .replace("</figcaption>", "</em></p>");
+    let cleaned_html = if embed_images {
+        embed_article_images(&cleaned_html, &url, IMAGE_EMBED_TIMEOUT).await
+    } else {
+        cleaned_html
+    };
 
     // CORRECTED: Use the original `html2md` crate's `parse_html` function.
     let markdown = html2md::parse_html(&cleaned_html);
 
     if markdown.trim().is_empty() {
-        return Err("Извлеченное содержимое оказалось пустым после обработки.".to_string());
+        return Err(UrlProcessError::EmptyAfterConversion);
     }
 
     let title_md = if !product.title.is_empty() {
@@ -98,3 +145,581 @@ pub async fn process_url(url_str: &str) -> Result<String, String> {
 
     Ok(format!("{}{}", title_md, markdown.trim()))
 }
+
+/// Looks up the cover image a page advertises for link previews.
+///
+/// # Arguments
+/// * `url_str` - The URL of the page.
+///
+/// # Returns
+/// The absolute URL from the page's `og:image` (or `twitter:image`) meta tag,
+/// `None` if the page has no such tag, or an error if the page could not be loaded.
+pub async fn fetch_cover_image_url(url_str: &str) -> Result<Option<Url>, UrlProcessError> {
+    let (url, html) = fetch_page(url_str).await?;
+    Ok(find_cover_image_url(&html, &url))
+}
+
+/// Downloads a page, trying each of the [`candidate_urls`] in turn.
+///
+/// Returns the URL that answered together with the response body, decoded from
+/// the page's encoding with [`decode_page`].
+async fn fetch_page(url_str: &str) -> Result<(Url, String), UrlProcessError> {
+    let candidates = candidate_urls(url_str)?;
+
+    let client = http_client().map_err(UrlProcessError::Client)?;
+
+    // Schemeless input yields both an https:// and an http:// candidate. Falling
+    // back to http:// only when the https:// connection is refused keeps a failed
+    // TLS handshake (e.g. a bad certificate) from silently downgrading the request.
+    let mut last_error = None;
+    let mut fetched = None;
+    for url in candidates {
+        if last_error.is_some() {
+            log::warn!("Connection refused over https, falling back to {}", url);
+        }
+        match client.get(url.clone()).send().await {
+            Ok(response) => {
+                fetched = Some((url, response));
+                break;
+            }
+            Err(e) => {
+                let refused = is_connection_refused(&e);
+                last_error = Some(UrlProcessError::Network(e));
+                if !refused {
+                    break;
+                }
+            }
+        }
+    }
+    let (url, response) = match (fetched, last_error) {
+        (Some(fetched), _) => fetched,
+        (None, Some(e)) => return Err(e),
+        (None, None) => return Err(UrlProcessError::InvalidUrl(url_str.trim().to_string())),
+    };
+
+    if !response.status().is_success() {
+        return Err(UrlProcessError::HttpStatus(response.status().as_u16()));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let content_bytes = response.bytes().await.map_err(UrlProcessError::Body)?;
+
+    Ok((url, decode_page(&content_bytes, content_type.as_deref())))
+}
+
+/// How far into a page a `<meta charset>` declaration is looked for, as in browsers.
+const CHARSET_SNIFF_BYTES: usize = 1024;
+
+/// Decodes a page to UTF-8, so pages served in legacy encodings such as
+/// windows-1251 do not turn into mojibake.
+///
+/// The encoding is taken, in order of precedence, from a byte order mark, the
+/// `charset` of the `Content-Type` header, or a `<meta>` declaration near the start
+/// of the page; pages declaring none are read as UTF-8. Invalid bytes become U+FFFD.
+fn decode_page(bytes: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(charset_label)
+        .or_else(|| {
+            let head = String::from_utf8_lossy(&bytes[..bytes.len().min(CHARSET_SNIFF_BYTES)]);
+            charset_label(&head)
+        })
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    // `decode` lets a byte order mark override the declared encoding.
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+/// Finds the value after the first `charset=` in a `Content-Type` value or in
+/// HTML (`<meta charset="…">` or `<meta http-equiv … content="…; charset=…">`).
+fn charset_label(text: &str) -> Option<String> {
+    let start = find_ascii_case_insensitive(text, "charset=")? + "charset=".len();
+    let label: String = text[start..]
+        .trim_start_matches(['"', '\'', ' '])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+        .collect();
+    (!label.is_empty()).then_some(label)
+}
+
+/// Meta tags that can name a page's cover image, in order of preference.
+const COVER_IMAGE_PROPERTIES: [&str; 4] = [
+    "og:image",
+    "og:image:url",
+    "twitter:image",
+    "twitter:image:src",
+];
+
+/// Finds the cover image declared in a page's `<meta>` tags, resolved against
+/// the page URL. Only `http` and `https` images are returned.
+fn find_cover_image_url(html: &str, page_url: &Url) -> Option<Url> {
+    let mut candidates: Vec<(usize, String)> = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = find_ascii_case_insensitive(rest, "<meta") {
+        rest = &rest[start + "<meta".len()..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let attributes = parse_tag_attributes(&rest[..end]);
+        rest = &rest[end..];
+
+        let property = attributes
+            .iter()
+            .find(|(name, _)| name == "property" || name == "name")
+            .map(|(_, value)| value.to_ascii_lowercase());
+        let content = attributes
+            .iter()
+            .find(|(name, _)| name == "content")
+            .map(|(_, value)| value.trim());
+
+        if let (Some(property), Some(content)) = (property, content) {
+            if let Some(rank) = COVER_IMAGE_PROPERTIES.iter().position(|p| *p == property) {
+                if !content.is_empty() {
+                    candidates.push((rank, content.to_string()));
+                }
+            }
+        }
+    }
+
+    candidates.sort_by_key(|(rank, _)| *rank);
+    candidates.into_iter().find_map(|(_, content)| {
+        page_url
+            .join(&content)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+    })
+}
+
+fn find_ascii_case_insensitive(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Splits the inside of an HTML start tag into lowercase attribute names and
+/// their entity-decoded values. Values may be double-quoted, single-quoted or bare.
+pub fn parse_tag_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag.trim_start();
+
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            let (raw, remainder) = match after_eq.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let body = &after_eq[1..];
+                    let close = body.find(quote).unwrap_or(body.len());
+                    (&body[..close], body.get(close + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after_eq
+                        .find(char::is_whitespace)
+                        .unwrap_or(after_eq.len());
+                    (&after_eq[..end], &after_eq[end..])
+                }
+            };
+            value = decode_html_entities(raw);
+            rest = remainder;
+        } else if name.is_empty() {
+            // Stray characters such as a self-closing `/`.
+            rest = &rest[rest.chars().next().map_or(0, char::len_utf8)..];
+        }
+
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+        rest = rest.trim_start();
+    }
+
+    attributes
+}
+
+/// Which allowlist fetched pages are sanitized with; see [`Allowlist::for_preset`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SanitizePreset {
+    /// Text, links, tables and images.
+    #[default]
+    Standard,
+    /// Like `Standard` without images, for a text-only workflow.
+    TextOnly,
+    /// Like `Standard`, also keeping embedded frames, audio and video, which
+    /// become links in the Markdown.
+    Full,
+}
+
+/// The tags, and the attributes per tag, kept when HTML is sanitized. Everything
+/// else is stripped; scripts and styles are removed with their contents.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Allowlist {
+    pub tags: HashSet<&'static str>,
+    pub tag_attributes: HashMap<&'static str, HashSet<&'static str>>,
+}
+
+impl Default for Allowlist {
+    /// The curated set suitable for a clean reading experience.
+    fn default() -> Self {
+        Self {
+            tags: HashSet::from([
+                "h1", "h2", "h3", "h4", "h5", "h6", "p", "br", "hr", "strong", "em", "b", "i",
+                "u", "del", "s", "strike", "blockquote", "ul", "ol", "li", "pre", "code", "img",
+                "figure", "figcaption", "table", "thead", "tbody", "tr", "th", "td", "a",
+                "details", "summary",
+            ]),
+            tag_attributes: HashMap::from([
+                ("a", HashSet::from(["href"])),
+                ("details", HashSet::from(["open"])),
+                ("img", HashSet::from(["src", "alt", "title"])),
+            ]),
+        }
+    }
+}
+
+impl Allowlist {
+    /// Builds the allowlist of a preset. [`SanitizePreset::Standard`] is
+    /// [`Allowlist::default`].
+    pub fn for_preset(preset: SanitizePreset) -> Self {
+        let mut allowlist = Self::default();
+        match preset {
+            SanitizePreset::Standard => {}
+            SanitizePreset::TextOnly => {
+                for tag in ["img", "figure", "figcaption"] {
+                    allowlist.tags.remove(tag);
+                    allowlist.tag_attributes.remove(tag);
+                }
+            }
+            SanitizePreset::Full => {
+                allowlist.tags.extend([
+                    "iframe", "video", "audio", "source", "sub", "sup", "mark", "kbd", "abbr",
+                    "dl", "dt", "dd",
+                ]);
+                allowlist.tag_attributes.extend([
+                    ("iframe", HashSet::from(["src", "title"])),
+                    ("video", HashSet::from(["src", "poster", "title"])),
+                    ("audio", HashSet::from(["src", "title"])),
+                    ("source", HashSet::from(["src", "type"])),
+                    ("abbr", HashSet::from(["title"])),
+                ]);
+            }
+        }
+        allowlist
+    }
+
+    /// Sanitizes HTML, keeping only the allowed tags and attributes.
+    pub fn clean(&self, html: &str) -> String {
+        Builder::new()
+            .tags(self.tags.clone())
+            .tag_attributes(self.tag_attributes.clone())
+            .link_rel(None) // Don't add rel="noopener noreferrer"
+            .clean(html)
+            .to_string()
+    }
+}
+
+/// Sanitizes HTML with the default [`Allowlist`], keeping only a curated set of
+/// tags and attributes suitable for a clean reading experience. Scripts, styles
+/// and other clutter are removed.
+pub fn sanitize_html(html: &str) -> String {
+    Allowlist::default().clean(html)
+}
+
+/// Converts an HTML fragment, such as the HTML variant of copied browser content,
+/// to Markdown. It is sanitized with [`sanitize_html`] first, so styles, scripts
+/// and clipboard metadata do not end up in the text.
+pub fn html_fragment_to_markdown(html: &str) -> String {
+    html2md::parse_html(&sanitize_html(html)).trim().to_string()
+}
+
+/// Cleans Markdown that may contain raw HTML by running it through the same
+/// pipeline as fetched articles: Markdown is rendered to HTML (with raw HTML kept),
+/// sanitized with [`sanitize_html`] and converted back to Markdown.
+///
+/// Clean content is stable under this round trip: sanitizing the result again
+/// returns it unchanged.
+pub fn sanitize_markdown(markdown_text: &str) -> String {
+    let mut options = markdown::Options::gfm();
+    options.compile.allow_dangerous_html = true;
+    // The GFM tag filter would escape `<script>` into visible text; ammonia drops it instead.
+    options.compile.gfm_tagfilter = false;
+    let html = markdown::to_html_with_options(markdown_text, &options)
+        .unwrap_or_else(|_| markdown::to_html(markdown_text));
+
+    html_fragment_to_markdown(&html)
+}
+
+/// Builds the HTTP client used for all outgoing requests, with a browser-like
+/// user agent (some sites refuse unknown clients) and a request timeout.
+pub fn http_client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0")
+        .timeout(std::time::Duration::from_secs(20))
+        .build()
+        .map_err(|e| format!("Ошибка создания HTTP клиента: {}", e))
+}
+
+/// Whether a request failed because nothing accepted the connection, as opposed to
+/// e.g. a timeout or a TLS error.
+fn is_connection_refused(error: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        if cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused)
+        {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// Whether input starts with a URL scheme followed by `://`, e.g. `https://`.
+/// A `://` later in the input, as in `example.com/?next=https://x`, does not count.
+fn has_scheme(input: &str) -> bool {
+    let Some((scheme, _)) = input.split_once("://") else {
+        return false;
+    };
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+}
+
+/// Resolves user input into the URLs to try, in order.
+///
+/// Input with an explicit scheme (see [`has_scheme`]) is parsed as is. Input without one (e.g.
+/// `example.com/article`) is tried as `https://` first and `http://` second, but
+/// only if it plausibly starts with a host name: it must contain a dot and no
+/// whitespace.
+fn candidate_urls(input: &str) -> Result<Vec<Url>, UrlProcessError> {
+    let input = input.trim();
+
+    if has_scheme(input) {
+        return Url::parse(input)
+            .map(|url| vec![url])
+            .map_err(|e| UrlProcessError::InvalidUrl(e.to_string()));
+    }
+
+    if input.is_empty() || !input.contains('.') || input.contains(char::is_whitespace) {
+        return Err(UrlProcessError::InvalidUrl(input.to_string()));
+    }
+
+    ["https", "http"]
+        .iter()
+        .map(|scheme| {
+            Url::parse(&format!("{}://{}", scheme, input))
+                .map_err(|e| UrlProcessError::InvalidUrl(e.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_fragment_to_markdown_converts_copied_browser_content() {
+        let html = "<html><body><style>p { color: red; }</style><!--StartFragment--><p>See <a href=\"https://example.com\">the docs</a> <b>now</b>.</p><!--EndFragment--></body></html>";
+
+        let markdown = html_fragment_to_markdown(html);
+
+        assert_eq!(markdown, "See [the docs](https://example.com) **now**.");
+    }
+
+    #[test]
+    fn decode_page_transcodes_windows_1251() {
+        // "<p>Привет</p>" in windows-1251.
+        let body: &[u8] = b"<p>\xCF\xF0\xE8\xE2\xE5\xF2</p>";
+        assert_eq!(
+            decode_page(body, Some("text/html; charset=windows-1251")),
+            "<p>Привет</p>"
+        );
+
+        let page = [
+            b"<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1251\">".as_slice(),
+            b"</head><body>".as_slice(),
+            body,
+            b"</body></html>".as_slice(),
+        ]
+        .concat();
+        assert!(decode_page(&page, Some("text/html")).contains("<p>Привет</p>"));
+        let page = [b"<meta charset='CP1251'>".as_slice(), body].concat();
+        assert!(decode_page(&page, None).ends_with("<p>Привет</p>"));
+
+        // The header wins over the page; undeclared pages are UTF-8.
+        let utf8 = "<meta charset=\"windows-1251\"><p>Привет</p>";
+        assert_eq!(decode_page(utf8.as_bytes(), Some("text/html; charset=UTF-8")), utf8);
+        assert_eq!(decode_page("<p>Привет</p>".as_bytes(), None), "<p>Привет</p>");
+    }
+
+    #[test]
+    fn sanitize_presets_keep_or_drop_images_and_embeds() {
+        let html = concat!(
+            "<p>Текст<script>x()</script></p>",
+            "<img src=\"https://example.com/a.png\" alt=\"Схема\">",
+            "<iframe src=\"https://www.youtube.com/embed/abc\" width=\"560\"></iframe>"
+        );
+
+        let standard = Allowlist::for_preset(SanitizePreset::Standard).clean(html);
+        assert_eq!(standard, sanitize_html(html));
+        assert_eq!(
+            standard,
+            "<p>Текст</p><img src=\"https://example.com/a.png\" alt=\"Схема\">"
+        );
+        assert_eq!(
+            Allowlist::for_preset(SanitizePreset::TextOnly).clean(html),
+            "<p>Текст</p>"
+        );
+        assert_eq!(
+            Allowlist::for_preset(SanitizePreset::Full).clean(html),
+            concat!(
+                "<p>Текст</p><img src=\"https://example.com/a.png\" alt=\"Схема\">",
+                "<iframe src=\"https://www.youtube.com/embed/abc\"></iframe>"
+            )
+        );
+    }
+
+    #[test]
+    fn sanitize_markdown_strips_scripts_and_is_idempotent() {
+        let messy = "# Title\n\nSome <b>bold</b> text.<script>alert('x')</script>\n\n<div style=\"color:red\" onclick=\"evil()\">Boxed</div>\n\n- one\n- two\n\n```\ncode  block\n```";
+
+        let cleaned = sanitize_markdown(messy);
+        assert!(!cleaned.contains("script"));
+        assert!(!cleaned.contains("alert"));
+        assert!(!cleaned.contains("onclick"));
+        assert!(cleaned.contains("Title"));
+        assert!(cleaned.contains("Boxed"));
+
+        assert_eq!(sanitize_markdown(&cleaned), cleaned);
+    }
+
+    #[test]
+    fn find_cover_image_url_prefers_open_graph_and_resolves_relative_urls() {
+        let page = Url::parse("https://example.com/blog/post").unwrap();
+        let html = r#"<html><head>
+            <meta name="twitter:image" content="https://cdn.example.com/twitter.png">
+            <META property='og:image' content="/images/cover.jpg?a=1&amp;b=2" />
+        </head><body></body></html>"#;
+
+        let cover = find_cover_image_url(html, &page).unwrap();
+        assert_eq!(cover.as_str(), "https://example.com/images/cover.jpg?a=1&b=2");
+    }
+
+    #[test]
+    fn find_cover_image_url_falls_back_to_twitter_card() {
+        let page = Url::parse("https://example.com/").unwrap();
+        let html = r#"<meta name="twitter:image:src" content="https://cdn.example.com/t.png">"#;
+
+        let cover = find_cover_image_url(html, &page).unwrap();
+        assert_eq!(cover.as_str(), "https://cdn.example.com/t.png");
+    }
+
+    #[test]
+    fn find_cover_image_url_returns_none_without_usable_image() {
+        let page = Url::parse("https://example.com/").unwrap();
+
+        assert!(find_cover_image_url("<meta name=description content=text>", &page).is_none());
+        assert!(find_cover_image_url(r#"<meta property="og:image" content="">"#, &page).is_none());
+        assert!(find_cover_image_url(
+            r#"<meta property="og:image" content="javascript:alert(1)">"#,
+            &page
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn candidate_urls_prepends_schemes_to_schemeless_input() {
+        let urls = candidate_urls("example.com/article?id=1").unwrap();
+        let urls: Vec<&str> = urls.iter().map(Url::as_str).collect();
+
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/article?id=1",
+                "http://example.com/article?id=1"
+            ]
+        );
+    }
+
+    #[test]
+    fn candidate_urls_ignores_schemes_after_the_host() {
+        let urls = candidate_urls("example.com/?next=https://x").unwrap();
+
+        assert_eq!(urls[0].as_str(), "https://example.com/?next=https://x");
+        assert_eq!(urls.len(), 2);
+    }
+
+    #[test]
+    fn candidate_urls_keeps_explicit_scheme() {
+        let urls = candidate_urls("  http://example.com/a ").unwrap();
+
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].as_str(), "http://example.com/a");
+    }
+
+    #[test]
+    fn candidate_urls_rejects_invalid_input() {
+        for input in ["", "just some words", "localhost", "http://"] {
+            assert!(matches!(
+                candidate_urls(input),
+                Err(UrlProcessError::InvalidUrl(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_page_does_not_downgrade_after_a_failed_tls_handshake() {
+        use axum::{routing::get, Router};
+        use tokio::net::TcpListener;
+
+        // A plain-http server: the https:// attempt connects but fails the handshake.
+        let app = Router::new().route("/", get(|| async { "plain" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let result = fetch_page(&addr.to_string()).await;
+
+        assert!(matches!(result, Err(UrlProcessError::Network(_))));
+    }
+
+    #[tokio::test]
+    async fn is_connection_refused_detects_closed_ports() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let error = http_client()
+            .unwrap()
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(is_connection_refused(&error));
+    }
+
+    #[test]
+    fn url_process_error_displays_user_facing_messages() {
+        assert_eq!(
+            UrlProcessError::HttpStatus(404).to_string(),
+            "Ошибка загрузки: сервер ответил со статусом 404 Not Found"
+        );
+        assert_eq!(
+            UrlProcessError::InvalidUrl("localhost".to_string()).to_string(),
+            "Неверный URL: localhost"
+        );
+        assert!(!UrlProcessError::EmptyExtraction.is_timeout());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::core::{count_words, process_markdown};
+use crate::state::AppState;
+use crate::url_processor::{process_url, Allowlist};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The shortest allowed interval between re-fetches of a watched page, to be
+/// polite to the servers being watched.
+pub const MIN_WATCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Fetches `url` and replaces the shared text with it. Images are not embedded,
+/// as downloading them again on every re-fetch would be wasteful.
+///
+/// # Returns
+/// The content hash of the fetched page, or an error string.
+pub async fn fetch_into_shared_text(state: &AppState, url: &str) -> Result<String, String> {
+    state.ensure_fetch_allowed()?;
+    let allowlist = Allowlist::for_preset(state.settings().sanitize_preset);
+    let markdown = process_url(url, false, &allowlist)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (_, hash) = process_markdown(&markdown);
+    store(state, markdown)?;
+    Ok(hash)
+}
+
+/// Resumes the watch saved in `state.watched_page`, if any, e.g. after a restart.
+/// The shared text is taken as the last fetch, so it is only replaced once the
+/// page has changed.
+pub fn resume(state: &Arc<AppState>) {
+    let Some(page) = state.watched_page.read().ok().and_then(|page| page.clone()) else {
+        return;
+    };
+    let Ok(last_hash) = state
+        .shared_text
+        .read()
+        .map(|text| process_markdown(&text).1)
+    else {
+        return;
+    };
+    let interval = Duration::from_secs(page.interval_secs).max(MIN_WATCH_INTERVAL);
+    let generation = state.watch_generation.fetch_add(1, Ordering::SeqCst) + 1;
+    log::info!(
+        "Resuming watch of {} every {} s.",
+        page.url,
+        interval.as_secs()
+    );
+    tauri::async_runtime::spawn(watch_url(
+        state.clone(),
+        page.url,
+        interval,
+        generation,
+        last_hash,
+    ));
+}
+
+/// Re-fetches `url` every `interval` and updates the shared text whenever the
+/// page content changes. Unchanged content is not written, so the reader is not
+/// repainted needlessly.
+///
+/// The task stops once `state.watch_generation` no longer equals `generation`,
+/// i.e. after another watch was started or watching was stopped.
+///
+/// # Arguments
+/// * `state` - The shared application state.
+/// * `url` - The page to watch.
+/// * `interval` - The time between fetches.
+/// * `generation` - The value of `watch_generation` this watch was started with.
+/// * `last_hash` - The content hash of the last fetch.
+pub async fn watch_url(
+    state: Arc<AppState>,
+    url: String,
+    interval: Duration,
+    generation: u64,
+    mut last_hash: String,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if !is_current(&state, generation) {
+            break;
+        }
+
+        // Re-fetches count against the fetch rate limit too; over it, this round is
+        // skipped (the limit logs a warning).
+        if state.ensure_fetch_allowed().is_err() {
+            continue;
+        }
+        let allowlist = Allowlist::for_preset(state.settings().sanitize_preset);
+        let markdown = match process_url(&url, false, &allowlist).await {
+            Ok(markdown) => markdown,
+            Err(e) if e.is_timeout() => {
+                log::warn!("Watched URL {} timed out, retrying next time.", url);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Failed to re-fetch watched URL {}: {}", url, e);
+                continue;
+            }
+        };
+
+        let (_, hash) = process_markdown(&markdown);
+        if hash == last_hash {
+            log::debug!("Watched URL {} is unchanged.", url);
+            continue;
+        }
+        // Watching may have been stopped while the page was loading.
+        if !is_current(&state, generation) {
+            break;
+        }
+        match store(&state, markdown) {
+            Ok(()) => {
+                log::info!("Watched URL {} changed, shared text updated.", url);
+                last_hash = hash;
+            }
+            Err(e) => log::error!("{}", e),
+        }
+    }
+
+    log::info!("Stopped watching {}", url);
+}
+
+fn is_current(state: &AppState, generation: u64) -> bool {
+    state.watch_generation.load(Ordering::SeqCst) == generation
+}
+
+fn store(state: &AppState, markdown: String) -> Result<(), String> {
+    state.ensure_text_fits(&markdown)?;
+    let words = count_words(&markdown);
+    let mut text = state
+        .shared_text
+        .write()
+        .map_err(|e| format!("Failed to acquire write lock for watched URL: {}", e))?;
+    state.replace_shared_text(&mut text, markdown);
+    state.stats.record_fetch();
+    state.stats.record_document(words);
+    Ok(())
+}
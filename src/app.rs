@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use unicode_segmentation::UnicodeSegmentation;
 use wasm_bindgen::{prelude::*, JsCast};
 use wasm_bindgen_futures::spawn_local;
 use web_sys::{Headers, HtmlInputElement, Request, RequestInit, Response};
@@ -9,6 +11,10 @@ extern "C" {
     #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"])]
     async fn invoke(cmd: &str, args: JsValue) -> JsValue;
 
+    /// Как `invoke`, но возвращает ошибку команды вместо исключения.
+    #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"], js_name = invoke, catch)]
+    async fn try_invoke(cmd: &str, args: JsValue) -> Result<JsValue, JsValue>;
+
     #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "event"])]
     async fn listen(event: &str, handler: &Closure<dyn FnMut(JsValue)>) -> JsValue;
 }
@@ -18,10 +24,46 @@ extern "C" {
 struct SetTextArgs {
     new_text: String,
 }
+/// Body of `POST /api/content`. `base_hash` makes the server reject the save with
+/// `409 Conflict` if the text was changed elsewhere since the editor loaded it.
 #[derive(Serialize)]
-struct FetchUrlPayload {
+struct SaveTextPayload {
+    new_text: String,
+    base_hash: Option<String>,
+}
+#[derive(Deserialize)]
+struct SourceResponse {
+    hash: String,
+}
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchUrlArgs {
+    url: String,
+    embed_images: bool,
+}
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WatchUrlArgs {
+    url: String,
+    interval_secs: u64,
+}
+
+/// The page being watched, as returned by `get_watched_page`.
+#[derive(Deserialize)]
+struct WatchedPage {
     url: String,
 }
+
+/// How often a watched page is re-fetched, in seconds.
+const WATCH_INTERVAL_SECS: u64 = 300;
+
+/// Arguments of `format_selection`; `kind` is one of `Quote`, `H1`, `H2`, `Code`, `Bullet`.
+#[derive(Serialize)]
+struct FormatSelectionArgs {
+    kind: &'static str,
+    text: String,
+}
+
 #[derive(Serialize)]
 struct SetSendOnCopyArgs {
     enabled: bool,
@@ -31,6 +73,127 @@ struct SetAddToEditorArgs {
     enabled: bool,
 }
 
+#[derive(Serialize)]
+struct SetEscapePlainTextArgs {
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+struct SetClearClipboardArgs {
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+struct SetContentModeArgs<'a> {
+    mode: &'a str,
+}
+
+#[derive(Serialize)]
+struct SetSanitizePresetArgs {
+    preset: String,
+}
+
+#[derive(Serialize)]
+struct PushToPeerArgs {
+    host: String,
+    port: u16,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+struct PeerInfo {
+    name: String,
+    addresses: Vec<String>,
+    port: u16,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+struct DiffSegment {
+    kind: String,
+    text: String,
+    lines: usize,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+struct LineDiff {
+    added: usize,
+    removed: usize,
+    segments: Vec<DiffSegment>,
+}
+
+/// Unchanged segments longer than this are collapsed in the diff preview.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+#[derive(Deserialize, Clone, PartialEq)]
+struct ReadingStats {
+    documents: u64,
+    words: u64,
+    fetches: u64,
+    uptime_seconds: u64,
+    content_age_seconds: u64,
+}
+
+/// A saved document, as listed by `list_documents`.
+#[derive(Deserialize, Clone, PartialEq)]
+struct DocumentInfo {
+    id: u64,
+    name: String,
+    active: bool,
+}
+
+#[derive(Serialize)]
+struct DocumentIdArgs {
+    id: u64,
+}
+
+/// Metrics of the shared text reported by `get_content_stats`.
+#[derive(Deserialize, Clone, PartialEq)]
+struct ContentStats {
+    words: u64,
+    reading_minutes: u64,
+}
+
+/// The page the reader reported being on, from `get_reading_progress` or the
+/// `reading-progress` event.
+#[derive(Deserialize, Clone, PartialEq)]
+struct ReadingProgress {
+    page: u32,
+    total: u32,
+}
+
+/// Loads the reader's last reported page into `reading_progress`.
+async fn refresh_reading_progress(reading_progress: &UseStateHandle<Option<ReadingProgress>>) {
+    let progress = invoke("get_reading_progress", JsValue::NULL).await;
+    let progress = serde_wasm_bindgen::from_value::<Option<ReadingProgress>>(progress);
+    reading_progress.set(progress.ok().flatten());
+}
+
+/// Loads the current content metrics into `content_stats`.
+async fn refresh_content_stats(content_stats: &UseStateHandle<Option<ContentStats>>) {
+    let stats = invoke("get_content_stats", JsValue::NULL).await;
+    content_stats.set(serde_wasm_bindgen::from_value::<ContentStats>(stats).ok());
+}
+
+/// The part of the `get_all_settings` report the editor uses: current values of
+/// the settings it has toggles for.
+#[derive(Deserialize)]
+struct SettingsReport {
+    values: SettingsValues,
+}
+
+#[derive(Deserialize)]
+struct SettingsValues {
+    send_on_copy: bool,
+    add_to_editor_on_copy: bool,
+    escape_plain_text_on_copy: bool,
+    clear_clipboard_after_capture: bool,
+    /// Сколько символов захваченного текста попадает в лог; 0 — только длина.
+    log_preview_chars: usize,
+    /// `"markdown"` или `"html"`.
+    content_mode: String,
+    /// `"standard"`, `"text_only"` или `"full"`.
+    sanitize_preset: String,
+}
+
 #[derive(Deserialize)]
 struct ApiResponse {
     message: String,
@@ -41,11 +204,73 @@ struct TauriEvent<T> {
     payload: T,
 }
 
+/// Обрезает текст для лога до `max_chars` графем, как `truncate_for_log` в бэкенде,
+/// чтобы не разрезать эмодзи и составные символы. При `max_chars == 0` (по
+/// умолчанию, настройка `log_preview_chars`) текст не выводится, только его длина.
+fn log_preview(text: &str, max_chars: usize) -> String {
+    let total = text.chars().count();
+    if max_chars == 0 {
+        return format!("({} символов)", total);
+    }
+    let single_line = text.replace(['\r', '\n'], " ");
+    let mut graphemes = single_line.graphemes(true);
+    let preview: String = graphemes.by_ref().take(max_chars).collect();
+    if graphemes.next().is_none() {
+        preview
+    } else {
+        format!("{}… ({} символов)", preview, total)
+    }
+}
+
+/// Builds the URL of `path` on the app's web server, which may not listen on the
+/// default port (`KI_PORT`, or a taken port).
+async fn server_url(path: &str) -> String {
+    let port = invoke("get_server_port", JsValue::NULL)
+        .await
+        .as_f64()
+        .map_or(5001, |port| port as u16);
+    format!("http://localhost:{}{}", port, path)
+}
+
+/// Remembers which version of the shared text the editor now shows; it is sent as
+/// `base_hash` on save.
+async fn sync_base_hash(base_hash: &RefCell<Option<String>>) {
+    *base_hash.borrow_mut() = invoke("get_text_hash", JsValue::NULL).await.as_string();
+}
+
+/// Runs a library command and, if it succeeded, shows the updated document list
+/// and loads the active document into the editor.
+///
+/// # Returns
+/// `false` if the command failed.
+async fn change_document(
+    cmd: &str,
+    args: JsValue,
+    documents: &UseStateHandle<Vec<DocumentInfo>>,
+    editor_content: &UseStateHandle<String>,
+    editor_ref: &RefCell<String>,
+    base_hash: &RefCell<Option<String>>,
+) -> bool {
+    let result = invoke(cmd, args).await;
+    let Ok(list) = serde_wasm_bindgen::from_value::<Vec<DocumentInfo>>(result) else {
+        return false;
+    };
+    documents.set(list);
+    let text = invoke("get_text", JsValue::NULL).await.as_string().unwrap_or_default();
+    *editor_ref.borrow_mut() = text.clone();
+    editor_content.set(text);
+    sync_base_hash(base_hash).await;
+    true
+}
+
 #[function_component(App)]
 pub fn app() -> Html {
     // --- Состояние редактора ---
     let editor_content = use_state(String::new);
     let editor_ref = use_mut_ref(|| String::new()); // всегда актуальное значение
+    let base_hash = use_mut_ref(|| None::<String>); // версия текста, загруженная в редактор
+    let log_preview_chars = use_mut_ref(|| 0usize); // настройка log_preview_chars
+    let editor_node = use_node_ref();
 
     // синхронизация editor_ref при каждом изменении состояния
     {
@@ -66,26 +291,140 @@ pub fn app() -> Html {
     let url_input = use_state(String::new);
     let fetch_status = use_state(String::new);
     let is_fetching = use_state(|| false);
-    let server_info = use_state(|| "Загрузка информации о сервере...".to_string());
+    let embed_images = use_state(|| false);
+    // `None`, пока адреса загружаются.
+    let server_urls = use_state(|| None::<Vec<String>>);
+    let server_status = use_state(String::new);
     let send_on_copy = use_state(|| false);
     let add_to_editor_on_copy = use_state(|| false);
+    let escape_plain_text = use_state(|| true);
+    let clear_clipboard = use_state(|| false);
+    let html_mode = use_state(|| false);
+    let sanitize_preset = use_state(|| "standard".to_string());
+    let safe_mode = use_state(|| false);
+    let peers = use_state(Vec::<PeerInfo>::new);
+    let peer_status = use_state(String::new);
+    let is_discovering = use_state(|| false);
+    let scratch_active = use_state(|| false);
+    let stats = use_state(|| None::<ReadingStats>);
+    let diff = use_state(|| None::<LineDiff>);
+    let is_watching = use_state(|| false);
+    let content_stats = use_state(|| None::<ContentStats>);
+    let reading_progress = use_state(|| None::<ReadingProgress>);
+    let documents = use_state(Vec::<DocumentInfo>::new);
 
     // --- загрузка данных при старте ---
     {
         let editor_content = editor_content.clone();
         let editor_ref = editor_ref.clone();
-        let server_info = server_info.clone();
+        let server_urls = server_urls.clone();
+        let safe_mode = safe_mode.clone();
+        let base_hash = base_hash.clone();
+        let send_on_copy = send_on_copy.clone();
+        let add_to_editor_on_copy = add_to_editor_on_copy.clone();
+        let escape_plain_text = escape_plain_text.clone();
+        let clear_clipboard = clear_clipboard.clone();
+        let html_mode = html_mode.clone();
+        let sanitize_preset = sanitize_preset.clone();
+        let documents = documents.clone();
+        let scratch_active = scratch_active.clone();
+        let log_preview_chars = log_preview_chars.clone();
+        let is_watching = is_watching.clone();
+        let url_input = url_input.clone();
         use_effect_with((), move |_| {
             spawn_local(async move {
                 let text = invoke("get_text", JsValue::NULL).await.as_string().unwrap_or_default();
                 *editor_ref.borrow_mut() = text.clone();
                 editor_content.set(text);
+                sync_base_hash(&base_hash).await;
 
-                let info = invoke("get_server_info", JsValue::NULL)
+                let list = invoke("list_documents", JsValue::NULL).await;
+                if let Ok(list) = serde_wasm_bindgen::from_value::<Vec<DocumentInfo>>(list) {
+                    documents.set(list);
+                }
+                // Черновик мог остаться открытым при прошлом закрытии приложения.
+                scratch_active.set(invoke("is_scratch_active", JsValue::NULL).await.as_bool().unwrap_or(false));
+                // Слежение за страницей продолжается после перезапуска.
+                let watched = invoke("get_watched_page", JsValue::NULL).await;
+                if let Ok(Some(page)) = serde_wasm_bindgen::from_value::<Option<WatchedPage>>(watched) {
+                    url_input.set(page.url);
+                    is_watching.set(true);
+                }
+
+                let urls = invoke("get_server_info", JsValue::NULL).await;
+                server_urls.set(Some(serde_wasm_bindgen::from_value(urls).unwrap_or_default()));
+
+                let is_safe_mode = invoke("get_safe_mode", JsValue::NULL)
                     .await
-                    .as_string()
-                    .unwrap_or_else(|| "Ошибка получения информации о сервере".to_string());
-                server_info.set(info);
+                    .as_bool()
+                    .unwrap_or(false);
+                safe_mode.set(is_safe_mode);
+
+                // Переключатели показывают настройки, действующие в приложении.
+                let report = invoke("get_all_settings", JsValue::NULL).await;
+                if let Ok(report) = serde_wasm_bindgen::from_value::<SettingsReport>(report) {
+                    send_on_copy.set(report.values.send_on_copy);
+                    add_to_editor_on_copy.set(report.values.add_to_editor_on_copy);
+                    escape_plain_text.set(report.values.escape_plain_text_on_copy);
+                    clear_clipboard.set(report.values.clear_clipboard_after_capture);
+                    *log_preview_chars.borrow_mut() = report.values.log_preview_chars;
+                    html_mode.set(report.values.content_mode == "html");
+                    sanitize_preset.set(report.values.sanitize_preset);
+                }
+            });
+            || {}
+        });
+    }
+
+    // --- метрики текста: при старте и после каждого изменения ---
+    {
+        let content_stats = content_stats.clone();
+        use_effect_with((), move |_| {
+            spawn_local(async move {
+                refresh_content_stats(&content_stats).await;
+
+                let callback = Closure::wrap(Box::new(move |_event: JsValue| {
+                    let content_stats = content_stats.clone();
+                    spawn_local(async move {
+                        refresh_content_stats(&content_stats).await;
+                    });
+                }) as Box<dyn FnMut(JsValue)>);
+
+                listen("content-changed", &callback).await;
+                callback.forget();
+            });
+            || {}
+        });
+    }
+
+    // --- страница, открытая на читалке ---
+    {
+        let reading_progress = reading_progress.clone();
+        use_effect_with((), move |_| {
+            spawn_local(async move {
+                refresh_reading_progress(&reading_progress).await;
+
+                let on_progress = {
+                    let reading_progress = reading_progress.clone();
+                    Closure::wrap(Box::new(move |event: JsValue| {
+                        if let Ok(evt) = serde_wasm_bindgen::from_value::<TauriEvent<ReadingProgress>>(event) {
+                            reading_progress.set(Some(evt.payload));
+                        }
+                    }) as Box<dyn FnMut(JsValue)>)
+                };
+                listen("reading-progress", &on_progress).await;
+                on_progress.forget();
+
+                // После изменения текста прежняя страница больше не верна, пока читалка
+                // не сообщит новую.
+                let on_content_changed = Closure::wrap(Box::new(move |_event: JsValue| {
+                    let reading_progress = reading_progress.clone();
+                    spawn_local(async move {
+                        refresh_reading_progress(&reading_progress).await;
+                    });
+                }) as Box<dyn FnMut(JsValue)>);
+                listen("content-changed", &on_content_changed).await;
+                on_content_changed.forget();
             });
             || {}
         });
@@ -95,6 +434,8 @@ pub fn app() -> Html {
     {
         let editor_ref = editor_ref.clone();
         let editor_content = editor_content.clone();
+        let base_hash = base_hash.clone();
+        let log_preview_chars = log_preview_chars.clone();
 
         use_effect_with((), move |_| {
             spawn_local(async move {
@@ -102,10 +443,15 @@ pub fn app() -> Html {
                     if let Ok(evt) = serde_wasm_bindgen::from_value::<TauriEvent<String>>(event) {
                         let text_to_append = evt.payload;
                         let current = editor_ref.borrow().clone();
+                        let max_chars = *log_preview_chars.borrow();
 
                         web_sys::console::log_1(
-                            &format!("Clipboard event: current='{}', append='{}'",
-                                     current, text_to_append).into(),
+                            &format!(
+                                "Clipboard event: current='{}', append='{}'",
+                                log_preview(&current, max_chars),
+                                log_preview(&text_to_append, max_chars)
+                            )
+                            .into(),
                         );
 
                         let new_content = if current.trim().is_empty() {
@@ -117,11 +463,13 @@ pub fn app() -> Html {
                         *editor_ref.borrow_mut() = new_content.clone();
                         editor_content.set(new_content.clone());
 
+                        let base_hash = base_hash.clone();
                         spawn_local(async move {
                             let args = SetTextArgs { new_text: new_content };
                             let js_payload =
                                 serde_wasm_bindgen::to_value(&args).expect("serde convert");
                             invoke("set_text", js_payload).await;
+                            sync_base_hash(&base_hash).await;
                         });
                     }
                 }) as Box<dyn FnMut(JsValue)>);
@@ -146,11 +494,63 @@ pub fn app() -> Html {
         })
     };
 
+    // --- форматирование выделенного текста ---
+    let format_selection = {
+        let editor_node = editor_node.clone();
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let save_status = save_status.clone();
+        move |kind: &'static str| {
+            let editor_node = editor_node.clone();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let save_status = save_status.clone();
+            Callback::from(move |e: MouseEvent| {
+                e.prevent_default();
+                let Some(textarea) = editor_node.cast::<web_sys::HtmlTextAreaElement>() else {
+                    return;
+                };
+                let (Ok(Some(start)), Ok(Some(end))) =
+                    (textarea.selection_start(), textarea.selection_end())
+                else {
+                    return;
+                };
+                if start == end {
+                    return;
+                }
+                // Границы выделения заданы в UTF-16, поэтому подстроку вырезает JS.
+                let text = String::from(js_sys::JsString::from(textarea.value()).substring(start, end));
+                let editor_content = editor_content.clone();
+                let editor_ref = editor_ref.clone();
+                let save_status = save_status.clone();
+                spawn_local(async move {
+                    let args = FormatSelectionArgs { kind, text };
+                    let Some(formatted) =
+                        invoke("format_selection", serde_wasm_bindgen::to_value(&args).unwrap())
+                            .await
+                            .as_string()
+                    else {
+                        return;
+                    };
+                    if textarea.set_range_text_with_start_and_end(&formatted, start, end).is_err() {
+                        return;
+                    }
+                    let value = textarea.value();
+                    editor_content.set(value.clone());
+                    *editor_ref.borrow_mut() = value;
+                    save_status.set("".to_string());
+                    let _ = textarea.focus();
+                });
+            })
+        }
+    };
+
     // --- сохранение текста ---
     let on_save = {
         let editor_content = editor_content.clone();
         let save_status = save_status.clone();
         let is_saving = is_saving.clone();
+        let base_hash = base_hash.clone();
         Callback::from(move |e: MouseEvent| {
             e.prevent_default();
             if *is_saving {
@@ -162,9 +562,13 @@ pub fn app() -> Html {
             let content_to_save = (*editor_content).clone();
             let save_status_clone = save_status.clone();
             let is_saving_clone = is_saving.clone();
+            let base_hash = base_hash.clone();
 
             spawn_local(async move {
-                let payload = SetTextArgs { new_text: content_to_save };
+                let payload = SaveTextPayload {
+                    new_text: content_to_save,
+                    base_hash: base_hash.borrow().clone(),
+                };
                 let js_payload = serde_wasm_bindgen::to_value(&payload).unwrap();
                 let headers = Headers::new().unwrap();
                 headers.set("Content-Type", "application/json").unwrap();
@@ -174,7 +578,7 @@ pub fn app() -> Html {
                 opts.set_body(&body_str);
                 opts.set_headers(&headers);
                 let request =
-                    Request::new_with_str_and_init("http://localhost:5001/api/content", &opts)
+                    Request::new_with_str_and_init(&server_url("/api/content").await, &opts)
                         .unwrap();
                 let window = web_sys::window().unwrap();
                 let resp_value =
@@ -185,6 +589,41 @@ pub fn app() -> Html {
                         let resp: Response = resp.dyn_into().unwrap();
                         if resp.ok() {
                             save_status_clone.set("Сохранено!".to_string());
+                            *base_hash.borrow_mut() =
+                                resp.headers().get("x-content-hash").ok().flatten();
+                        } else if resp.status() == 409 {
+                            // Текст изменили в другом месте (например, из буфера обмена).
+                            // Правки в редакторе сохраняются, а повторное нажатие перезапишет текст.
+                            if let Ok(json_promise) = resp.json() {
+                                if let Ok(json_val) =
+                                    wasm_bindgen_futures::JsFuture::from(json_promise).await
+                                {
+                                    if let Ok(current) =
+                                        serde_wasm_bindgen::from_value::<SourceResponse>(json_val)
+                                    {
+                                        *base_hash.borrow_mut() = Some(current.hash);
+                                    }
+                                }
+                            }
+                            save_status_clone.set(
+                                "Текст на читалке изменился после загрузки. Нажмите «Сохранить» ещё раз, чтобы перезаписать его."
+                                    .to_string(),
+                            );
+                        } else if resp.status() == 413 {
+                            // Текст длиннее допустимого; сервер объясняет, насколько.
+                            let mut message = "Текст слишком длинный.".to_string();
+                            if let Ok(json_promise) = resp.json() {
+                                if let Ok(json_val) =
+                                    wasm_bindgen_futures::JsFuture::from(json_promise).await
+                                {
+                                    if let Ok(data) =
+                                        serde_wasm_bindgen::from_value::<ApiResponse>(json_val)
+                                    {
+                                        message = data.message;
+                                    }
+                                }
+                            }
+                            save_status_clone.set(message);
                         } else {
                             let error_msg = format!(
                                 "Ошибка сохранения: {} {}",
@@ -203,6 +642,438 @@ pub fn app() -> Html {
         })
     };
 
+    // --- очистка HTML в текущем тексте ---
+    let on_sanitize = {
+        let editor_content = editor_content.clone();
+        let save_status = save_status.clone();
+        let base_hash = base_hash.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let editor_content = editor_content.clone();
+            let save_status = save_status.clone();
+            let base_hash = base_hash.clone();
+            spawn_local(async move {
+                match invoke("sanitize_current", JsValue::NULL).await.as_string() {
+                    Some(cleaned) => {
+                        editor_content.set(cleaned);
+                        sync_base_hash(&base_hash).await;
+                        save_status.set("HTML очищен.".to_string());
+                    }
+                    None => save_status.set("Не удалось очистить текст.".to_string()),
+                }
+            });
+        })
+    };
+
+    // --- очистка текста в редакторе и на читалке ---
+    let on_clear = {
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let save_status = save_status.clone();
+        let base_hash = base_hash.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let save_status = save_status.clone();
+            let base_hash = base_hash.clone();
+            spawn_local(async move {
+                invoke("clear_text", JsValue::NULL).await;
+                editor_ref.borrow_mut().clear();
+                editor_content.set(String::new());
+                sync_base_hash(&base_hash).await;
+                save_status.set("Текст очищен.".to_string());
+            });
+        })
+    };
+
+    // --- экспорт в файл Markdown ---
+    let on_export_markdown = {
+        let save_status = save_status.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let save_status = save_status.clone();
+            spawn_local(async move {
+                match try_invoke("export_markdown", JsValue::NULL).await {
+                    Ok(path) => {
+                        // Пустой ответ означает, что диалог закрыли без выбора файла.
+                        if let Some(path) = path.as_string() {
+                            save_status.set(format!("Сохранено в {}", path));
+                        }
+                    }
+                    Err(error) => save_status.set(error.as_string().unwrap_or_default()),
+                }
+            });
+        })
+    };
+
+    // --- открытие локального файла ---
+    let on_import_file = {
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let save_status = save_status.clone();
+        let base_hash = base_hash.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let save_status = save_status.clone();
+            let base_hash = base_hash.clone();
+            spawn_local(async move {
+                match try_invoke("import_file", JsValue::NULL).await {
+                    Ok(text) => {
+                        // Пустой ответ означает, что диалог закрыли без выбора файла.
+                        if let Some(text) = text.as_string() {
+                            *editor_ref.borrow_mut() = text.clone();
+                            editor_content.set(text);
+                            sync_base_hash(&base_hash).await;
+                            save_status.set("Файл загружен.".to_string());
+                        }
+                    }
+                    Err(error) => save_status.set(error.as_string().unwrap_or_default()),
+                }
+            });
+        })
+    };
+
+    // --- отмена и возврат изменений текста ---
+    let history_step = |command: &'static str| {
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let save_status = save_status.clone();
+        let base_hash = base_hash.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let save_status = save_status.clone();
+            let base_hash = base_hash.clone();
+            spawn_local(async move {
+                match try_invoke(command, JsValue::NULL).await {
+                    Ok(text) => {
+                        let text = text.as_string().unwrap_or_default();
+                        *editor_ref.borrow_mut() = text.clone();
+                        editor_content.set(text);
+                        sync_base_hash(&base_hash).await;
+                        save_status.set(String::new());
+                    }
+                    Err(error) => save_status.set(error.as_string().unwrap_or_default()),
+                }
+            });
+        })
+    };
+    let on_undo = history_step("undo");
+    let on_redo = history_step("redo");
+
+    // --- перезапуск веб-сервера ---
+    let on_restart_server = {
+        let server_urls = server_urls.clone();
+        let server_status = server_status.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let server_urls = server_urls.clone();
+            let server_status = server_status.clone();
+            server_urls.set(None);
+            server_status.set(String::new());
+            spawn_local(async move {
+                let restarted = invoke("restart_server", JsValue::NULL)
+                    .await
+                    .as_bool()
+                    .unwrap_or(false);
+                if !restarted {
+                    server_status.set("Не удалось запустить сервер: все порты заняты.".to_string());
+                }
+                // Порт мог измениться, поэтому адреса запрашиваются заново.
+                let urls = invoke("get_server_info", JsValue::NULL).await;
+                server_urls.set(Some(serde_wasm_bindgen::from_value(urls).unwrap_or_default()));
+            });
+        })
+    };
+
+    // --- переключение между статьей и черновиком ---
+    let on_toggle_scratch = {
+        let editor_content = editor_content.clone();
+        let scratch_active = scratch_active.clone();
+        let base_hash = base_hash.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let editor_content = editor_content.clone();
+            let scratch_active = scratch_active.clone();
+            let base_hash = base_hash.clone();
+            spawn_local(async move {
+                let active = invoke("toggle_scratch", JsValue::NULL).await.as_bool();
+                if let Some(active) = active {
+                    scratch_active.set(active);
+                    let text = invoke("get_text", JsValue::NULL).await.as_string().unwrap_or_default();
+                    editor_content.set(text);
+                    sync_base_hash(&base_hash).await;
+                }
+            });
+        })
+    };
+
+    // --- библиотека документов ---
+    let on_switch_document = {
+        let documents = documents.clone();
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let base_hash = base_hash.clone();
+        let save_status = save_status.clone();
+        Callback::from(move |e: Event| {
+            let value = e.target_unchecked_into::<web_sys::HtmlSelectElement>().value();
+            let Ok(id) = value.parse::<u64>() else {
+                return;
+            };
+            let documents = documents.clone();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let base_hash = base_hash.clone();
+            let save_status = save_status.clone();
+            spawn_local(async move {
+                let args = serde_wasm_bindgen::to_value(&DocumentIdArgs { id }).unwrap();
+                if !change_document(
+                    "switch_document",
+                    args,
+                    &documents,
+                    &editor_content,
+                    &editor_ref,
+                    &base_hash,
+                )
+                .await
+                {
+                    save_status.set("Не удалось переключить документ.".to_string());
+                }
+            });
+        })
+    };
+
+    let on_new_document = {
+        let documents = documents.clone();
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let base_hash = base_hash.clone();
+        let save_status = save_status.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let documents = documents.clone();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let base_hash = base_hash.clone();
+            let save_status = save_status.clone();
+            spawn_local(async move {
+                if !change_document(
+                    "new_document",
+                    JsValue::NULL,
+                    &documents,
+                    &editor_content,
+                    &editor_ref,
+                    &base_hash,
+                )
+                .await
+                {
+                    save_status.set("Не удалось создать документ.".to_string());
+                }
+            });
+        })
+    };
+
+    let on_delete_document = {
+        let documents = documents.clone();
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let base_hash = base_hash.clone();
+        let save_status = save_status.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let Some(active) = documents.iter().find(|document| document.active) else {
+                return;
+            };
+            let confirmed = web_sys::window()
+                .and_then(|window| {
+                    window
+                        .confirm_with_message(&format!("Удалить документ «{}»?", active.name))
+                        .ok()
+                })
+                .unwrap_or(false);
+            if !confirmed {
+                return;
+            }
+            let id = active.id;
+            let documents = documents.clone();
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let base_hash = base_hash.clone();
+            let save_status = save_status.clone();
+            spawn_local(async move {
+                let args = serde_wasm_bindgen::to_value(&DocumentIdArgs { id }).unwrap();
+                if !change_document(
+                    "delete_document",
+                    args,
+                    &documents,
+                    &editor_content,
+                    &editor_ref,
+                    &base_hash,
+                )
+                .await
+                {
+                    save_status.set("Не удалось удалить документ.".to_string());
+                }
+            });
+        })
+    };
+
+    // --- сравнение с текстом на читалке ---
+    let on_toggle_diff = {
+        let editor_content = editor_content.clone();
+        let diff = diff.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            if diff.is_some() {
+                diff.set(None);
+                return;
+            }
+            let diff = diff.clone();
+            let args = SetTextArgs { new_text: (*editor_content).clone() };
+            spawn_local(async move {
+                let result =
+                    invoke("diff_against_live", serde_wasm_bindgen::to_value(&args).unwrap()).await;
+                if let Ok(line_diff) = serde_wasm_bindgen::from_value::<LineDiff>(result) {
+                    diff.set(Some(line_diff));
+                }
+            });
+        })
+    };
+
+    // --- статистика чтения ---
+    let on_toggle_stats = {
+        let stats = stats.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            if stats.is_some() {
+                stats.set(None);
+                return;
+            }
+            let stats = stats.clone();
+            spawn_local(async move {
+                let result = invoke("get_reading_stats", JsValue::NULL).await;
+                if let Ok(report) = serde_wasm_bindgen::from_value::<ReadingStats>(result) {
+                    stats.set(Some(report));
+                }
+            });
+        })
+    };
+
+    // --- другие экземпляры в сети ---
+    let on_discover_peers = {
+        let peers = peers.clone();
+        let peer_status = peer_status.clone();
+        let is_discovering = is_discovering.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            if *is_discovering {
+                return;
+            }
+            is_discovering.set(true);
+            peer_status.set("Поиск устройств...".to_string());
+            let peers = peers.clone();
+            let peer_status = peer_status.clone();
+            let is_discovering = is_discovering.clone();
+            spawn_local(async move {
+                let result = invoke("discover_peers", JsValue::NULL).await;
+                match serde_wasm_bindgen::from_value::<Vec<PeerInfo>>(result) {
+                    Ok(found) => {
+                        peer_status.set(if found.is_empty() {
+                            "Другие устройства не найдены.".to_string()
+                        } else {
+                            String::new()
+                        });
+                        peers.set(found);
+                    }
+                    Err(_) => peer_status.set("Не удалось выполнить поиск.".to_string()),
+                }
+                is_discovering.set(false);
+            });
+        })
+    };
+
+    let push_to_peer = {
+        let peer_status = peer_status.clone();
+        move |peer: PeerInfo| {
+            let peer_status = peer_status.clone();
+            Callback::from(move |e: MouseEvent| {
+                e.prevent_default();
+                let Some(host) = peer.addresses.first().cloned() else {
+                    return;
+                };
+                let name = peer.name.clone();
+                let port = peer.port;
+                let peer_status = peer_status.clone();
+                peer_status.set(format!("Отправка на {}...", name));
+                spawn_local(async move {
+                    let args = PushToPeerArgs { host, port };
+                    let result =
+                        invoke("push_to_peer", serde_wasm_bindgen::to_value(&args).unwrap()).await;
+                    peer_status.set(if result.is_string() {
+                        format!("Отправлено на {}.", name)
+                    } else {
+                        format!("Не удалось отправить на {}.", name)
+                    });
+                });
+            })
+        }
+    };
+
+    // --- слежение за страницей ---
+    let on_toggle_watch = {
+        let url_input = url_input.clone();
+        let fetch_status = fetch_status.clone();
+        let is_watching = is_watching.clone();
+        let editor_content = editor_content.clone();
+        let editor_ref = editor_ref.clone();
+        let base_hash = base_hash.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let fetch_status = fetch_status.clone();
+            let is_watching = is_watching.clone();
+            if *is_watching {
+                spawn_local(async move {
+                    invoke("stop_watching", JsValue::NULL).await;
+                    is_watching.set(false);
+                    fetch_status.set("Слежение остановлено.".to_string());
+                });
+                return;
+            }
+            if (*url_input).trim().is_empty() {
+                return;
+            }
+            let args = WatchUrlArgs {
+                url: (*url_input).clone(),
+                interval_secs: WATCH_INTERVAL_SECS,
+            };
+            let editor_content = editor_content.clone();
+            let editor_ref = editor_ref.clone();
+            let base_hash = base_hash.clone();
+            fetch_status.set("Открываю страницу...".to_string());
+            spawn_local(async move {
+                let result = invoke("watch_url", serde_wasm_bindgen::to_value(&args).unwrap()).await;
+                match result.as_f64() {
+                    Some(interval) => {
+                        is_watching.set(true);
+                        fetch_status.set(format!(
+                            "Страница обновляется каждые {} мин.",
+                            (interval as u64) / 60
+                        ));
+                        let text = invoke("get_text", JsValue::NULL).await.as_string().unwrap_or_default();
+                        *editor_ref.borrow_mut() = text.clone();
+                        editor_content.set(text);
+                        sync_base_hash(&base_hash).await;
+                    }
+                    None => fetch_status.set("Не удалось открыть страницу.".to_string()),
+                }
+            });
+        })
+    };
+
     // --- ввод URL ---
     let on_url_input = {
         let url_input = url_input.clone();
@@ -215,12 +1086,19 @@ pub fn app() -> Html {
     };
 
     // --- загрузка URL ---
+    let on_embed_images_toggle = {
+        let embed_images = embed_images.clone();
+        Callback::from(move |_e: Event| embed_images.set(!*embed_images))
+    };
+
     let on_fetch_url = {
+        let embed_images = embed_images.clone();
         let url_input = url_input.clone();
         let fetch_status = fetch_status.clone();
         let is_fetching = is_fetching.clone();
         let editor_content = editor_content.clone();
         let editor_ref = editor_ref.clone();
+        let base_hash = base_hash.clone();
 
         Callback::from(move |e: MouseEvent| {
             e.prevent_default();
@@ -231,64 +1109,33 @@ pub fn app() -> Html {
             fetch_status.set("Открываю страницу...".to_string());
 
             let url_to_fetch = (*url_input).clone();
+            let embed_images = *embed_images;
             let fetch_status_clone = fetch_status.clone();
             let is_fetching_clone = is_fetching.clone();
             let editor_content_clone = editor_content.clone();
             let editor_ref_clone = editor_ref.clone();
+            let base_hash = base_hash.clone();
 
             spawn_local(async move {
-                let payload = FetchUrlPayload { url: url_to_fetch };
-                let js_payload = serde_wasm_bindgen::to_value(&payload).unwrap();
-                let headers = Headers::new().unwrap();
-                headers.set("Content-Type", "application/json").unwrap();
-                let mut opts = RequestInit::new();
-                opts.set_method("POST");
-                let body_str = js_sys::JSON::stringify(&js_payload).unwrap();
-                opts.set_body(&body_str);
-                opts.set_headers(&headers);
-                let request =
-                    Request::new_with_str_and_init("http://localhost:5001/api/url", &opts).unwrap();
-                let window = web_sys::window().unwrap();
-                let resp_value =
-                    wasm_bindgen_futures::JsFuture::from(window.fetch_with_request(&request)).await;
-
-                match resp_value {
-                    Ok(resp_val) => {
-                        let resp: Response = resp_val.dyn_into().unwrap();
-                        if let Ok(json_promise) = resp.json() {
-                            if let Ok(json_val) =
-                                wasm_bindgen_futures::JsFuture::from(json_promise).await
-                            {
-                                if let Ok(data) =
-                                    serde_wasm_bindgen::from_value::<ApiResponse>(json_val)
-                                {
-                                    if resp.ok() {
-                                        fetch_status_clone.set("Отправлено!".to_string());
-                                        let text = invoke("get_text", JsValue::NULL)
-                                            .await
-                                            .as_string()
-                                            .unwrap_or_default();
-                                        *editor_ref_clone.borrow_mut() = text.clone();
-                                        editor_content_clone.set(text);
-                                    } else {
-                                        fetch_status_clone
-                                            .set(format!("Ошибка: {}", data.message));
-                                    }
-                                } else {
-                                    fetch_status_clone
-                                        .set("Ошибка: неверный формат ответа.".to_string());
-                                }
-                            } else {
-                                fetch_status_clone
-                                    .set("Ошибка: не удалось прочитать ответ.".to_string());
-                            }
-                        } else {
-                            fetch_status_clone
-                                .set("Ошибка: ответ сервера - не JSON.".to_string());
-                        }
+                let args = FetchUrlArgs {
+                    url: url_to_fetch,
+                    embed_images,
+                };
+                let result =
+                    try_invoke("fetch_url", serde_wasm_bindgen::to_value(&args).unwrap()).await;
+                match result {
+                    Ok(markdown) => {
+                        fetch_status_clone.set("Отправлено!".to_string());
+                        let text = markdown.as_string().unwrap_or_default();
+                        *editor_ref_clone.borrow_mut() = text.clone();
+                        editor_content_clone.set(text);
+                        sync_base_hash(&base_hash).await;
                     }
-                    Err(_) => {
-                        fetch_status_clone.set("Ошибка сети. Сервер доступен?".to_string());
+                    Err(error) => {
+                        let message = error
+                            .as_string()
+                            .unwrap_or_else(|| "не удалось загрузить страницу.".to_string());
+                        fetch_status_clone.set(format!("Ошибка: {}", message));
                     }
                 }
                 is_fetching_clone.set(false);
@@ -350,11 +1197,107 @@ pub fn app() -> Html {
         })
     };
 
+    let on_escape_toggle = {
+        let escape_plain_text = escape_plain_text.clone();
+        Callback::from(move |_e: Event| {
+            let new_value = !*escape_plain_text;
+            escape_plain_text.set(new_value);
+
+            spawn_local(async move {
+                let args = SetEscapePlainTextArgs { enabled: new_value };
+                invoke(
+                    "set_escape_plain_text_on_copy",
+                    serde_wasm_bindgen::to_value(&args).unwrap(),
+                )
+                .await;
+            });
+        })
+    };
+
+    let on_clear_clipboard_toggle = {
+        let clear_clipboard = clear_clipboard.clone();
+        Callback::from(move |_e: Event| {
+            let new_value = !*clear_clipboard;
+            clear_clipboard.set(new_value);
+
+            spawn_local(async move {
+                let args = SetClearClipboardArgs { enabled: new_value };
+                invoke(
+                    "set_clear_clipboard_after_capture",
+                    serde_wasm_bindgen::to_value(&args).unwrap(),
+                )
+                .await;
+            });
+        })
+    };
+
+    let on_html_mode_toggle = {
+        let html_mode = html_mode.clone();
+        Callback::from(move |_e: Event| {
+            let new_value = !*html_mode;
+            html_mode.set(new_value);
+
+            spawn_local(async move {
+                let args = SetContentModeArgs {
+                    mode: if new_value { "html" } else { "markdown" },
+                };
+                invoke("set_content_mode", serde_wasm_bindgen::to_value(&args).unwrap()).await;
+            });
+        })
+    };
+
+    let on_sanitize_preset_change = {
+        let sanitize_preset = sanitize_preset.clone();
+        Callback::from(move |e: Event| {
+            let preset = e.target_unchecked_into::<web_sys::HtmlSelectElement>().value();
+            sanitize_preset.set(preset.clone());
+
+            spawn_local(async move {
+                let args = SetSanitizePresetArgs { preset };
+                invoke("set_sanitize_preset", serde_wasm_bindgen::to_value(&args).unwrap()).await;
+            });
+        })
+    };
+
     // --- рендер ---
     html! {
         <main class="container">
             <div class="server-info">
-                <p>{ &*server_info }</p>
+                {
+                    match &*server_urls {
+                        None => html! { <p>{ "Загрузка информации о сервере..." }</p> },
+                        Some(urls) if urls.is_empty() => html! {
+                            <p>{ "Не удалось определить IP-адрес. Проверьте подключение к сети." }</p>
+                        },
+                        Some(urls) => html! {
+                            <>
+                                <p>{ "Откройте на читалке:" }</p>
+                                <ul class="server-urls">
+                                    { for urls.iter().map(|url| html! { <li>{ url }</li> }) }
+                                </ul>
+                            </>
+                        },
+                    }
+                }
+                <button class="restart-server" onclick={on_restart_server}>{ "Перезапустить сервер" }</button>
+                if !server_status.is_empty() {
+                    <p class="server-status">{ &*server_status }</p>
+                }
+                if let Some(progress) = &*reading_progress {
+                    <p class="reading-progress">
+                        { format!("Читалка на странице {} из {}", progress.page, progress.total) }
+                    </p>
+                }
+                if let Some(stats) = &*content_stats {
+                    <p class="content-stats">
+                        { format!("{} слов · ≈ {} мин чтения", stats.words, stats.reading_minutes) }
+                    </p>
+                }
+                if *safe_mode {
+                    <p class="safe-mode-notice">
+                        {"Безопасный режим: буфер обмена не отслеживается, загрузка из сети отключена."}
+                    </p>
+                }
             </div>
 
             <div class="url-loader">
@@ -364,16 +1307,62 @@ pub fn app() -> Html {
                     placeholder="Вставьте URL статьи для отправки на читалку"
                     value={(*url_input).clone()}
                     oninput={on_url_input}
-                    disabled={*is_fetching}
+                    disabled={*is_fetching || *safe_mode}
                 />
-                <button onclick={on_fetch_url} disabled={*is_fetching}>
+                <button onclick={on_fetch_url} disabled={*is_fetching || *safe_mode}>
                     { if *is_fetching { "Загрузка..." } else { "Отправить" } }
                 </button>
+                <button onclick={on_toggle_watch} disabled={*is_fetching || *safe_mode}>
+                    { if *is_watching { "Остановить слежение" } else { "Следить" } }
+                </button>
+                <label
+                    class="embed-images-toggle"
+                    title="Изображения статьи загружаются в текст, и читалке не нужен доступ к их серверам"
+                >
+                    <input
+                        type="checkbox"
+                        checked={*embed_images}
+                        onchange={on_embed_images_toggle}
+                        disabled={*is_fetching || *safe_mode}
+                    />
+                    {"Встроить изображения"}
+                </label>
                 <span class="fetch-status">{&*fetch_status}</span>
             </div>
 
+            <div class="document-picker">
+                <select onchange={on_switch_document} disabled={*scratch_active}>
+                    { for documents.iter().map(|document| html! {
+                        <option
+                            key={document.id}
+                            value={document.id.to_string()}
+                            selected={document.active}
+                        >
+                            { &document.name }
+                        </option>
+                    }) }
+                </select>
+                <button onclick={on_new_document} disabled={*scratch_active}>
+                    { "Новый документ" }
+                </button>
+                <button
+                    onclick={on_delete_document}
+                    disabled={*scratch_active || documents.len() < 2}
+                >
+                    { "Удалить документ" }
+                </button>
+            </div>
+
             <div class="editor-wrapper">
+                <div class="format-toolbar">
+                    <button onclick={format_selection("Quote")}>{"Цитата"}</button>
+                    <button onclick={format_selection("H1")}>{"H1"}</button>
+                    <button onclick={format_selection("H2")}>{"H2"}</button>
+                    <button onclick={format_selection("Code")}>{"Код"}</button>
+                    <button onclick={format_selection("Bullet")}>{"Список"}</button>
+                </div>
                 <textarea
+                    ref={editor_node}
                     class="editor-textarea"
                     value={(*editor_content).clone()}
                     oninput={on_input}
@@ -385,6 +1374,30 @@ pub fn app() -> Html {
                 <button onclick={on_save} disabled={*is_saving}>
                     { if *is_saving { "Сохранение..." } else { "Сохранить и обновить читалку" } }
                 </button>
+                <button onclick={on_sanitize} disabled={*is_saving}>
+                    { "Очистить HTML" }
+                </button>
+                <button onclick={on_clear} disabled={*is_saving}>
+                    { "Очистить" }
+                </button>
+                <button onclick={on_undo} disabled={*is_saving} title="Вернуть текст, который был до последнего изменения">
+                    { "Отменить" }
+                </button>
+                <button onclick={on_redo} disabled={*is_saving}>
+                    { "Повторить" }
+                </button>
+                <button onclick={on_import_file} disabled={*is_saving} title="Загрузить файл .md, .txt или .html">
+                    { "Открыть файл" }
+                </button>
+                <button onclick={on_export_markdown} title="Сохранить текст с читалки в файл .md">
+                    { "Экспорт" }
+                </button>
+                <button onclick={on_toggle_scratch} disabled={*is_saving}>
+                    { if *scratch_active { "Вернуться к статье" } else { "Черновик" } }
+                </button>
+                <button onclick={on_toggle_diff}>
+                    { if diff.is_some() { "Скрыть изменения" } else { "Сравнить с читалкой" } }
+                </button>
                 <span class="save-status">{&*save_status}</span>
 
                 <div class="toggle-controls">
@@ -394,6 +1407,7 @@ pub fn app() -> Html {
                             id="sendOnCopy"
                             checked={*send_on_copy}
                             onchange={on_send_toggle}
+                            disabled={*safe_mode}
                         />
                         <label for="sendOnCopy">{"Отправлять текст при копировании"}</label>
                     </div>
@@ -403,10 +1417,126 @@ pub fn app() -> Html {
                             id="addOnCopy"
                             checked={*add_to_editor_on_copy}
                             onchange={on_add_toggle}
+                            disabled={*safe_mode}
                         />
                         <label for="addOnCopy">{"Добавлять в редактор при копировании"}</label>
                     </div>
+                    <div class="auto-send-toggle">
+                        <input
+                            type="checkbox"
+                            id="escapePlainText"
+                            checked={*escape_plain_text}
+                            onchange={on_escape_toggle}
+                        />
+                        <label for="escapePlainText">{"Не форматировать обычный текст как Markdown"}</label>
+                    </div>
+                    <div class="auto-send-toggle">
+                        <input
+                            type="checkbox"
+                            id="clearClipboard"
+                            checked={*clear_clipboard}
+                            onchange={on_clear_clipboard_toggle}
+                            disabled={*safe_mode}
+                        />
+                        <label
+                            for="clearClipboard"
+                            title="Скопированный текст удаляется из буфера обмена сразу после отправки или добавления в редактор"
+                        >
+                            {"Очищать буфер обмена после захвата"}
+                        </label>
+                    </div>
+                    <div class="auto-send-toggle">
+                        <input
+                            type="checkbox"
+                            id="htmlMode"
+                            checked={*html_mode}
+                            onchange={on_html_mode_toggle}
+                        />
+                        <label
+                            for="htmlMode"
+                            title="Текст показывается на читалке как HTML (после очистки от скриптов и стилей), а не разбирается как Markdown"
+                        >
+                            {"Текст в формате HTML"}
+                        </label>
+                    </div>
+                    <div class="auto-send-toggle">
+                        <label for="sanitizePreset">{"Со страниц сохранять:"}</label>
+                        <select id="sanitizePreset" onchange={on_sanitize_preset_change}>
+                            {
+                                for [
+                                    ("standard", "текст и изображения"),
+                                    ("text_only", "только текст"),
+                                    ("full", "все, включая видео и встраивания"),
+                                ]
+                                .into_iter()
+                                .map(|(value, label)| html! {
+                                    <option value={value} selected={*sanitize_preset == value}>
+                                        { label }
+                                    </option>
+                                })
+                            }
+                        </select>
+                    </div>
+                </div>
+            </div>
+
+            if let Some(line_diff) = &*diff {
+                <div class="diff-preview">
+                    <p class="diff-summary">
+                        { if line_diff.added == 0 && line_diff.removed == 0 {
+                            "Текст совпадает с показанным на читалке.".to_string()
+                        } else {
+                            format!("Добавлено строк: {}, удалено: {}", line_diff.added, line_diff.removed)
+                        } }
+                    </p>
+                    <pre>
+                        { for line_diff.segments.iter().map(|segment| match segment.kind.as_str() {
+                            "added" => html! { <span class="diff-added">{ &segment.text }</span> },
+                            "removed" => html! { <span class="diff-removed">{ &segment.text }</span> },
+                            _ if segment.lines > DIFF_CONTEXT_LINES => html! {
+                                <span class="diff-skipped">{ format!("… без изменений: {} строк\n", segment.lines) }</span>
+                            },
+                            _ => html! { <span>{ &segment.text }</span> },
+                        }) }
+                    </pre>
                 </div>
+            }
+
+            <div class="peers">
+                <button onclick={on_discover_peers} disabled={*is_discovering || *safe_mode}>
+                    { if *is_discovering { "Поиск..." } else { "Найти другие устройства" } }
+                </button>
+                <span class="peer-status">{&*peer_status}</span>
+                <ul class="peer-list">
+                    { for peers.iter().map(|peer| html! {
+                        <li key={peer.name.clone()}>
+                            <span>{ format!("{} ({}:{})", peer.name, peer.addresses.first().cloned().unwrap_or_default(), peer.port) }</span>
+                            <button onclick={push_to_peer(peer.clone())} disabled={*safe_mode}>
+                                { "Отправить текст" }
+                            </button>
+                        </li>
+                    }) }
+                </ul>
+            </div>
+
+            <div class="stats">
+                <button onclick={on_toggle_stats}>
+                    { if stats.is_some() { "Скрыть статистику" } else { "Статистика" } }
+                </button>
+                if let Some(report) = &*stats {
+                    <dl class="stats-list">
+                        <dt>{"Документов отправлено"}</dt>
+                        <dd>{ report.documents }</dd>
+                        <dt>{"Слов"}</dt>
+                        <dd>{ report.words }</dd>
+                        <dt>{"Страниц загружено по URL"}</dt>
+                        <dd>{ report.fetches }</dd>
+                        <dt>{"Время в приложении"}</dt>
+                        <dd>{ format!("{} ч {} мин", report.uptime_seconds / 3600, report.uptime_seconds / 60 % 60) }</dd>
+                        <dt>{"Текст обновлен"}</dt>
+                        <dd>{ format!("{} ч {} мин назад", report.content_age_seconds / 3600, report.content_age_seconds / 60 % 60) }</dd>
+                    </dl>
+                }
             </div>
         </main>
     }